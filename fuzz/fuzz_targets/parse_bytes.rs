@@ -0,0 +1,13 @@
+//! Run with `cargo +nightly fuzz run parse_bytes` from the repository root.
+//! Any input must produce `Ok` or `Err`; a panic or stack overflow is a bug.
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    if let Err(e) = extreme_xml_parse::parse_bytes(data) {
+        if let Ok(text) = std::str::from_utf8(data) {
+            let _ = e.diagnostic(text).to_string();
+        }
+    }
+});
@@ -0,0 +1,393 @@
+use std::error;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::error::XmlError;
+use crate::resolver::{has_scheme, EntityResolver, ResolvedEntity};
+use crate::{parse_str_with, Elem, ParseOptions};
+
+/// Maps public and system identifiers to local files, as described by an
+/// OASIS catalog: either an XML Catalog (XML Catalogs 1.1) or a plain text
+/// catalog (TR9401).
+///
+/// A catalog is an `EntityResolver` that only loads the files it maps
+/// identifiers to, so DTDs can be resolved offline from a vendored catalog.
+/// Identifiers it has no entry for are skipped.
+#[derive(Debug, Default)]
+pub struct Catalog {
+    /// entries in document order, with groups flattened
+    entries: Vec<CatalogEntry>,
+    /// catalogs consulted, in order, when no entry matches
+    next: Vec<Catalog>,
+}
+
+#[derive(Debug)]
+enum CatalogEntry {
+    Public { public_id: String, uri: String, prefer_public: bool },
+    System { system_id: String, uri: String },
+    RewriteSystem { prefix: String, rewrite: String },
+    SystemSuffix { suffix: String, uri: String },
+}
+
+/// An error loading a catalog file
+#[derive(Debug)]
+pub enum CatalogError {
+    /// the file could not be read
+    Io(PathBuf, io::Error),
+    /// an XML catalog is not well-formed
+    Xml(PathBuf, Box<XmlError>),
+    /// a text catalog has an unknown keyword or a missing argument
+    Syntax(PathBuf, String),
+}
+
+impl fmt::Display for CatalogError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self {
+            CatalogError::Io(path, e) => write!(f, "cannot read catalog {}: {}", path.display(), e),
+            CatalogError::Xml(path, e) => write!(f, "in catalog {}: {}", path.display(), e),
+            CatalogError::Syntax(path, msg) => write!(f, "in catalog {}: {}", path.display(), msg),
+        }
+    }
+}
+
+impl error::Error for CatalogError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match &self {
+            CatalogError::Io(_, e) => Some(e),
+            CatalogError::Xml(_, e) => Some(e.as_ref()),
+            CatalogError::Syntax(..) => None,
+        }
+    }
+}
+
+/// The namespace of the elements of an XML catalog
+const CATALOG_NAMESPACE: &str = "urn:oasis:names:tc:entity:xmlns:xml:catalog";
+
+/// Catalogs chained with `nextCatalog` or `CATALOG` may nest at most this deep
+const MAX_CATALOG_DEPTH: usize = 16;
+
+impl Catalog {
+    /// Load the catalog at `path`, along with any catalogs it chains to.
+    /// Files starting with `<` are read as XML catalogs, anything else as
+    /// TR9401 text catalogs. Relative URIs in the catalog are resolved
+    /// against its location.
+    pub fn load(path: impl AsRef<Path>) -> Result<Catalog, CatalogError> {
+        Catalog::load_nested(path.as_ref(), 0)
+    }
+
+    fn load_nested(path: &Path, depth: usize) -> Result<Catalog, CatalogError> {
+        let text = fs::read_to_string(path).map_err(|e| CatalogError::Io(path.to_path_buf(), e))?;
+        let base = path.to_string_lossy();
+        let mut builder = Builder {
+            path,
+            depth,
+            catalog: Catalog::default(),
+        };
+        if text.trim_start().starts_with('<') {
+            // entries are recognized by their namespace
+            let options = ParseOptions { namespaces: true, ..ParseOptions::default() };
+            let doc = parse_str_with(&text, &options).map_err(|e| CatalogError::Xml(path.to_path_buf(), Box::new(e)))?;
+            builder.read_xml(doc.root(), &base, true)?;
+        } else {
+            builder.read_text(&text, &base)?;
+        }
+        Ok(builder.catalog)
+    }
+
+    /// The file the catalog maps an external identifier to, if any.
+    ///
+    /// System identifiers are looked up first, by `system` entries, then by
+    /// the longest matching `rewriteSystem` prefix, then by the longest
+    /// matching `systemSuffix` suffix. Failing that the public identifier is
+    /// looked up, unless the entry was declared with `prefer="system"` and a
+    /// system identifier is given.
+    pub fn lookup(&self, public_id: Option<&str>, system_id: Option<&str>) -> Option<PathBuf> {
+        let public_id = public_id.map(normalize_public_id);
+        self.lookup_uri(public_id.as_deref(), system_id).map(|uri| to_path(&uri))
+    }
+
+    fn lookup_uri(&self, public_id: Option<&str>, system_id: Option<&str>) -> Option<String> {
+        if let Some(system_id) = system_id {
+            if let Some(uri) = self.entries.iter().find_map(|entry| match entry {
+                CatalogEntry::System { system_id: id, uri } if id == system_id => Some(uri.clone()),
+                _ => None,
+            }) {
+                return Some(uri);
+            }
+            // any rewriteSystem match is used before systemSuffix is tried
+            let rewrite = self.longest_match(|entry| match entry {
+                CatalogEntry::RewriteSystem { prefix, rewrite } if system_id.starts_with(prefix.as_str()) => {
+                    Some((prefix.len(), format!("{}{}", rewrite, &system_id[prefix.len()..])))
+                }
+                _ => None,
+            });
+            let suffix = || {
+                self.longest_match(|entry| match entry {
+                    CatalogEntry::SystemSuffix { suffix, uri } if system_id.ends_with(suffix.as_str()) => {
+                        Some((suffix.len(), uri.clone()))
+                    }
+                    _ => None,
+                })
+            };
+            if let Some(uri) = rewrite.or_else(suffix) {
+                return Some(uri);
+            }
+        }
+        if let Some(public_id) = public_id
+            && let Some(uri) = self.entries.iter().find_map(|entry| match entry {
+                CatalogEntry::Public {
+                    public_id: id,
+                    uri,
+                    prefer_public,
+                } if id == public_id && (*prefer_public || system_id.is_none()) => Some(uri.clone()),
+                _ => None,
+            })
+        {
+            return Some(uri);
+        }
+        self.next.iter().find_map(|next| next.lookup_uri(public_id, system_id))
+    }
+
+    /// The URI given by the first of the entries that match the longest
+    /// string, where `matches` returns the length matched and the URI
+    fn longest_match(&self, matches: impl Fn(&CatalogEntry) -> Option<(usize, String)>) -> Option<String> {
+        self.entries
+            .iter()
+            .filter_map(matches)
+            .fold(None, |best: Option<(usize, String)>, candidate| match best {
+                Some(best) if best.0 >= candidate.0 => Some(best),
+                _ => Some(candidate),
+            })
+            .map(|(_, uri)| uri)
+    }
+}
+
+impl EntityResolver for Catalog {
+    fn resolve(
+        &self,
+        public_id: Option<&str>,
+        system_id: &str,
+        _base_uri: Option<&str>,
+    ) -> io::Result<Option<ResolvedEntity>> {
+        match self.lookup(public_id, Some(system_id)) {
+            Some(path) => {
+                let bytes = fs::read(&path)?;
+                Ok(Some(ResolvedEntity {
+                    uri: path.to_string_lossy().into_owned(),
+                    bytes,
+                }))
+            }
+            None => Ok(None),
+        }
+    }
+}
+
+/// Collects the entries of a catalog file
+struct Builder<'a> {
+    path: &'a Path,
+    depth: usize,
+    catalog: Catalog,
+}
+
+impl Builder<'_> {
+    fn read_xml(&mut self, elem: &Elem, base: &str, prefer_public: bool) -> Result<(), CatalogError> {
+        // elements from other namespaces are ignored along with their content
+        if elem.namespace_uri() != Some(CATALOG_NAMESPACE) {
+            return Ok(());
+        }
+        let base = match elem.attribute("xml:base") {
+            Some(attrib) => resolve_uri(base, &attrib.value()),
+            None => String::from(base),
+        };
+        let prefer_public = match elem.attribute("prefer").map(|attrib| attrib.value()) {
+            Some(prefer) => prefer.trim() == "public",
+            None => prefer_public,
+        };
+        let value = |name: &str| elem.attribute(name).map(|attrib| attrib.value());
+        let entry = match elem.local_name() {
+            "catalog" | "group" => {
+                for child in elem.child_elems() {
+                    self.read_xml(child, &base, prefer_public)?;
+                }
+                None
+            }
+            "public" => value("publicId").zip(value("uri")).map(|(id, uri)| CatalogEntry::Public {
+                public_id: normalize_public_id(&id),
+                uri: resolve_uri(&base, &uri),
+                prefer_public,
+            }),
+            "system" => value("systemId").zip(value("uri")).map(|(id, uri)| CatalogEntry::System {
+                system_id: id,
+                uri: resolve_uri(&base, &uri),
+            }),
+            "rewriteSystem" => value("systemIdStartString")
+                .zip(value("rewritePrefix"))
+                .map(|(prefix, rewrite)| CatalogEntry::RewriteSystem {
+                    prefix,
+                    rewrite: resolve_uri(&base, &rewrite),
+                }),
+            "systemSuffix" => value("systemIdSuffix").zip(value("uri")).map(|(suffix, uri)| CatalogEntry::SystemSuffix {
+                suffix,
+                uri: resolve_uri(&base, &uri),
+            }),
+            "nextCatalog" => {
+                if let Some(next) = value("catalog") {
+                    self.chain(&resolve_uri(&base, &next))?;
+                }
+                None
+            }
+            // entries for URIs and delegation are not supported
+            _ => None,
+        };
+        self.push(entry);
+        Ok(())
+    }
+
+    fn read_text(&mut self, text: &str, base: &str) -> Result<(), CatalogError> {
+        let mut base = String::from(base);
+        let mut prefer_public = true;
+        let mut tokens = Tokens { text };
+        while let Some(keyword) = tokens.next() {
+            let mut arg = || {
+                tokens
+                    .next()
+                    .ok_or_else(|| CatalogError::Syntax(self.path.to_path_buf(), format!("missing argument to {}", keyword)))
+            };
+            let entry = match keyword.to_ascii_uppercase().as_str() {
+                "PUBLIC" => {
+                    let public_id = normalize_public_id(arg()?);
+                    let uri = resolve_uri(&base, arg()?);
+                    Some(CatalogEntry::Public {
+                        public_id,
+                        uri,
+                        prefer_public,
+                    })
+                }
+                "SYSTEM" => {
+                    let system_id = String::from(arg()?);
+                    let uri = resolve_uri(&base, arg()?);
+                    Some(CatalogEntry::System { system_id, uri })
+                }
+                "BASE" => {
+                    base = resolve_uri(&base, arg()?);
+                    None
+                }
+                "OVERRIDE" => {
+                    prefer_public = arg()?.eq_ignore_ascii_case("YES");
+                    None
+                }
+                "CATALOG" => {
+                    let next = resolve_uri(&base, arg()?);
+                    self.chain(&next)?;
+                    None
+                }
+                "SGMLDECL" | "DOCUMENT" => {
+                    arg()?;
+                    None
+                }
+                "DOCTYPE" | "ENTITY" | "LINKTYPE" | "NOTATION" | "DTDDECL" | "DELEGATE" => {
+                    arg()?;
+                    arg()?;
+                    None
+                }
+                _ => {
+                    return Err(CatalogError::Syntax(self.path.to_path_buf(), format!("unknown keyword {}", keyword)));
+                }
+            };
+            self.push(entry);
+        }
+        Ok(())
+    }
+
+    fn push(&mut self, entry: Option<CatalogEntry>) {
+        // only local files can be resolved offline
+        let local = |uri: &str| !has_scheme(uri) || uri.starts_with("file:");
+        let keep = match &entry {
+            Some(CatalogEntry::Public { uri, .. })
+            | Some(CatalogEntry::System { uri, .. })
+            | Some(CatalogEntry::SystemSuffix { uri, .. }) => local(uri),
+            Some(CatalogEntry::RewriteSystem { rewrite, .. }) => local(rewrite),
+            None => false,
+        };
+        if keep && let Some(entry) = entry {
+            self.catalog.entries.push(entry);
+        }
+    }
+
+    /// Load the catalog at `uri` to be consulted after this one
+    fn chain(&mut self, uri: &str) -> Result<(), CatalogError> {
+        if self.depth >= MAX_CATALOG_DEPTH {
+            return Err(CatalogError::Syntax(
+                self.path.to_path_buf(),
+                format!("catalogs chain more than {} deep", MAX_CATALOG_DEPTH),
+            ));
+        }
+        let next = Catalog::load_nested(&to_path(uri), self.depth + 1)?;
+        self.catalog.next.push(next);
+        Ok(())
+    }
+}
+
+/// Splits a TR9401 catalog into keywords and literals, skipping white space
+/// and `-- comments --`
+struct Tokens<'a> {
+    text: &'a str,
+}
+
+impl<'a> Iterator for Tokens<'a> {
+    type Item = &'a str;
+
+    fn next(&mut self) -> Option<&'a str> {
+        loop {
+            self.text = self.text.trim_start();
+            match self.text.strip_prefix("--") {
+                Some(comment) => {
+                    self.text = match comment.find("--") {
+                        Some(end) => &comment[end + 2..],
+                        None => "",
+                    }
+                }
+                None => break,
+            };
+        }
+        let quote = self.text.chars().next()?;
+        if quote == '"' || quote == '\'' {
+            let literal = &self.text[1..];
+            let end = literal.find(quote).unwrap_or(literal.len());
+            self.text = literal.get(end + 1..).unwrap_or("");
+            Some(&literal[..end])
+        } else {
+            let end = self.text.find(char::is_whitespace).unwrap_or(self.text.len());
+            let (token, rest) = self.text.split_at(end);
+            self.text = rest;
+            Some(token)
+        }
+    }
+}
+
+/// Collapse runs of white space in a public identifier to single spaces
+fn normalize_public_id(id: &str) -> String {
+    id.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+/// Resolve `uri` relative to `base`, both URIs or file paths
+fn resolve_uri(base: &str, uri: &str) -> String {
+    if uri.starts_with('/') || has_scheme(uri) {
+        return String::from(uri);
+    }
+    match base.rfind('/') {
+        Some(slash) => format!("{}{}", &base[..=slash], uri),
+        None => String::from(uri),
+    }
+}
+
+/// The file path named by a local URI
+fn to_path(uri: &str) -> PathBuf {
+    let path = match uri.strip_prefix("file://") {
+        Some(rest) => rest,
+        None => uri.strip_prefix("file:").unwrap_or(uri),
+    };
+    PathBuf::from(path)
+}
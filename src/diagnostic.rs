@@ -0,0 +1,146 @@
+use std::fmt;
+
+use crate::error::{NamespaceError, ValidityError, XmlError, XmlErrorKind};
+use crate::position::Position;
+
+/// Renders a parse error against the document it came from, showing the
+/// offending source line with a caret under the column where parsing failed.
+///
+/// ```text
+/// error: tags `inner` and `outer` do not match
+///  --> line 3, column 1
+///   |
+/// 3 | </outer>
+///   | ^
+///   = while parsing ETag
+///   = note: opening tag `inner` is at line 2, column 3
+///   |
+/// 2 |   <inner>
+///   |   ^
+/// ```
+///
+/// When the error was caused by a failure deeper in the grammar, the deepest
+/// cause is the one rendered, since it points at the actual mistake.
+pub struct Diagnostic<'a> {
+    error: &'a XmlError,
+    source: &'a str,
+}
+
+impl<'a> Diagnostic<'a> {
+    pub fn new(error: &'a XmlError, source: &'a str) -> Diagnostic<'a> {
+        Diagnostic { error, source }
+    }
+
+    /// The error the diagnostic describes, the deepest cause of the
+    /// error it was created from
+    pub fn primary(&self) -> &'a XmlError {
+        let mut error = self.error;
+        while let Some(cause) = error.underlying() {
+            error = cause;
+        }
+        error
+    }
+
+    /// A suggestion for fixing common mistakes, if one applies
+    pub fn hint(&self) -> Option<String> {
+        let error = self.primary();
+        let context = error.context();
+        match error.kind() {
+            XmlErrorKind::MismatchedTags(start, _end) => match error.related_idx() {
+                Some(idx) => Some(format!(
+                    "opening tag `{}` is at {}",
+                    start,
+                    Position::locate(self.source, idx)
+                )),
+                None => Some(format!("expected closing tag `</{}>`", start)),
+            },
+            XmlErrorKind::BadChar('<') if context.starts_with("AttValue") => {
+                Some(String::from("write `<` as `&lt;` inside attribute values"))
+            }
+            XmlErrorKind::BadChar(_) if context.starts_with("EntityRef") || context.starts_with("Name in EntityRef") => Some(String::from(
+                "a literal `&` must be written as `&amp;`",
+            )),
+            XmlErrorKind::IllegalSubstr if context.starts_with("Comment") => {
+                Some(String::from("`--` is not allowed inside comments"))
+            }
+            XmlErrorKind::IllegalSubstr if context.starts_with("CharData") => Some(String::from(
+                "write `]]>` as `]]&gt;` in text, or wrap the text in a CDATA section",
+            )),
+            XmlErrorKind::Invalid(ValidityError::DuplicateId(_)) => error
+                .related_idx()
+                .map(|idx| format!("the ID is first used at {}", Position::locate(self.source, idx))),
+            XmlErrorKind::Invalid(ValidityError::DuplicateElementDecl(_)) => error
+                .related_idx()
+                .map(|idx| format!("the element type is first declared at {}", Position::locate(self.source, idx))),
+            XmlErrorKind::DuplicateAttribute(_) | XmlErrorKind::Namespace(NamespaceError::DuplicateAttribute(_)) => error
+                .related_idx()
+                .map(|idx| format!("the attribute is first given at {}", Position::locate(self.source, idx))),
+            XmlErrorKind::Namespace(NamespaceError::UndeclaredPrefix(prefix)) => Some(format!(
+                "declare the prefix with an `xmlns:{}` attribute on this element or an ancestor",
+                prefix
+            )),
+            XmlErrorKind::UndeclaredEntity(_) => Some(String::from(
+                "declare the entity in the DTD, or write a literal `&` as `&amp;`",
+            )),
+            XmlErrorKind::UnparsedEntityRef(_) => Some(String::from(
+                "unparsed entities may only be named by attributes of type ENTITY or ENTITIES",
+            )),
+            XmlErrorKind::ReservedNameXml => Some(String::from(
+                "an XML declaration is only allowed at the very start of the document",
+            )),
+            XmlErrorKind::TextEnd => Some(String::from(
+                "the document ended before this construct was closed",
+            )),
+            _ => None,
+        }
+    }
+
+    /// Write the source line containing `offset` with a caret under it
+    fn write_snippet(&self, f: &mut fmt::Formatter<'_>, offset: usize) -> fmt::Result {
+        let pos = Position::locate(self.source, offset);
+        let line_start = self.source[..pos.offset]
+            .rfind(['\n', '\r'])
+            .map_or(0, |idx| idx + 1);
+        let line_end = self.source[pos.offset..]
+            .find(['\n', '\r'])
+            .map_or(self.source.len(), |idx| pos.offset + idx);
+        let line = &self.source[line_start..line_end];
+        let gutter = " ".repeat(pos.line.to_string().len());
+        // keep tabs so the caret lines up however the terminal renders them
+        let pad: String = self.source[line_start..pos.offset]
+            .chars()
+            .map(|c| if c == '\t' { '\t' } else { ' ' })
+            .collect();
+        writeln!(f, "{} |", gutter)?;
+        writeln!(f, "{} | {}", pos.line, line)?;
+        writeln!(f, "{} | {}^", gutter, pad)
+    }
+}
+
+impl fmt::Display for Diagnostic<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let error = self.primary();
+        let pos = Position::locate(self.source, error.doc_idx());
+        let gutter = " ".repeat(pos.line.to_string().len());
+        writeln!(f, "error: {}", error.kind())?;
+        writeln!(f, "{}--> {}", gutter, pos)?;
+        self.write_snippet(f, error.doc_idx())?;
+        if !error.context().is_empty() {
+            writeln!(f, "{} = while parsing {}", gutter, error.context())?;
+        }
+        if let Some(hint) = self.hint() {
+            writeln!(f, "{} = note: {}", gutter, hint)?;
+        }
+        if let (XmlErrorKind::MismatchedTags(..), Some(idx)) = (error.kind(), error.related_idx()) {
+            self.write_snippet(f, idx)?;
+        }
+        Ok(())
+    }
+}
+
+impl XmlError {
+    /// Render this error against the source text it was encountered in
+    pub fn diagnostic<'a>(&'a self, source: &'a str) -> Diagnostic<'a> {
+        Diagnostic::new(self, source)
+    }
+}
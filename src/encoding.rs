@@ -0,0 +1,316 @@
+use std::borrow::Cow;
+use std::fmt;
+
+use crate::error::{XmlError, XmlErrorKind};
+
+/// How far into a document its XML declaration is looked for when choosing
+/// the encoding to read it in
+const DECL_LIMIT: usize = 1024;
+
+/// A character encoding that documents can be read in
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TextEncoding {
+    Utf8,
+    Utf16Le,
+    Utf16Be,
+    /// ISO-8859-1, with every byte standing for the code point of its value
+    Latin1,
+    Windows1252,
+    /// US-ASCII, where bytes above 0x7F are errors
+    Ascii,
+}
+
+impl TextEncoding {
+    /// Look up an encoding by a name it may be declared with, ignoring case.
+    /// `UTF-16` is not found, since it leaves the byte order to the byte
+    /// order mark.
+    pub fn from_name(name: &str) -> Option<TextEncoding> {
+        let encoding = match name.to_ascii_uppercase().as_str() {
+            "UTF-8" | "UTF8" => TextEncoding::Utf8,
+            "UTF-16LE" => TextEncoding::Utf16Le,
+            "UTF-16BE" => TextEncoding::Utf16Be,
+            "ISO-8859-1" | "ISO_8859-1" | "LATIN1" | "L1" | "ISO-IR-100" | "CP819" | "IBM819" => TextEncoding::Latin1,
+            "WINDOWS-1252" | "CP1252" => TextEncoding::Windows1252,
+            "US-ASCII" | "ASCII" | "ISO646-US" => TextEncoding::Ascii,
+            _ => return None,
+        };
+        Some(encoding)
+    }
+
+    /// The name the encoding is registered under
+    pub fn name(&self) -> &'static str {
+        match self {
+            TextEncoding::Utf8 => "UTF-8",
+            TextEncoding::Utf16Le => "UTF-16LE",
+            TextEncoding::Utf16Be => "UTF-16BE",
+            TextEncoding::Latin1 => "ISO-8859-1",
+            TextEncoding::Windows1252 => "windows-1252",
+            TextEncoding::Ascii => "US-ASCII",
+        }
+    }
+
+    fn is_utf16(&self) -> bool {
+        matches!(self, TextEncoding::Utf16Le | TextEncoding::Utf16Be)
+    }
+}
+
+impl fmt::Display for TextEncoding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+/// Decode a document or external parsed entity to UTF-8, reading it in the
+/// encoding its byte order mark or encoding declaration gives, as Appendix F
+/// of the XML specification describes, or in UTF-8 if it has neither.
+///
+/// Returns the text without its byte order mark, borrowed from `bytes` when
+/// it is already UTF-8, along with the encoding it was read in. Errors are
+/// positioned in the decoded text.
+pub fn decode(bytes: &[u8]) -> Result<(Cow<'_, str>, TextEncoding), XmlError> {
+    let (encoding, bom) = detect_encoding(bytes)?;
+    let bytes = &bytes[bom..];
+    if encoding == TextEncoding::Utf8 {
+        return match std::str::from_utf8(bytes) {
+            Ok(text) => Ok((Cow::Borrowed(text), encoding)),
+            Err(e) => {
+                let text = std::str::from_utf8(&bytes[..e.valid_up_to()]).unwrap_or_default();
+                Err(XmlError::new(XmlErrorKind::InvalidUtf8, text.len(), "document").locate(text))
+            }
+        };
+    }
+    let mut text = String::with_capacity(bytes.len());
+    let decoded = decode_into(encoding, bytes, &mut text, 0).and_then(|used| {
+        if used == bytes.len() {
+            Ok(())
+        } else {
+            Err(XmlError::new(invalid_kind(encoding), text.len(), "document"))
+        }
+    });
+    match decoded {
+        Ok(()) => Ok((Cow::Owned(text), encoding)),
+        Err(e) => Err(e.locate(&text)),
+    }
+}
+
+/// What the first bytes of a document say about how it is encoded
+#[derive(Clone, Copy)]
+enum Detected {
+    /// a byte order mark of the given length
+    Bom(TextEncoding, usize),
+    /// `<?` in UTF-16 without a byte order mark
+    Utf16(TextEncoding),
+    /// an encoding that keeps ASCII characters as single bytes
+    AsciiCompatible,
+    /// an encoding that cannot be read, by the name of its family
+    Unsupported(&'static str),
+}
+
+fn detect(bytes: &[u8]) -> Detected {
+    match bytes {
+        [0x00, 0x00, 0xFE, 0xFF, ..]
+        | [0xFF, 0xFE, 0x00, 0x00, ..]
+        | [0x00, 0x00, 0x00, 0x3C, ..]
+        | [0x3C, 0x00, 0x00, 0x00, ..] => Detected::Unsupported("ISO-10646-UCS-4"),
+        [0x4C, 0x6F, 0xA7, 0x94, ..] => Detected::Unsupported("EBCDIC"),
+        [0xEF, 0xBB, 0xBF, ..] => Detected::Bom(TextEncoding::Utf8, 3),
+        [0xFE, 0xFF, ..] => Detected::Bom(TextEncoding::Utf16Be, 2),
+        [0xFF, 0xFE, ..] => Detected::Bom(TextEncoding::Utf16Le, 2),
+        [0x00, 0x3C, 0x00, 0x3F, ..] => Detected::Utf16(TextEncoding::Utf16Be),
+        [0x3C, 0x00, 0x3F, 0x00, ..] => Detected::Utf16(TextEncoding::Utf16Le),
+        _ => Detected::AsciiCompatible,
+    }
+}
+
+/// Whether enough of the start of a document has been read to choose its
+/// encoding, which takes the whole of its XML declaration if it has one
+pub(crate) fn can_detect(bytes: &[u8]) -> bool {
+    bytes.len() >= DECL_LIMIT || (bytes.len() >= 4 && bytes.contains(&b'>'))
+}
+
+/// Choose the encoding to read a document in from its first bytes and the
+/// encoding it declares, returning it with the length of the byte order
+/// mark to skip
+pub(crate) fn detect_encoding(bytes: &[u8]) -> Result<(TextEncoding, usize), XmlError> {
+    let detected = detect(bytes);
+    let (found, bom) = match detected {
+        Detected::Bom(encoding, len) => (encoding, len),
+        Detected::Utf16(encoding) => (encoding, 0),
+        Detected::AsciiCompatible => (TextEncoding::Utf8, 0),
+        Detected::Unsupported(name) => {
+            return Err(XmlError::new(XmlErrorKind::UnsupportedEncoding(String::from(name)), 0, "document"));
+        }
+    };
+    // the declaration is ASCII, so it reads the same whatever the encoding
+    // as long as the byte order is right
+    let head = &bytes[bom..bytes.len().min(DECL_LIMIT)];
+    let head: String = if found.is_utf16() {
+        let units = head.chunks_exact(2).map(|pair| utf16_unit(found, pair[0], pair[1]));
+        char::decode_utf16(units).map(|c| c.unwrap_or(char::REPLACEMENT_CHARACTER)).collect()
+    } else {
+        head.iter().map(|&b| char::from(b)).collect()
+    };
+    let Some((name, at)) = declared_encoding(&head) else {
+        return Ok((found, bom));
+    };
+    let conflict = || {
+        let kind = XmlErrorKind::EncodingConflict(String::from(name), found);
+        XmlError::new(kind, at, "EncodingDecl").locate(&head)
+    };
+    let declared = match TextEncoding::from_name(name) {
+        Some(declared) => declared,
+        None if name.eq_ignore_ascii_case("UTF-16") && found.is_utf16() => found,
+        None if name.eq_ignore_ascii_case("UTF-16") => return Err(conflict()),
+        None => {
+            let kind = XmlErrorKind::UnsupportedEncoding(String::from(name));
+            return Err(XmlError::new(kind, at, "EncodingDecl").locate(&head));
+        }
+    };
+    let agrees = match detected {
+        Detected::AsciiCompatible => !declared.is_utf16(),
+        _ => declared == found,
+    };
+    if !agrees {
+        return Err(conflict());
+    }
+    Ok((declared, bom))
+}
+
+/// Find the encoding named in the XML or text declaration at the start of
+/// `text`, returning it with where the name starts
+fn declared_encoding(text: &str) -> Option<(&str, usize)> {
+    let is_ws = |c: char| matches!(c, ' ' | '\t' | '\r' | '\n');
+    let decl = text.strip_prefix("<?xml").filter(|decl| decl.starts_with(is_ws))?;
+    let decl = &decl[..decl.find("?>")?];
+    let after = &decl[decl.find("encoding")? + "encoding".len()..];
+    let after = after.trim_start_matches(is_ws).strip_prefix('=')?.trim_start_matches(is_ws);
+    let quote = after.chars().next().filter(|&c| c == '"' || c == '\'')?;
+    let value = &after[1..];
+    let name = &value[..value.find(quote)?];
+    Some((name, "<?xml".len() + decl.len() - value.len()))
+}
+
+fn utf16_unit(encoding: TextEncoding, first: u8, second: u8) -> u16 {
+    match encoding {
+        TextEncoding::Utf16Be => u16::from_be_bytes([first, second]),
+        _ => u16::from_le_bytes([first, second]),
+    }
+}
+
+/// The characters that windows-1252 puts at bytes 0x80 to 0x9F, where
+/// ISO-8859-1 has C1 controls. The five bytes it leaves undefined are read
+/// as the controls.
+const WINDOWS_1252_HIGH: [char; 32] = [
+    '\u{20AC}', '\u{81}', '\u{201A}', '\u{192}', '\u{201E}', '\u{2026}', '\u{2020}', '\u{2021}', '\u{2C6}', '\u{2030}',
+    '\u{160}', '\u{2039}', '\u{152}', '\u{8D}', '\u{17D}', '\u{8F}', '\u{90}', '\u{2018}', '\u{2019}', '\u{201C}',
+    '\u{201D}', '\u{2022}', '\u{2013}', '\u{2014}', '\u{2DC}', '\u{2122}', '\u{161}', '\u{203A}', '\u{153}', '\u{9D}',
+    '\u{17E}', '\u{178}',
+];
+
+/// Decodes bytes in one encoding to UTF-8 a piece at a time, for reading
+/// documents from a source as they arrive
+pub(crate) struct Decoder {
+    encoding: TextEncoding,
+    /// bytes given that end part way through a character
+    partial: Vec<u8>,
+}
+
+impl Decoder {
+    pub(crate) fn new(encoding: TextEncoding) -> Decoder {
+        Decoder {
+            encoding,
+            partial: Vec::new(),
+        }
+    }
+
+    /// Decode `bytes` onto the end of `out`, keeping a character they end
+    /// part way through for the next call. `offset` is where `out` starts
+    /// in the document. On error, `out` holds the text before the bytes
+    /// that could not be decoded.
+    pub(crate) fn decode(&mut self, bytes: &[u8], out: &mut String, offset: usize) -> Result<(), XmlError> {
+        self.partial.extend_from_slice(bytes);
+        let used = decode_into(self.encoding, &self.partial, out, offset)?;
+        self.partial.drain(..used);
+        Ok(())
+    }
+
+    /// Check that the bytes given did not end part way through a character.
+    /// `end` is where the decoded text ends in the document.
+    pub(crate) fn finish(&self, end: usize) -> Result<(), XmlError> {
+        if self.partial.is_empty() {
+            Ok(())
+        } else {
+            Err(XmlError::new(invalid_kind(self.encoding), end, "document"))
+        }
+    }
+}
+
+/// Decode as much of `bytes` as makes whole characters onto the end of
+/// `out`, returning how many bytes were used. `offset` is where `out` starts
+/// in the document.
+fn decode_into(encoding: TextEncoding, bytes: &[u8], out: &mut String, offset: usize) -> Result<usize, XmlError> {
+    let invalid = |out: &String| XmlError::new(invalid_kind(encoding), offset + out.len(), "document");
+    let used = match encoding {
+        TextEncoding::Utf8 => {
+            let (valid, broken) = match std::str::from_utf8(bytes) {
+                Ok(text) => (text.len(), false),
+                // a character may be completed by the next call
+                Err(e) => (e.valid_up_to(), e.error_len().is_some()),
+            };
+            out.push_str(std::str::from_utf8(&bytes[..valid]).unwrap_or_default());
+            if broken {
+                return Err(invalid(out));
+            }
+            valid
+        }
+        TextEncoding::Utf16Le | TextEncoding::Utf16Be => {
+            let mut used = 0;
+            while let [first, second, tail @ ..] = &bytes[used..] {
+                let unit = utf16_unit(encoding, *first, *second);
+                let (c, len) = match unit {
+                    0xD800..=0xDBFF => match tail {
+                        [third, fourth, ..] => {
+                            let low = utf16_unit(encoding, *third, *fourth);
+                            let pair = char::decode_utf16([unit, low]).next().and_then(Result::ok);
+                            (pair.ok_or_else(|| invalid(out))?, 4)
+                        }
+                        // the other half of the pair may come with the next call
+                        _ => break,
+                    },
+                    _ => (char::from_u32(u32::from(unit)).ok_or_else(|| invalid(out))?, 2),
+                };
+                out.push(c);
+                used += len;
+            }
+            used
+        }
+        TextEncoding::Latin1 => {
+            out.extend(bytes.iter().map(|&b| char::from(b)));
+            bytes.len()
+        }
+        TextEncoding::Windows1252 => {
+            out.extend(bytes.iter().map(|&b| match b {
+                0x80..=0x9F => WINDOWS_1252_HIGH[usize::from(b - 0x80)],
+                _ => char::from(b),
+            }));
+            bytes.len()
+        }
+        TextEncoding::Ascii => {
+            for &b in bytes {
+                if !b.is_ascii() {
+                    return Err(invalid(out));
+                }
+                out.push(char::from(b));
+            }
+            bytes.len()
+        }
+    };
+    Ok(used)
+}
+
+fn invalid_kind(encoding: TextEncoding) -> XmlErrorKind {
+    match encoding {
+        TextEncoding::Utf8 => XmlErrorKind::InvalidUtf8,
+        encoding => XmlErrorKind::InvalidEncoding(encoding),
+    }
+}
@@ -0,0 +1,1151 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::encoding;
+use crate::error::{ExpansionLimit, XmlError, XmlErrorKind};
+use crate::position::Position;
+use crate::{
+    parse_content, parse_int_subset_item, parse_pereference, parse_reference, parse_textdecl, parse_ws,
+    predefined_entity, rest, AttValue, AttValueItem, ContentItem, DefaultDecl, Doc, DoctypeDecl, Elem, EntityDecl, EntityDef,
+    EntityValue, Ends, ExtSubset, IntSubsetItem, Name, PEDef, PEReference, ParseOptions, Reference,
+    MAX_RECUR_DEPTH,
+};
+
+/// A general or parameter entity declared in the DTD
+#[derive(Debug, Clone, PartialEq)]
+pub enum Entity {
+    /// declared with a literal value, holding its replacement text: the
+    /// literal with character references replaced by their characters
+    Internal(String),
+    /// declared with an external identifier. Entities with a notation are
+    /// unparsed, and may only be named by `ENTITY` attributes.
+    External {
+        public_id: Option<String>,
+        system_id: String,
+        notation: Option<String>,
+        /// the URI of the external DTD text holding the declaration, which a
+        /// relative `system_id` is resolved against. `None` when declared in
+        /// the document itself.
+        base_uri: Option<String>,
+    },
+}
+
+impl Entity {
+    /// The replacement text of an internal entity
+    pub fn replacement_text(&self) -> Option<&str> {
+        match &self {
+            Entity::Internal(text) => Some(text),
+            Entity::External { .. } => None,
+        }
+    }
+
+    /// Whether the entity is an unparsed entity, declared with `NDATA`
+    pub fn is_unparsed(&self) -> bool {
+        matches!(self, Entity::External { notation: Some(_), .. })
+    }
+}
+
+/// The general and parameter entities declared in a DTD, by name. When an
+/// entity is declared more than once, the first declaration is binding.
+#[derive(Debug, Default)]
+pub struct EntityTable {
+    general: HashMap<String, Entity>,
+    parameter: HashMap<String, Entity>,
+}
+
+impl EntityTable {
+    pub fn new() -> EntityTable {
+        EntityTable::default()
+    }
+
+    /// Look up a general entity by name
+    pub fn get(&self, name: &str) -> Option<&Entity> {
+        self.general.get(name)
+    }
+
+    /// Declare a general entity, unless one with the same name was already
+    /// declared. Returns whether the declaration was added.
+    pub fn declare(&mut self, name: &str, entity: Entity) -> bool {
+        if self.general.contains_key(name) {
+            false
+        } else {
+            self.general.insert(String::from(name), entity);
+            true
+        }
+    }
+
+    /// Names of the declared general entities, in no particular order
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.general.keys().map(|name| name.as_str())
+    }
+
+    /// Look up a parameter entity by name, without the leading `%`
+    pub fn get_parameter(&self, name: &str) -> Option<&Entity> {
+        self.parameter.get(name)
+    }
+
+    /// Declare a parameter entity, unless one with the same name was
+    /// already declared. Returns whether the declaration was added.
+    pub fn declare_parameter(&mut self, name: &str, entity: Entity) -> bool {
+        if self.parameter.contains_key(name) {
+            false
+        } else {
+            self.parameter.insert(String::from(name), entity);
+            true
+        }
+    }
+
+    pub(crate) fn from_doctype(doctype: &DoctypeDecl) -> EntityTable {
+        let mut table = EntityTable::new();
+        if let Some(subset) = &doctype.int_subset {
+            table.declare_all(&subset.items, None);
+        }
+        if let Some(ext) = &doctype.ext_subset {
+            table.declare_all(&ext.items, Some(&ext.uri));
+        }
+        table
+    }
+
+    /// Add the entities declared by `items` to the table, in document order
+    fn declare_all(&mut self, items: &[IntSubsetItem], base_uri: Option<&str>) {
+        for item in items {
+            match item {
+                IntSubsetItem::EntityDecl(decl) => {
+                    self.declare_from(decl, base_uri);
+                }
+                IntSubsetItem::ParamEntity { uri, items, .. } => self.declare_all(items, uri.as_deref().or(base_uri)),
+                IntSubsetItem::IncludeSect { items, .. } => self.declare_all(items, base_uri),
+                _ => (),
+            };
+        }
+    }
+
+    /// Add the entity declared by `decl`, in DTD text loaded from
+    /// `base_uri`, to the table
+    fn declare_from(&mut self, decl: &EntityDecl, base_uri: Option<&str>) -> bool {
+        match decl {
+            EntityDecl::GEDecl(gedecl) => {
+                let entity = match &gedecl.entity_def {
+                    EntityDef::EntityValue(value) => Entity::Internal(replacement_text(value)),
+                    EntityDef::External { ext_id, ndatadecl } => Entity::External {
+                        public_id: ext_id.public_id().map(String::from),
+                        system_id: String::from(ext_id.system_id()),
+                        notation: ndatadecl.as_ref().map(|ndata| ndata.name.0.clone()),
+                        base_uri: base_uri.map(String::from),
+                    },
+                };
+                self.declare(gedecl.name.as_str(), entity)
+            }
+            EntityDecl::PEDecl(pedecl) => {
+                let entity = match &pedecl.pedef {
+                    PEDef::EntityValue(value) => Entity::Internal(replacement_text(value)),
+                    PEDef::ExternalID(ext_id) => Entity::External {
+                        public_id: ext_id.public_id().map(String::from),
+                        system_id: String::from(ext_id.system_id()),
+                        notation: None,
+                        base_uri: base_uri.map(String::from),
+                    },
+                };
+                self.declare_parameter(pedecl.name.as_str(), entity)
+            }
+        }
+    }
+}
+
+/// The general entities a document may reference, for checking the
+/// well-formedness constraints on entity references whether or not they are
+/// expanded: Entity Declared, Parsed Entity, No External Entity References,
+/// No Recursion and No < in Attribute Values
+#[derive(Debug)]
+pub(crate) struct DeclaredEntities {
+    table: EntityTable,
+    /// whether every entity the document may reference is in `table`, so
+    /// that references to others are errors
+    complete: bool,
+    /// the first error found by following the references in the replacement
+    /// text of each internal entity that has one, keyed by its name and
+    /// whether it is referenced in an attribute value
+    faults: HashMap<(String, bool), XmlErrorKind>,
+}
+
+impl DeclaredEntities {
+    pub(crate) fn new(table: EntityTable, complete: bool) -> DeclaredEntities {
+        let mut declared = DeclaredEntities {
+            table,
+            complete,
+            faults: HashMap::new(),
+        };
+        let mut checked = HashSet::new();
+        let names: Vec<String> = declared.table.names().map(String::from).collect();
+        for name in &names {
+            for in_attvalue in [false, true] {
+                declared.find_fault(name, in_attvalue, &mut Vec::new(), &mut checked);
+            }
+        }
+        declared
+    }
+
+    /// Check that `reference` in content, at byte offset `start`, names an
+    /// entity that may be referenced there
+    pub(crate) fn check(&self, reference: &Reference, start: usize) -> Result<(), XmlError> {
+        self.check_in(reference, start, false)
+    }
+
+    /// Check that `reference` in an attribute value, at byte offset `start`,
+    /// names an entity that may be referenced there
+    pub(crate) fn check_in_attvalue(&self, reference: &Reference, start: usize) -> Result<(), XmlError> {
+        self.check_in(reference, start, true)
+    }
+
+    fn check_in(&self, reference: &Reference, start: usize, in_attvalue: bool) -> Result<(), XmlError> {
+        let name = match reference {
+            Reference::EntityRef(name) => name.as_str(),
+            Reference::CharRef(_) => return Ok(()),
+        };
+        if predefined_entity(name).is_some() {
+            return Ok(());
+        }
+        if let Some(kind) = self.reference_fault(name, in_attvalue) {
+            return Err(XmlError::new(kind, start, "EntityRef"));
+        }
+        let kind = match self.faults.get(&(String::from(name), in_attvalue)) {
+            // which entity is found to recur depends on where the search
+            // started, so name the one that expanding `name` comes back to
+            Some(XmlErrorKind::RecursiveEntity(_)) => XmlErrorKind::RecursiveEntity(self.reentered(name, in_attvalue)),
+            Some(kind) => kind.clone(),
+            None => return Ok(()),
+        };
+        Err(XmlError::new(kind, start, "EntityRef").within(&format!("entity `{}`", name)))
+    }
+
+    /// The first entity that expanding recursive entity `name` would open
+    /// again, following the first reference with an error in each
+    /// replacement text
+    fn reentered(&self, name: &str, in_attvalue: bool) -> String {
+        let mut path = vec![String::from(name)];
+        while let Some(Entity::Internal(text)) = path.last().and_then(|current| self.table.get(current)) {
+            let mut next = None;
+            let mut here = 0;
+            while next.is_none()
+                && let Some(idx) = rest(text, here).find('&')
+            {
+                here += idx;
+                if let Ok(Reference::EntityRef(inner)) = parse_reference(text, here)
+                    && self.faults.contains_key(&(inner.0.clone(), in_attvalue))
+                {
+                    next = Some(inner.0);
+                }
+                here += 1;
+            }
+            match next {
+                Some(next) if path.contains(&next) => return next,
+                Some(next) => path.push(next),
+                None => break,
+            };
+        }
+        String::from(name)
+    }
+
+    /// The error in referring to entity `name` itself, leaving aside what
+    /// its replacement text refers to
+    fn reference_fault(&self, name: &str, in_attvalue: bool) -> Option<XmlErrorKind> {
+        match self.table.get(name) {
+            None if self.complete => Some(XmlErrorKind::UndeclaredEntity(String::from(name))),
+            // WFC: No External Entity References
+            Some(Entity::External { .. }) if in_attvalue => {
+                Some(XmlErrorKind::ExternalEntityInAttValue(String::from(name)))
+            }
+            // WFC: Parsed Entity
+            Some(Entity::External { notation: Some(_), .. }) => Some(XmlErrorKind::UnparsedEntityRef(String::from(name))),
+            _ => None,
+        }
+    }
+
+    /// Find the first error in the replacement text of entity `name`,
+    /// following the references in it through the entities in `open`,
+    /// and note it in `faults`
+    fn find_fault(
+        &mut self,
+        name: &str,
+        in_attvalue: bool,
+        open: &mut Vec<String>,
+        checked: &mut HashSet<(String, bool)>,
+    ) -> Option<XmlErrorKind> {
+        let key = (String::from(name), in_attvalue);
+        if checked.contains(&key) {
+            return self.faults.get(&key).cloned();
+        }
+        // WFC: No Recursion
+        if open.iter().any(|open| open == name) {
+            return Some(XmlErrorKind::RecursiveEntity(String::from(name)));
+        }
+        if open.len() > MAX_RECUR_DEPTH {
+            return None;
+        }
+        let text = match self.table.get(name) {
+            Some(Entity::Internal(text)) => text.clone(),
+            _ => return None,
+        };
+        // WFC: No < in Attribute Values
+        let mut found = if in_attvalue && text.contains('<') {
+            Some(XmlErrorKind::BadChar('<'))
+        } else {
+            None
+        };
+        open.push(String::from(name));
+        let mut here = 0;
+        while found.is_none()
+            && let Some(idx) = rest(&text, here).find('&')
+        {
+            here += idx;
+            if let Ok(Reference::EntityRef(inner)) = parse_reference(&text, here)
+                && predefined_entity(inner.as_str()).is_none()
+            {
+                found = self
+                    .reference_fault(inner.as_str(), in_attvalue)
+                    .or_else(|| self.find_fault(inner.as_str(), in_attvalue, open, checked));
+            }
+            here += 1;
+        }
+        open.pop();
+        if let Some(kind) = &found {
+            self.faults.insert(key.clone(), kind.clone());
+        }
+        checked.insert(key);
+        found
+    }
+}
+
+/// Build the replacement text of an internal entity from its literal value.
+/// Character references are replaced immediately, while general entity
+/// references are left for when the entity itself is expanded.
+fn replacement_text(value: &EntityValue) -> String {
+    let literal = &value.text;
+    let mut buf = String::new();
+    let mut here = 0;
+    while let Some(c) = literal.get(here..).and_then(|rest| rest.chars().next()) {
+        if c == '&'
+            && let Ok(reference @ Reference::CharRef(_)) = parse_reference(literal, here)
+            && let Some(decoded) = reference.decode()
+        {
+            buf.push(decoded);
+            here += reference.text_len();
+            continue;
+        }
+        buf.push(c);
+        here += c.len_utf8();
+    }
+    buf
+}
+
+/// Keeps track of entity expansion across a document, enforcing the limits
+/// set in `ParseOptions`
+pub(crate) struct Budget<'a> {
+    options: &'a ParseOptions,
+    /// size of the document in bytes
+    doc_len: usize,
+    /// characters of replacement text expanded so far
+    expanded: usize,
+    /// names of the entities currently being expanded, to detect recursion
+    open: Vec<String>,
+}
+
+impl<'a> Budget<'a> {
+    pub(crate) fn new(options: &'a ParseOptions, doc_len: usize) -> Budget<'a> {
+        Budget {
+            options,
+            doc_len,
+            expanded: 0,
+            open: Vec::new(),
+        }
+    }
+
+    /// Note that the entity written as `name`, with replacement text `text`,
+    /// is being expanded. Fails if it is already open further up, which would
+    /// make the expansion infinite, or if expanding it would exceed a limit.
+    fn open(&mut self, name: &str, text: &str, recurdepth: usize) -> Result<(), XmlError> {
+        if recurdepth > MAX_RECUR_DEPTH {
+            return Err(XmlError::new(XmlErrorKind::MaxRecurDepth(recurdepth as u32), 0, "EntityRef"));
+        }
+        if self.open.iter().any(|open| open == name) {
+            return Err(XmlError::new(XmlErrorKind::RecursiveEntity(String::from(name)), 0, "EntityRef"));
+        }
+        let options = self.options;
+        self.expanded = self.expanded.saturating_add(text.chars().count());
+        let exceeded = if self.open.len() >= options.max_entity_depth {
+            Some(ExpansionLimit::Depth(options.max_entity_depth))
+        } else if self.expanded > options.max_expanded_chars {
+            Some(ExpansionLimit::TotalChars(options.max_expanded_chars))
+        } else if self.expanded > self.doc_len.saturating_mul(options.max_expansion_ratio) {
+            Some(ExpansionLimit::Ratio(options.max_expansion_ratio))
+        } else {
+            None
+        };
+        if let Some(limit) = exceeded {
+            return Err(XmlError::new(XmlErrorKind::ExpansionLimit(limit), 0, "EntityRef"));
+        }
+        self.open.push(String::from(name));
+        Ok(())
+    }
+
+    /// Note that the most recently opened entity is fully expanded
+    fn close(&mut self) {
+        self.open.pop();
+    }
+
+    /// Count `len` bytes of external text loaded by the resolver as part of
+    /// the document when limiting the expansion ratio
+    fn add_source(&mut self, len: usize) {
+        self.doc_len = self.doc_len.saturating_add(len);
+    }
+}
+
+/// Load an external entity with the resolver set in the options. Returns the
+/// URI it was loaded from and its text following any text declaration, or
+/// `None` if the resolver skips it.
+fn load_external(
+    budget: &mut Budget,
+    public_id: Option<&str>,
+    system_id: &str,
+    base_uri: Option<&str>,
+) -> Result<Option<(String, String)>, XmlError> {
+    let options = budget.options;
+    let base_uri = base_uri.or(options.base_uri.as_deref());
+    let resolved = match options.resolver.resolve(public_id, system_id, base_uri) {
+        Ok(Some(resolved)) => resolved,
+        Ok(None) => return Ok(None),
+        Err(e) => {
+            let kind = XmlErrorKind::UnresolvedEntity(String::from(system_id), e.to_string().into());
+            return Err(XmlError::new(kind, 0, "ExternalID"));
+        }
+    };
+    let (text, _) = encoding::decode(&resolved.bytes).map_err(|e| e.within("external entity"))?;
+    let mut text = text.into_owned();
+    match parse_textdecl(&text, 0) {
+        Ok(decl) => {
+            text.drain(..decl.end);
+        }
+        Err(e) => {
+            if e.kind() != &XmlErrorKind::BadXDeclStart {
+                return Err(e);
+            }
+        }
+    };
+    budget.add_source(text.len());
+    Ok(Some((resolved.uri, text)))
+}
+
+/// Replace references to parameter entities between the markup declarations
+/// of the internal subset with the declarations parsed from their replacement
+/// text, then load and parse the external subset if the options ask for it.
+/// The internal subset is read first, so its declarations are binding.
+pub(crate) fn expand_dtd(doctype: &mut DoctypeDecl, budget: &mut Budget) -> Result<(), XmlError> {
+    let mut table = EntityTable::new();
+    if let Some(subset) = doctype.int_subset.as_mut() {
+        expand_decls(&mut subset.items, &mut table, budget, 0)?;
+    }
+    if !budget.options.load_external_dtd {
+        return Ok(());
+    }
+    if let Some(ext_id) = &doctype.ext_id {
+        let mut reader = DtdReader {
+            table: &mut table,
+            budget,
+        };
+        let loaded = reader
+            .load_dtd(ext_id.public_id(), ext_id.system_id(), None, 0)
+            .map_err(|e| e.relocate(ext_id.get_startpos()).within("external subset"))?;
+        doctype.ext_subset = loaded.map(|(uri, items)| ExtSubset { uri, items });
+    }
+    Ok(())
+}
+
+fn expand_decls(
+    items: &mut [IntSubsetItem],
+    table: &mut EntityTable,
+    budget: &mut Budget,
+    recurdepth: usize,
+) -> Result<(), XmlError> {
+    for item in items.iter_mut() {
+        let expanded = match item {
+            IntSubsetItem::EntityDecl(decl) => {
+                check_no_pe_refs(decl)?;
+                table.declare_from(decl, None);
+                None
+            }
+            IntSubsetItem::PEReference { start, reference } => {
+                let name = format!("%{}", reference.0);
+                match table.get_parameter(reference.0.as_str()).cloned() {
+                    Some(Entity::Internal(text)) => {
+                        let items = expand_pe_decls(&name, &text, table, budget, recurdepth)
+                            .map_err(|e| e.relocate(*start).within(&format!("entity `{}`", name)))?;
+                        Some(IntSubsetItem::ParamEntity {
+                            start: *start,
+                            reference: PEReference(reference.0.clone()),
+                            uri: None,
+                            items,
+                        })
+                    }
+                    Some(Entity::External {
+                        public_id,
+                        system_id,
+                        base_uri,
+                        ..
+                    }) if budget.options.load_external_dtd => {
+                        let mut reader = DtdReader { table, budget };
+                        reader
+                            .load_external_pe(&name, public_id.as_deref(), &system_id, base_uri.as_deref(), recurdepth)
+                            .map_err(|e| e.relocate(*start).within(&format!("entity `{}`", name)))?
+                            .map(|(uri, items)| IntSubsetItem::ParamEntity {
+                                start: *start,
+                                reference: PEReference(reference.0.clone()),
+                                uri: Some(uri),
+                                items,
+                            })
+                    }
+                    // external entities that are not loaded and undeclared
+                    // entities are left as references
+                    _ => None,
+                }
+            }
+            _ => None,
+        };
+        if let Some(expanded) = expanded {
+            *item = expanded;
+        }
+    }
+    Ok(())
+}
+
+/// Parse the replacement text of parameter entity `name` as markup
+/// declarations, expanding any references within it
+fn expand_pe_decls(
+    name: &str,
+    text: &str,
+    table: &mut EntityTable,
+    budget: &mut Budget,
+    recurdepth: usize,
+) -> Result<Vec<IntSubsetItem>, XmlError> {
+    budget.open(name, text, recurdepth)?;
+    let mut items = Vec::new();
+    let mut here = 0;
+    while here < text.len() {
+        let item = parse_int_subset_item(text, here)?;
+        here = item.get_endpos();
+        items.push(item);
+    }
+    expand_decls(&mut items, table, budget, recurdepth + 1)?;
+    budget.close();
+    Ok(items)
+}
+
+/// Check that a literal entity value in the internal subset does not refer
+/// to parameter entities, which may only appear between declarations there
+fn check_no_pe_refs(decl: &EntityDecl) -> Result<(), XmlError> {
+    let value = match decl {
+        EntityDecl::GEDecl(gedecl) => match &gedecl.entity_def {
+            EntityDef::EntityValue(value) => value,
+            EntityDef::External { .. } => return Ok(()),
+        },
+        EntityDecl::PEDecl(pedecl) => match &pedecl.pedef {
+            PEDef::EntityValue(value) => value,
+            PEDef::ExternalID(_) => return Ok(()),
+        },
+    };
+    match value.text.find('%') {
+        // skip the opening qoute
+        Some(offset) => Err(XmlError::new(XmlErrorKind::BadChar('%'), value.start + 1 + offset, "EntityValue in intSubset")),
+        None => Ok(()),
+    }
+}
+
+/// Reads external DTD text: the external subset and external parameter
+/// entities. Unlike the internal subset, parameter entity references may
+/// appear within markup declarations there, and conditional sections are
+/// allowed.
+struct DtdReader<'a, 'b> {
+    table: &'a mut EntityTable,
+    budget: &'a mut Budget<'b>,
+}
+
+impl DtdReader<'_, '_> {
+    /// Load and parse DTD text, returning the URI it was loaded from and
+    /// its declarations. Errors are reported at the start of the external
+    /// identifier, naming the line of the DTD where they occurred.
+    fn load_dtd(
+        &mut self,
+        public_id: Option<&str>,
+        system_id: &str,
+        base_uri: Option<&str>,
+        recurdepth: usize,
+    ) -> Result<Option<(String, Vec<IntSubsetItem>)>, XmlError> {
+        let (uri, text) = match load_external(self.budget, public_id, system_id, base_uri)? {
+            Some(loaded) => loaded,
+            None => return Ok(None),
+        };
+        match self.parse_decls(&text, 0, false, &uri, recurdepth) {
+            Ok((items, _)) => Ok(Some((uri, items))),
+            Err(e) => {
+                let line = Position::locate(&text, e.doc_idx()).line;
+                Err(e.within(&format!("`{}` line {}", uri, line)))
+            }
+        }
+    }
+
+    /// Load the external parameter entity `name` and parse its declarations
+    fn load_external_pe(
+        &mut self,
+        name: &str,
+        public_id: Option<&str>,
+        system_id: &str,
+        base_uri: Option<&str>,
+        recurdepth: usize,
+    ) -> Result<Option<(String, Vec<IntSubsetItem>)>, XmlError> {
+        // the loaded text counts as part of the document rather than as
+        // expanded characters, but the entity is still open for recursion
+        self.budget.open(name, "", recurdepth)?;
+        let loaded = self.load_dtd(public_id, system_id, base_uri, recurdepth + 1)?;
+        self.budget.close();
+        Ok(loaded)
+    }
+
+    /// Parse declarations from `start` to the end of `text`, or up to the
+    /// `]]>` closing an include section when `in_sect` is set. Returns the
+    /// declarations and where they end. `base_uri` is where `text` was
+    /// loaded from.
+    fn parse_decls(
+        &mut self,
+        text: &str,
+        start: usize,
+        in_sect: bool,
+        base_uri: &str,
+        recurdepth: usize,
+    ) -> Result<(Vec<IntSubsetItem>, usize), XmlError> {
+        if recurdepth > MAX_RECUR_DEPTH {
+            return Err(XmlError::new(XmlErrorKind::MaxRecurDepth(recurdepth as u32), start, "extSubsetDecl"));
+        }
+        let mut items = Vec::new();
+        let mut here = start;
+        loop {
+            let subtext = rest(text, here);
+            if subtext.is_empty() {
+                if in_sect {
+                    return Err(XmlError::new(XmlErrorKind::TextEnd, here, "includeSect"));
+                }
+                break;
+            }
+            if in_sect && subtext.starts_with("]]>") {
+                break;
+            }
+            let (item, end) = if subtext.starts_with('%') {
+                self.expand_pe(text, here, base_uri, recurdepth)?
+            } else if subtext.starts_with("<![") {
+                self.parse_conditional_sect(text, here, base_uri, recurdepth)?
+            } else if subtext.starts_with("<!") && !subtext.starts_with("<!--") {
+                self.parse_markup_decl(text, here, base_uri, recurdepth)?
+            } else {
+                // white space, comments and processing instructions
+                let item = parse_int_subset_item(text, here)?;
+                let end = item.get_endpos();
+                (item, end)
+            };
+            items.push(item);
+            here = end;
+        }
+        Ok((items, here))
+    }
+
+    /// Expand a parameter entity reference between declarations
+    fn expand_pe(
+        &mut self,
+        text: &str,
+        start: usize,
+        base_uri: &str,
+        recurdepth: usize,
+    ) -> Result<(IntSubsetItem, usize), XmlError> {
+        let reference = parse_pereference(text, start)?;
+        let end = start + reference.textlen();
+        let name = format!("%{}", reference.0);
+        let expanded = match self.table.get_parameter(reference.0.as_str()).cloned() {
+            Some(Entity::Internal(replacement)) => {
+                self.budget.open(&name, &replacement, recurdepth)?;
+                let (items, _) = self
+                    .parse_decls(&replacement, 0, false, base_uri, recurdepth + 1)
+                    .map_err(|e| e.relocate(start).within(&format!("entity `{}`", name)))?;
+                self.budget.close();
+                Some((None, items))
+            }
+            Some(Entity::External {
+                public_id,
+                system_id,
+                base_uri: entity_base,
+                ..
+            }) => self
+                .load_external_pe(&name, public_id.as_deref(), &system_id, entity_base.as_deref(), recurdepth)
+                .map_err(|e| e.relocate(start).within(&format!("entity `{}`", name)))?
+                .map(|(uri, items)| (Some(uri), items)),
+            None => None,
+        };
+        let item = match expanded {
+            Some((uri, items)) => IntSubsetItem::ParamEntity {
+                start,
+                reference,
+                uri,
+                items,
+            },
+            // as in the internal subset, undeclared and skipped entities are
+            // left as references
+            None => IntSubsetItem::PEReference { start, reference },
+        };
+        Ok((item, end))
+    }
+
+    /// Parse `<![INCLUDE[ ... ]]>` or `<![IGNORE[ ... ]]>`, where the keyword
+    /// may be given by a parameter entity reference
+    fn parse_conditional_sect(
+        &mut self,
+        text: &str,
+        start: usize,
+        base_uri: &str,
+        recurdepth: usize,
+    ) -> Result<(IntSubsetItem, usize), XmlError> {
+        let mut here = skip_ws(text, start + 3);
+        let keyword = if rest(text, here).starts_with('%') {
+            let reference = parse_pereference(text, here)?;
+            let keyword = self
+                .pe_text(&reference, false, base_uri, recurdepth)
+                .map_err(|e| e.relocate(here).within(&format!("entity `%{}`", reference.0)))?;
+            here += reference.textlen();
+            String::from(keyword.trim())
+        } else {
+            let keyword: String = rest(text, here).chars().take_while(|c| c.is_ascii_uppercase()).collect();
+            here += keyword.len();
+            keyword
+        };
+        let include = match keyword.as_str() {
+            "INCLUDE" => true,
+            "IGNORE" => false,
+            _ => return Err(XmlError::new(XmlErrorKind::KeywordMatchFail, start + 3, "conditionalSect")),
+        };
+        here = skip_ws(text, here);
+        if !rest(text, here).starts_with('[') {
+            let context = if include { "includeSect" } else { "ignoreSect" };
+            return match rest(text, here).chars().next() {
+                Some(c) => Err(XmlError::new(XmlErrorKind::BadChar(c), here, context)),
+                None => Err(XmlError::new(XmlErrorKind::TextEnd, here, context)),
+            };
+        }
+        here += 1;
+        if include {
+            let (items, end) = self.parse_decls(text, here, true, base_uri, recurdepth + 1)?;
+            let end = end + 3;
+            Ok((IntSubsetItem::IncludeSect { start, end, items }, end))
+        } else {
+            // ignored sections nest, but their content is not parsed
+            let mut depth = 1;
+            while depth > 0 {
+                let subtext = rest(text, here);
+                let open = subtext.find("<![");
+                let close = subtext.find("]]>");
+                match (open, close) {
+                    (Some(open), Some(close)) if open < close => {
+                        depth += 1;
+                        here += open + 3;
+                    }
+                    (_, Some(close)) => {
+                        depth -= 1;
+                        here += close + 3;
+                    }
+                    (_, None) => return Err(XmlError::new(XmlErrorKind::TextEnd, text.len(), "ignoreSect")),
+                };
+            }
+            Ok((IntSubsetItem::IgnoreSect { start, end: here }, here))
+        }
+    }
+
+    /// Parse a markup declaration, replacing any parameter entity references
+    /// within it first
+    fn parse_markup_decl(
+        &mut self,
+        text: &str,
+        start: usize,
+        base_uri: &str,
+        recurdepth: usize,
+    ) -> Result<(IntSubsetItem, usize), XmlError> {
+        let end = markup_decl_end(text, start)?;
+        let raw = &text[start..end];
+        let item = if raw.contains('%') {
+            let decl = self.substitute_decl(raw, base_uri, recurdepth).map_err(|e| {
+                let idx = start + e.doc_idx();
+                e.relocate(idx)
+            })?;
+            // positions within the declaration are lost once references
+            // are replaced, so errors are reported at its start
+            let item = parse_int_subset_item(&decl, 0).map_err(|e| e.relocate(start))?;
+            if item.get_endpos() != decl.len() {
+                return Err(XmlError::new(XmlErrorKind::IllegalSubstr, start, "markupdecl"));
+            }
+            item
+        } else {
+            let item = parse_int_subset_item(text, start)?;
+            if item.get_endpos() != end {
+                return Err(XmlError::new(XmlErrorKind::IllegalSubstr, item.get_endpos(), "markupdecl"));
+            }
+            item
+        };
+        if let IntSubsetItem::EntityDecl(decl) = &item {
+            self.table.declare_from(decl, Some(base_uri));
+        }
+        Ok((item, end))
+    }
+
+    /// Replace the parameter entity references in markup declaration `decl`.
+    /// Outside literals the replacement text is padded with a space on each
+    /// side. Within literals references are only recognized in entity
+    /// values, where quotes in the replacement text do not end the literal.
+    fn substitute_decl(&mut self, decl: &str, base_uri: &str, recurdepth: usize) -> Result<String, XmlError> {
+        let entity_decl = decl.starts_with("<!ENTITY");
+        let mut buf = String::new();
+        let mut quote = None;
+        let mut here = 0;
+        while let Some(c) = decl.get(here..).and_then(|rest| rest.chars().next()) {
+            match c {
+                '"' | '\'' if quote.is_none() => quote = Some(c),
+                _ if quote == Some(c) => quote = None,
+                '%' if quote.is_none() || entity_decl => {
+                    // a `%` not starting a reference, as in `<!ENTITY % name`,
+                    // is kept as it is
+                    if let Ok(reference) = parse_pereference(decl, here) {
+                        let replacement = self
+                            .pe_text(&reference, quote.is_some(), base_uri, recurdepth)
+                            .map_err(|e| e.relocate(here).within(&format!("entity `%{}`", reference.0)))?;
+                        match quote {
+                            Some('"') => buf.push_str(&replacement.replace('"', "&#34;")),
+                            Some(_) => buf.push_str(&replacement.replace('\'', "&#39;")),
+                            None => {
+                                buf.push(' ');
+                                buf.push_str(&replacement);
+                                buf.push(' ');
+                            }
+                        };
+                        here += reference.textlen();
+                        continue;
+                    }
+                }
+                _ => (),
+            };
+            buf.push(c);
+            here += c.len_utf8();
+        }
+        Ok(buf)
+    }
+
+    /// The replacement text of the parameter entity named by `reference`,
+    /// with references within it replaced in turn
+    fn pe_text(
+        &mut self,
+        reference: &PEReference,
+        in_literal: bool,
+        base_uri: &str,
+        recurdepth: usize,
+    ) -> Result<String, XmlError> {
+        let name = format!("%{}", reference.0);
+        let (text, uri) = match self.table.get_parameter(reference.0.as_str()).cloned() {
+            Some(Entity::Internal(replacement)) => {
+                self.budget.open(&name, &replacement, recurdepth)?;
+                (replacement, String::from(base_uri))
+            }
+            Some(Entity::External {
+                public_id,
+                system_id,
+                base_uri: entity_base,
+                ..
+            }) => {
+                let loaded = load_external(self.budget, public_id.as_deref(), &system_id, entity_base.as_deref())?;
+                match loaded {
+                    Some((uri, replacement)) => {
+                        self.budget.open(&name, "", recurdepth)?;
+                        (replacement, uri)
+                    }
+                    None => {
+                        let kind = XmlErrorKind::UnresolvedEntity(system_id, "skipped by the resolver".into());
+                        return Err(XmlError::new(kind, 0, "PEReference"));
+                    }
+                }
+            }
+            None => return Err(XmlError::new(XmlErrorKind::UndeclaredEntity(name), 0, "PEReference")),
+        };
+        let mut buf = String::new();
+        let mut here = 0;
+        while let Some(c) = text.get(here..).and_then(|rest| rest.chars().next()) {
+            if c == '%'
+                && let Ok(inner) = parse_pereference(&text, here)
+            {
+                let replacement = self
+                    .pe_text(&inner, in_literal, &uri, recurdepth + 1)
+                    .map_err(|e| e.relocate(here).within(&format!("entity `%{}`", inner.0)))?;
+                if !in_literal {
+                    buf.push(' ');
+                }
+                buf.push_str(&replacement);
+                if !in_literal {
+                    buf.push(' ');
+                }
+                here += inner.textlen();
+                continue;
+            }
+            buf.push(c);
+            here += c.len_utf8();
+        }
+        self.budget.close();
+        Ok(buf)
+    }
+}
+
+/// Where the markup declaration starting at `start` ends, just past the `>`
+/// that is not inside a quoted literal
+fn markup_decl_end(text: &str, start: usize) -> Result<usize, XmlError> {
+    let mut quote = None;
+    for (offset, c) in rest(text, start).char_indices() {
+        match c {
+            '"' | '\'' if quote.is_none() => quote = Some(c),
+            _ if quote == Some(c) => quote = None,
+            '>' if quote.is_none() => return Ok(start + offset + 1),
+            _ => (),
+        };
+    }
+    Err(XmlError::new(XmlErrorKind::TextEnd, text.len(), "markupdecl"))
+}
+
+/// Skip any white space at `start`
+fn skip_ws(text: &str, start: usize) -> usize {
+    match parse_ws(text, start) {
+        Ok(ws) => ws.get_endpos(),
+        Err(_) => start,
+    }
+}
+
+/// Replace references to internal entities in the content and attribute
+/// values of the document with their parsed replacement text, and references
+/// to external parsed entities in content with the content loaded by the
+/// resolver
+pub(crate) fn expand_entities(doc: &mut Doc, budget: &mut Budget) -> Result<(), XmlError> {
+    let doctype = match doc.prolog.doctype_decl.as_mut() {
+        Some(doctype) => doctype,
+        None => return Ok(()),
+    };
+    let table = doctype.entities();
+    let mut expander = Expander {
+        table: &table,
+        budget,
+        depth: 0,
+    };
+    // defaults are expanded first, as they are copied into elements later
+    if let Some(subset) = doctype.int_subset.as_mut() {
+        expander.expand_defaults(&mut subset.items)?;
+    }
+    if let Some(ext_subset) = doctype.ext_subset.as_mut() {
+        expander.expand_defaults(&mut ext_subset.items)?;
+    }
+    expander.expand_elem(&mut doc.elem, 0)
+}
+
+struct Expander<'a, 'b> {
+    table: &'a EntityTable,
+    budget: &'a mut Budget<'b>,
+    /// how many elements enclose the content being expanded, so elements in
+    /// replacement text are held to the same nesting limit as the document
+    depth: usize,
+}
+
+impl Expander<'_, '_> {
+    fn expand_elem(&mut self, elem: &mut Elem, recurdepth: usize) -> Result<(), XmlError> {
+        let (attribs, content) = match elem {
+            Elem::Empty(empty) => (&mut empty.attribs, None),
+            Elem::Full(full) => (&mut full.start.attribs, full.content.as_mut()),
+        };
+        for attrib in attribs.iter_mut() {
+            self.expand_attvalue(&mut attrib.value, recurdepth)?;
+        }
+        if let Some(content) = content {
+            self.depth += 1;
+            self.expand_items(&mut content.items, recurdepth)?;
+            self.depth -= 1;
+        }
+        Ok(())
+    }
+
+    fn expand_items(&mut self, items: &mut [ContentItem], recurdepth: usize) -> Result<(), XmlError> {
+        for item in items.iter_mut() {
+            let expanded = match item {
+                ContentItem::Elem(elem) => {
+                    self.expand_elem(elem, recurdepth)?;
+                    None
+                }
+                ContentItem::Reference {
+                    start,
+                    reference: Reference::EntityRef(name),
+                } => match self.table.get(name.as_str()) {
+                    Some(Entity::Internal(text)) => {
+                        let items = self
+                            .expand_content(name, text, recurdepth)
+                            .map_err(|e| e.relocate(*start).within(&format!("entity `{}`", name)))?;
+                        Some(ContentItem::Entity {
+                            start: *start,
+                            name: name.clone(),
+                            items,
+                        })
+                    }
+                    Some(Entity::External {
+                        public_id,
+                        system_id,
+                        notation: None,
+                        base_uri,
+                    }) => self
+                        .expand_external(name, public_id.as_deref(), system_id, base_uri.as_deref(), recurdepth)
+                        .map_err(|e| e.relocate(*start).within(&format!("entity `{}`", name)))?
+                        .map(|items| ContentItem::Entity {
+                            start: *start,
+                            name: name.clone(),
+                            items,
+                        }),
+                    // WFC: Parsed Entity
+                    Some(Entity::External { notation: Some(_), .. }) => {
+                        let kind = XmlErrorKind::UnparsedEntityRef(name.0.clone());
+                        return Err(XmlError::new(kind, *start, "EntityRef"));
+                    }
+                    None => None,
+                },
+                _ => None,
+            };
+            if let Some(expanded) = expanded {
+                *item = expanded;
+            }
+        }
+        Ok(())
+    }
+
+    /// Parse the replacement text of entity `name` as content, expanding
+    /// any references within it
+    fn expand_content(&mut self, name: &Name, text: &str, recurdepth: usize) -> Result<Vec<ContentItem>, XmlError> {
+        self.budget.open(name.as_str(), text, recurdepth)?;
+        let content = parse_content(text, 0, self.depth, self.budget.options.max_depth)?;
+        let end = match content.items.last() {
+            Some(item) => item.get_endpos(),
+            None => 0,
+        };
+        // content stops early at an end tag without a matching start tag
+        if end < text.len() {
+            return Err(XmlError::new(XmlErrorKind::IllegalSubstr, end, "content"));
+        }
+        let mut items = content.items;
+        self.expand_items(&mut items, recurdepth + 1)?;
+        self.budget.close();
+        Ok(items)
+    }
+
+    /// Load external entity `name` with the resolver and parse it as
+    /// content, or return `None` if the resolver skips it
+    fn expand_external(
+        &mut self,
+        name: &Name,
+        public_id: Option<&str>,
+        system_id: &str,
+        base_uri: Option<&str>,
+        recurdepth: usize,
+    ) -> Result<Option<Vec<ContentItem>>, XmlError> {
+        match load_external(self.budget, public_id, system_id, base_uri)? {
+            Some((_, text)) => self.expand_content(name, &text, recurdepth).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    /// Expand references in the default values of attribute declarations
+    fn expand_defaults(&mut self, items: &mut [IntSubsetItem]) -> Result<(), XmlError> {
+        for item in items.iter_mut() {
+            match item {
+                IntSubsetItem::AttlistDecl(decl) => {
+                    for def in decl.att_defs.iter_mut() {
+                        if let DefaultDecl::Fixed(value) | DefaultDecl::Default(value) = &mut def.default_decl {
+                            self.expand_attvalue(value, 0)?;
+                        }
+                    }
+                }
+                IntSubsetItem::ParamEntity { items, .. } | IntSubsetItem::IncludeSect { items, .. } => {
+                    self.expand_defaults(items)?;
+                }
+                _ => (),
+            };
+        }
+        Ok(())
+    }
+
+    fn expand_attvalue(&mut self, value: &mut AttValue, recurdepth: usize) -> Result<(), XmlError> {
+        // skip the opening qoute
+        let mut pos = value.start + 1;
+        for item in value.items.iter_mut() {
+            let len = item.text_len();
+            if let AttValueItem::Reference(Reference::EntityRef(name)) = item {
+                match self.table.get(name.as_str()) {
+                    Some(Entity::Internal(text)) => {
+                        let expanded = self
+                            .expand_att_text(name, text, recurdepth)
+                            .map_err(|e| e.relocate(pos).within(&format!("entity `{}`", name)))?;
+                        *item = AttValueItem::Entity {
+                            name: name.clone(),
+                            text: expanded,
+                        };
+                    }
+                    // WFC: No External Entity References
+                    Some(Entity::External { .. }) => {
+                        let kind = XmlErrorKind::ExternalEntityInAttValue(name.0.clone());
+                        return Err(XmlError::new(kind, pos, "AttValue"));
+                    }
+                    None => (),
+                };
+            }
+            pos += len;
+        }
+        Ok(())
+    }
+
+    /// Expand the replacement text of entity `name` for use in an attribute
+    /// value, where it is character data rather than markup
+    fn expand_att_text(&mut self, name: &Name, text: &str, recurdepth: usize) -> Result<String, XmlError> {
+        self.budget.open(name.as_str(), text, recurdepth)?;
+        let mut buf = String::new();
+        let mut here = 0;
+        while let Some(c) = text.get(here..).and_then(|rest| rest.chars().next()) {
+            match c {
+                '<' => return Err(XmlError::new(XmlErrorKind::BadChar(c), here, "AttValue")),
+                '&' => {
+                    let reference = parse_reference(text, here)?;
+                    let entity = match &reference {
+                        Reference::EntityRef(name) => self.table.get(name.as_str()),
+                        Reference::CharRef(_) => None,
+                    };
+                    match (reference.decode(), entity, &reference) {
+                        (Some(decoded), _, _) => buf.push(decoded),
+                        (None, Some(Entity::Internal(inner)), Reference::EntityRef(inner_name)) => {
+                            let expanded = self
+                                .expand_att_text(inner_name, inner, recurdepth + 1)
+                                .map_err(|e| e.relocate(here).within(&format!("entity `{}`", inner_name)))?;
+                            buf.push_str(&expanded);
+                        }
+                        (None, Some(Entity::External { .. }), Reference::EntityRef(inner_name)) => {
+                            let kind = XmlErrorKind::ExternalEntityInAttValue(inner_name.0.clone());
+                            return Err(XmlError::new(kind, here, "AttValue"));
+                        }
+                        _ => buf.push_str(&reference.to_string()),
+                    };
+                    here += reference.text_len();
+                }
+                _ => {
+                    buf.push(c);
+                    here += c.len_utf8();
+                }
+            };
+        }
+        self.budget.close();
+        Ok(buf)
+    }
+}
@@ -1,7 +1,12 @@
 use std::fmt;
 use std::error;
+use std::io;
 
-#[derive(Debug)]
+use crate::encoding::TextEncoding;
+use crate::position::Position;
+use crate::XmlVersion;
+
+#[derive(Debug, Clone, PartialEq)]
 pub enum XmlErrorKind {
     /// Character disallowed in current context
     BadChar(char),
@@ -27,6 +32,178 @@ pub enum XmlErrorKind {
     BadXDeclStart,
     /// did not see a keyword when one was expected
     KeywordMatchFail,
+    /// input bytes are not valid UTF-8
+    InvalidUtf8,
+    /// character reference to a character not allowed in XML, holding the
+    /// text between `&#` and `;`
+    InvalidCharRef(String),
+    /// reference to an entity that is neither predefined nor declared
+    UndeclaredEntity(String),
+    /// entity whose replacement text refers to itself, directly or indirectly
+    RecursiveEntity(String),
+    /// expanding entities exceeded one of the limits set in `ParseOptions`
+    ExpansionLimit(ExpansionLimit),
+    /// external entity that the resolver failed to load, holding its system
+    /// identifier and the reason
+    UnresolvedEntity(String, Box<str>),
+    /// the document is well-formed but violates a validity constraint of its DTD
+    Invalid(ValidityError),
+    /// the document is well-formed XML but breaks a constraint of XML Namespaces
+    Namespace(NamespaceError),
+    /// reading the document from its source failed, holding the reason
+    Io(io::ErrorKind, Box<str>),
+    /// a single construct does not fit in the read buffer, holding its size
+    BufferLimit(usize),
+    /// the document is in, or declares, an encoding that cannot be read,
+    /// holding its name
+    UnsupportedEncoding(String),
+    /// the encoding declared differs from the one the first bytes of the
+    /// document are in, holding the declared name and the detected encoding
+    EncodingConflict(String, TextEncoding),
+    /// input bytes are not valid in the encoding the document is read in
+    InvalidEncoding(TextEncoding),
+    /// an attribute name given twice in the same tag, holding the name
+    DuplicateAttribute(String),
+    /// reference to an external entity in an attribute value, holding the
+    /// name of the entity
+    ExternalEntityInAttValue(String),
+    /// reference in content to an unparsed entity, holding its name
+    UnparsedEntityRef(String),
+}
+
+/// The entity expansion limit that was exceeded, holding its configured value
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ExpansionLimit {
+    /// entities nested within the replacement text of other entities
+    Depth(usize),
+    /// characters of replacement text expanded over the whole document
+    TotalChars(usize),
+    /// characters expanded per byte of the document
+    Ratio(usize),
+}
+
+impl fmt::Display for ExpansionLimit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self {
+            ExpansionLimit::Depth(n) => write!(f, "{} nested entities", n),
+            ExpansionLimit::TotalChars(n) => write!(f, "{} expanded characters", n),
+            ExpansionLimit::Ratio(n) => write!(f, "{} times the size of the document", n),
+        }
+    }
+}
+
+/// A validity constraint the document violates
+#[derive(Debug, Clone, PartialEq)]
+pub enum ValidityError {
+    /// there is no document type declaration to validate against
+    NoDoctype,
+    /// the root element, whose name is held, is not the one named by the
+    /// document type declaration
+    RootMismatch(String),
+    /// element type used without an `<!ELEMENT>` declaration
+    UndeclaredElement(String),
+    /// content of the named element does not match its declared content
+    /// model, which is also held
+    InvalidContent(String, Box<str>),
+    /// attribute specified without an `<!ATTLIST>` declaration
+    UndeclaredAttribute(String),
+    /// `#REQUIRED` attribute left out
+    MissingAttribute(String),
+    /// `#FIXED` attribute specified with a value other than the fixed one
+    FixedAttribute(String),
+    /// attribute value not allowed by the declared type
+    InvalidAttributeValue(String),
+    /// the same `ID` value given to more than one element
+    DuplicateId(String),
+    /// `IDREF` value that is not the `ID` of any element
+    UnknownIdRef(String),
+    /// element type declared by more than one `<!ELEMENT>` declaration
+    DuplicateElementDecl(String),
+    /// element type, whose name is held, with more than one attribute of
+    /// type `ID`
+    MultipleIdAttributes(String),
+    /// `ID` attribute declared with a default other than `#IMPLIED` or
+    /// `#REQUIRED`
+    IdAttributeDefault(String),
+}
+
+impl fmt::Display for ValidityError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self {
+            ValidityError::NoDoctype => write!(f, "document has no DTD to validate against"),
+            ValidityError::RootMismatch(name) => {
+                write!(f, "root element `{}` does not match the document type name", name)
+            }
+            ValidityError::UndeclaredElement(name) => write!(f, "element `{}` is not declared", name),
+            ValidityError::InvalidContent(name, model) => {
+                write!(f, "content of element `{}` does not match `{}`", name, model)
+            }
+            ValidityError::UndeclaredAttribute(name) => write!(f, "attribute `{}` is not declared", name),
+            ValidityError::MissingAttribute(name) => write!(f, "required attribute `{}` is missing", name),
+            ValidityError::FixedAttribute(name) => {
+                write!(f, "attribute `{}` differs from its #FIXED value", name)
+            }
+            ValidityError::InvalidAttributeValue(name) => {
+                write!(f, "value of attribute `{}` is not allowed by its declared type", name)
+            }
+            ValidityError::DuplicateId(id) => write!(f, "ID `{}` is used more than once", id),
+            ValidityError::UnknownIdRef(id) => write!(f, "no element has the ID `{}`", id),
+            ValidityError::DuplicateElementDecl(name) => {
+                write!(f, "element type `{}` is declared more than once", name)
+            }
+            ValidityError::MultipleIdAttributes(name) => {
+                write!(f, "element type `{}` has more than one ID attribute", name)
+            }
+            ValidityError::IdAttributeDefault(name) => {
+                write!(f, "ID attribute `{}` must be #IMPLIED or #REQUIRED", name)
+            }
+        }
+    }
+}
+
+/// A constraint of XML Namespaces 1.0 the document breaks
+#[derive(Debug, Clone, PartialEq)]
+pub enum NamespaceError {
+    /// element or attribute name with an empty prefix or local part, or
+    /// more than one colon
+    BadQName(String),
+    /// prefix used without a namespace declaration in scope
+    UndeclaredPrefix(String),
+    /// two attributes of an element with the same local part and namespace,
+    /// holding the expanded name
+    DuplicateAttribute(String),
+    /// the `xml` or `xmlns` prefix declared, or used on an element, other
+    /// than as the specification allows
+    ReservedPrefix(String),
+    /// a prefix or the default namespace bound to the `xml` or `xmlns`
+    /// namespace name
+    ReservedNamespace(String),
+    /// prefix declared with an empty namespace name, which only XML
+    /// Namespaces 1.1 allows
+    EmptyNamespace(String),
+}
+
+impl fmt::Display for NamespaceError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self {
+            NamespaceError::BadQName(name) => write!(f, "`{}` is not a valid qualified name", name),
+            NamespaceError::UndeclaredPrefix(prefix) => {
+                write!(f, "namespace prefix `{}` is not declared", prefix)
+            }
+            NamespaceError::DuplicateAttribute(name) => {
+                write!(f, "attribute `{}` is given more than once", name)
+            }
+            NamespaceError::ReservedPrefix(prefix) => {
+                write!(f, "the reserved prefix `{}` cannot be used here", prefix)
+            }
+            NamespaceError::ReservedNamespace(uri) => {
+                write!(f, "the reserved namespace `{}` cannot be bound here", uri)
+            }
+            NamespaceError::EmptyNamespace(prefix) => {
+                write!(f, "namespace prefix `{}` cannot be undeclared", prefix)
+            }
+        }
+    }
 }
 
 #[derive(Debug)]
@@ -35,10 +212,15 @@ pub struct XmlError {
     category: XmlErrorKind,
     /// index in document where error is encountered
     doc_idx: usize,
+    /// line and column of `doc_idx`, once the error has been located in its source
+    line_col: Option<(usize, usize)>,
+    /// index of another location relevant to the error, such as the opening
+    /// tag of an element whose closing tag does not match
+    related_idx: Option<usize>,
     /// if there is a different issue causing this one, it gets reported here
     underlying: Option<Box<XmlError>>,
     /// report context name if doing so is potentially useful
-    context: String,
+    context: Box<str>,
 }
 
 impl fmt::Display for XmlErrorKind {
@@ -76,29 +258,192 @@ impl fmt::Display for XmlErrorKind {
                 f,
                 "failed when trying to match keyword, check spelling and capitalization"
             ),
+            XmlErrorKind::InvalidUtf8 => write!(f, "input is not valid UTF-8"),
+            XmlErrorKind::InvalidCharRef(s) => {
+                write!(f, "`&#{};` does not refer to a legal XML character", s)
+            }
+            XmlErrorKind::UndeclaredEntity(name) => {
+                write!(f, "entity `{}` is referenced but not declared", name)
+            }
+            XmlErrorKind::RecursiveEntity(name) => {
+                write!(f, "entity `{}` refers to itself", name)
+            }
+            XmlErrorKind::ExpansionLimit(limit) => {
+                write!(f, "entity expansion exceeds the limit of {}", limit)
+            }
+            XmlErrorKind::UnresolvedEntity(system_id, reason) => {
+                write!(f, "could not load external entity `{}`: {}", system_id, reason)
+            }
+            XmlErrorKind::Invalid(error) => write!(f, "{}", error),
+            XmlErrorKind::Namespace(error) => write!(f, "{}", error),
+            XmlErrorKind::Io(_, reason) => write!(f, "could not read the document: {}", reason),
+            XmlErrorKind::BufferLimit(size) => {
+                write!(f, "construct does not fit in the read buffer of {} bytes", size)
+            }
+            XmlErrorKind::UnsupportedEncoding(name) => write!(f, "encoding `{}` is not supported", name),
+            XmlErrorKind::EncodingConflict(name, detected) => write!(
+                f,
+                "encoding `{}` is declared but the document begins in {}",
+                name, detected
+            ),
+            XmlErrorKind::InvalidEncoding(encoding) => write!(f, "input is not valid {}", encoding),
+            XmlErrorKind::DuplicateAttribute(name) => {
+                write!(f, "attribute `{}` is given more than once", name)
+            }
+            XmlErrorKind::ExternalEntityInAttValue(name) => {
+                write!(f, "attribute values cannot refer to external entity `{}`", name)
+            }
+            XmlErrorKind::UnparsedEntityRef(name) => {
+                write!(f, "unparsed entity `{}` cannot be referenced in content", name)
+            }
+        }
+    }
+}
+
+impl XmlError {
+    /// Create an error of the given kind at byte offset `doc_idx`,
+    /// encountered while parsing the grammar production named by `context`
+    pub fn new(category: XmlErrorKind, doc_idx: usize, context: &str) -> XmlError {
+        XmlError {
+            category,
+            doc_idx,
+            line_col: None,
+            related_idx: None,
+            underlying: None,
+            context: Box::from(context),
         }
     }
+
+    /// Record the error that caused this one
+    pub fn caused_by(mut self, cause: XmlError) -> XmlError {
+        self.underlying = Some(Box::new(cause));
+        self
+    }
+
+    /// Point at another location relevant to the error
+    pub fn with_related(mut self, related_idx: usize) -> XmlError {
+        self.related_idx = Some(related_idx);
+        self
+    }
+
+    /// Note that the production this error occurred in was itself being
+    /// parsed as part of `outer`
+    pub fn within(mut self, outer: &str) -> XmlError {
+        if self.context.is_empty() {
+            self.context = Box::from(outer);
+        } else {
+            self.context = format!("{} in {}", self.context, outer).into_boxed_str();
+        }
+        self
+    }
+
+    /// The kind of error encountered
+    pub fn kind(&self) -> &XmlErrorKind {
+        &self.category
+    }
+
+    /// Byte offset into the document where the error was encountered
+    pub fn doc_idx(&self) -> usize {
+        self.doc_idx
+    }
+
+    /// Line and column of the error, available once it has been located
+    /// in the source text. Errors returned by `parse_str` are always located.
+    pub fn position(&self) -> Option<Position> {
+        self.line_col.map(|(line, column)| Position {
+            offset: self.doc_idx,
+            line,
+            column,
+        })
+    }
+
+    /// Compute the line and column of this error, and of any underlying
+    /// errors, from the source text it was encountered in
+    pub fn locate(mut self, text: &str) -> XmlError {
+        let pos = Position::locate(text, self.doc_idx);
+        self.line_col = Some((pos.line, pos.column));
+        self.underlying = self.underlying.map(|cause| Box::new(cause.locate(text)));
+        self
+    }
+
+    /// Compute the line and column of this error, and of any underlying
+    /// errors, from a part of the source text beginning at `base`, in a
+    /// document written in XML `version`
+    pub(crate) fn locate_from(mut self, base: Position, text: &str, version: XmlVersion) -> XmlError {
+        let pos = Position::locate_from(base, text, self.doc_idx, version);
+        self.line_col = Some((pos.line, pos.column));
+        self.underlying = self.underlying.map(|cause| Box::new(cause.locate_from(base, text, version)));
+        self
+    }
+
+    /// Add `by` to the offsets of this error and any underlying errors, for
+    /// errors found in a part of the source text beginning at offset `by`
+    pub(crate) fn shift(mut self, by: usize) -> XmlError {
+        self.doc_idx += by;
+        self.line_col = None;
+        self.related_idx = self.related_idx.map(|idx| idx + by);
+        self.underlying = self.underlying.map(|cause| Box::new(cause.shift(by)));
+        self
+    }
+
+    /// Move this error, and any underlying errors, to byte offset `doc_idx`.
+    /// Errors found in text that is not part of the document, such as the
+    /// replacement text of an entity, are reported where that text is used.
+    pub(crate) fn relocate(mut self, doc_idx: usize) -> XmlError {
+        self.doc_idx = doc_idx;
+        self.line_col = None;
+        self.related_idx = self.related_idx.map(|_| doc_idx);
+        self.underlying = self.underlying.map(|cause| Box::new(cause.relocate(doc_idx)));
+        self
+    }
+
+    /// Index of another location relevant to the error, if any
+    pub fn related_idx(&self) -> Option<usize> {
+        self.related_idx
+    }
+
+    /// The error that caused this one, if any
+    pub fn underlying(&self) -> Option<&XmlError> {
+        self.underlying.as_deref()
+    }
+
+    /// The grammar production(s) being parsed when the error occurred
+    pub fn context(&self) -> &str {
+        &self.context
+    }
 }
 
 impl fmt::Display for XmlError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.position() {
+            Some(pos) => write!(f, "XMLError at {} (index {}): {}", pos, self.doc_idx, self.category)?,
+            None => write!(f, "XMLError at index {}: {}", self.doc_idx, self.category)?,
+        };
         match &self.underlying {
-            Some(cause) => write!(f, "XMLError at index {}: {}. Caused by {}. Additional context: {}", 
-                self.doc_idx, self.category, cause, self.context),
-            None => write!(f, "XMLError at index {}: {}. Additional context: {}",
-                self.doc_idx, self.category, self.context),
+            Some(cause) => write!(f, ". Caused by {}. Additional context: {}", cause, self.context),
+            None => write!(f, ". Additional context: {}", self.context),
         }
     }
 }
 
+impl error::Error for XmlError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match &self.underlying {
+            Some(cause) => Some(cause.as_ref()),
+            None => None,
+        }
+    }
+}
 
 impl From<XmlErrorKind> for XmlError {
     fn from(value: XmlErrorKind) -> Self {
         XmlError {
             category : value,
             doc_idx : 0,
+            line_col : None,
+            related_idx : None,
             underlying : None,
-            context : String::new(),
+            context : Box::from(""),
         }
     }
 }
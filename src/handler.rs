@@ -0,0 +1,143 @@
+use std::error;
+use std::fmt;
+use std::io::Read;
+
+use crate::error::XmlError;
+use crate::reader::{Event, Reader, StreamReader, DEFAULT_BUFFER_SIZE};
+use crate::{Attribute, DoctypeDecl, ParseOptions, XmlDecl};
+
+/// Receives the content of a document as it is parsed, in document order.
+///
+/// Every method does nothing by default. Returning an error from any of
+/// them stops parsing, and the error is passed back to the caller as
+/// `HandlerError::Handler`.
+pub trait ContentHandler {
+    type Error;
+
+    /// Called first, with the XML declaration if the document has one
+    fn start_document(&mut self, _decl: Option<&XmlDecl>) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+    /// Called once the whole document has been parsed
+    fn end_document(&mut self) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+    fn doctype(&mut self, _doctype: &DoctypeDecl) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+    /// Called for each start tag and empty-element tag, with the attributes
+    /// given in it
+    fn start_element(&mut self, _name: &str, _attrs: &[Attribute]) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+    /// Called for each end tag, and after `start_element` for empty-element tags
+    fn end_element(&mut self, _name: &str) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+    /// Called with character data, with references to characters and
+    /// predefined entities decoded. A run of text may be passed in pieces.
+    fn characters(&mut self, _text: &str) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+    /// Called with the content of each CDATA section
+    fn cdata(&mut self, _text: &str) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+    fn comment(&mut self, _text: &str) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+    fn processing_instruction(&mut self, _target: &str, _data: Option<&str>) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+    /// Called for references to general entities other than the predefined
+    /// ones, which are not expanded
+    fn skipped_entity(&mut self, _name: &str) -> Result<(), Self::Error> {
+        Ok(())
+    }
+}
+
+/// Why parsing with a `ContentHandler` stopped early
+#[derive(Debug)]
+pub enum HandlerError<E> {
+    /// the document is not well-formed
+    Xml(XmlError),
+    /// the handler returned an error
+    Handler(E),
+}
+
+impl<E: fmt::Display> fmt::Display for HandlerError<E> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self {
+            HandlerError::Xml(e) => write!(f, "{}", e),
+            HandlerError::Handler(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl<E: error::Error + 'static> error::Error for HandlerError<E> {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match &self {
+            HandlerError::Xml(e) => Some(e),
+            HandlerError::Handler(e) => Some(e),
+        }
+    }
+}
+
+/// Parse `text`, passing its content to `handler` as it is read rather than
+/// building a tree. The document is read as `Reader` reads it.
+pub fn parse_str_with_handler<H: ContentHandler>(
+    text: &str,
+    options: &ParseOptions,
+    handler: &mut H,
+) -> Result<(), HandlerError<H::Error>> {
+    drive(Reader::with_options(text, options), handler)
+}
+
+/// Parse a document from `source`, passing its content to `handler`, with a
+/// bounded buffer as `StreamReader` does
+pub fn parse_read_with_handler<R: Read, H: ContentHandler>(
+    source: R,
+    options: &ParseOptions,
+    handler: &mut H,
+) -> Result<(), HandlerError<H::Error>> {
+    drive(StreamReader::with_options(source, options, DEFAULT_BUFFER_SIZE), handler)
+}
+
+fn drive<H: ContentHandler>(
+    mut events: impl Iterator<Item = Result<Event, XmlError>>,
+    handler: &mut H,
+) -> Result<(), HandlerError<H::Error>> {
+    let mut next = events.next().transpose().map_err(HandlerError::Xml)?;
+    match &next {
+        Some(Event::XmlDecl(decl)) => {
+            handler.start_document(Some(decl)).map_err(HandlerError::Handler)?;
+            next = events.next().transpose().map_err(HandlerError::Xml)?;
+        }
+        _ => handler.start_document(None).map_err(HandlerError::Handler)?,
+    };
+    while let Some(event) = next {
+        let handled = match &event {
+            Event::XmlDecl(_) => Ok(()),
+            Event::DocType(doctype) => handler.doctype(doctype),
+            Event::StartElement(start) => handler.start_element(start.name(), start.attributes()),
+            Event::EndElement(end) => handler.end_element(end.name()),
+            Event::Text(text) => handler.characters(text),
+            Event::CData(text) => handler.cdata(text),
+            Event::Comment(comment) => handler.comment(comment.text()),
+            Event::PI(pi) => handler.processing_instruction(pi.target(), pi.data()),
+            Event::EntityRef(name) => handler.skipped_entity(name),
+        };
+        handled.map_err(HandlerError::Handler)?;
+        next = events.next().transpose().map_err(HandlerError::Xml)?;
+    }
+    handler.end_document().map_err(HandlerError::Handler)
+}
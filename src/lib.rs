@@ -1,49 +1,72 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Arc;
+
+pub mod catalog;
+pub mod diagnostic;
+pub mod encoding;
+pub mod entity;
 pub mod error;
+pub mod handler;
+pub mod namespace;
+pub mod position;
+pub mod reader;
+pub mod resolver;
+pub mod validate;
+
+pub use catalog::{Catalog, CatalogError};
+pub use diagnostic::Diagnostic;
+pub use encoding::TextEncoding;
+pub use entity::{Entity, EntityTable};
+use entity::DeclaredEntities;
+pub use error::{ExpansionLimit, NamespaceError, ValidityError, XmlError, XmlErrorKind};
+pub use handler::{parse_read_with_handler, parse_str_with_handler, ContentHandler, HandlerError};
+pub use namespace::{XMLNS_NAMESPACE, XML_NAMESPACE};
+pub use position::Position;
+pub use reader::{EndElement, Event, Reader, StartElement, StreamReader};
+pub use resolver::{DenyResolver, EntityResolver, FsResolver, ResolvedEntity};
+pub use validate::validate;
 
 #[cfg(test)]
 mod test;
 
-#[derive(Debug, PartialEq)]
-pub enum XmlError {
-    /// character not allowed in current parsing context
-    BadChar(char),
-    /// recursion depth max exceeded
-    MaxRecurDepth(u32),
-    /// text ends before parsing complete
-    TextEnd,
-    /// available text does not match any variant of the parsing rule
-    NoValidVariant,
-    /// illegal substring encountered
-    IllegalSubstr,
-    /// use of name xml which is reserved
-    ReservedNameXml,
-    /// mismatch between opening and closing tags
-    MismatchedTags(String, String),
-    /// did not see opening <![CDATA[ tag while attempting to parse CDSect
-    BadCDATAStart,
-    /// No available data when trying to parse for character data
-    /// Need to make this an error because the rest of the parser doesn't expect
-    /// zero-length elements
-    NoData,
-    /// did not see opening <?xml when attempting to parse XmlDecl
-    BadXDeclStart,
-    /// did not see a keyword when one was expected
-    KeywordMatchFail,
-}
-
-trait Ends {
+pub trait Ends {
+    /// Return the index of the first character of the node
+    fn get_startpos(&self) -> usize;
+
     /// Return the index of the first character that is not part of the node
     /// that occurs after the node
     fn get_endpos(&self) -> usize;
+
+    /// Return the line and column in `text` where the node begins
+    fn start_position(&self, text: &str) -> Position {
+        Position::locate(text, self.get_startpos())
+    }
+
+    /// Return the line and column in `text` just past the end of the node
+    fn end_position(&self, text: &str) -> Position {
+        Position::locate(text, self.get_endpos())
+    }
 }
 
 impl Ends for Prolog {
+    fn get_startpos(&self) -> usize {
+        self.start
+    }
+
     fn get_endpos(&self) -> usize {
-        0usize
+        self.end
     }
 }
 
 impl Ends for Elem {
+    fn get_startpos(&self) -> usize {
+        match &self {
+            Elem::Empty(empty) => empty.get_startpos(),
+            Elem::Full(full) => full.get_startpos(),
+        }
+    }
+
     fn get_endpos(&self) -> usize {
         match &self {
             Elem::Empty(empty) => empty.get_endpos(),
@@ -53,12 +76,20 @@ impl Ends for Elem {
 }
 
 impl Ends for FullElem {
+    fn get_startpos(&self) -> usize {
+        self.start.get_startpos()
+    }
+
     fn get_endpos(&self) -> usize {
         self.end.get_endpos()
     }
 }
 
 impl Ends for Content {
+    fn get_startpos(&self) -> usize {
+        self.start
+    }
+
     fn get_endpos(&self) -> usize {
         match self.items.last() {
             Some(item) => item.get_endpos(),
@@ -68,28 +99,49 @@ impl Ends for Content {
 }
 
 impl Ends for Misc {
+    fn get_startpos(&self) -> usize {
+        match &self {
+            Misc::Ws(ws) => ws.get_startpos(),
+            Misc::Comment(comment) => comment.get_startpos(),
+            Misc::ProcInstr(pi) => pi.get_startpos(),
+        }
+    }
+
     fn get_endpos(&self) -> usize {
         match &self {
-            &Misc::Ws(ws) => ws.get_endpos(),
-            &Misc::Comment(comment) => comment.get_endpos(),
-            &Misc::ProcInstr(pi) => pi.get_endpos(),
+            Misc::Ws(ws) => ws.get_endpos(),
+            Misc::Comment(comment) => comment.get_endpos(),
+            Misc::ProcInstr(pi) => pi.get_endpos(),
         }
     }
 }
 
 impl Ends for Ws {
+    fn get_startpos(&self) -> usize {
+        self.start
+    }
+
     fn get_endpos(&self) -> usize {
         self.start + self.text.len()
     }
 }
 
 impl Ends for Comment {
+    fn get_startpos(&self) -> usize {
+        self.start
+    }
+
     fn get_endpos(&self) -> usize {
         self.start + self.text.len() + "<!--".len() + "-->".len()
     }
 }
 
 impl Ends for ProcInstr {
+    fn get_startpos(&self) -> usize {
+        self.start
+    }
+
+    #[allow(clippy::single_match)]
     fn get_endpos(&self) -> usize {
         let mut endpos = self.start + self.target.name.0.len() + 4;
         match &self.space {
@@ -109,12 +161,20 @@ impl Ends for ProcInstr {
 }
 
 impl Ends for Attribute {
+    fn get_startpos(&self) -> usize {
+        self.start
+    }
+
     fn get_endpos(&self) -> usize {
         self.end
     }
 }
 
 impl Ends for AttValue {
+    fn get_startpos(&self) -> usize {
+        self.start
+    }
+
     fn get_endpos(&self) -> usize {
         let mut pos = self.start;
         for item in &self.items {
@@ -125,28 +185,53 @@ impl Ends for AttValue {
 }
 
 impl Ends for EmptyElem {
+    fn get_startpos(&self) -> usize {
+        self.start
+    }
+
     fn get_endpos(&self) -> usize {
         self.end
     }
 }
 
 impl Ends for STag {
+    fn get_startpos(&self) -> usize {
+        self.start
+    }
+
     fn get_endpos(&self) -> usize {
         self.end
     }
 }
 
 impl Ends for ETag {
+    fn get_startpos(&self) -> usize {
+        self.start
+    }
+
     fn get_endpos(&self) -> usize {
         self.end
     }
 }
 
 impl Ends for ContentItem {
+    fn get_startpos(&self) -> usize {
+        match &self {
+            ContentItem::Elem(elem) => elem.get_startpos(),
+            ContentItem::Reference { start, .. } => *start,
+            ContentItem::Entity { start, .. } => *start,
+            ContentItem::ProcInstr(pi) => pi.get_startpos(),
+            ContentItem::Comment(comment) => comment.get_startpos(),
+            ContentItem::CharData(chardata) => chardata.get_startpos(),
+            ContentItem::CDSect(cdsect) => cdsect.get_startpos(),
+        }
+    }
+
     fn get_endpos(&self) -> usize {
         match &self {
             ContentItem::Elem(elem) => elem.get_endpos(),
             ContentItem::Reference { start, reference } => start + reference.text_len(),
+            ContentItem::Entity { start, name, .. } => start + name.0.len() + 2,
             ContentItem::ProcInstr(pi) => pi.get_endpos(),
             ContentItem::Comment(comment) => comment.get_endpos(),
             ContentItem::CharData(chardata) => chardata.get_endpos(),
@@ -156,84 +241,153 @@ impl Ends for ContentItem {
 }
 
 impl Ends for CharData {
+    fn get_startpos(&self) -> usize {
+        self.start
+    }
+
     fn get_endpos(&self) -> usize {
         self.start + self.text.len()
     }
 }
 
 impl Ends for CDSect {
+    fn get_startpos(&self) -> usize {
+        self.start
+    }
+
     fn get_endpos(&self) -> usize {
         self.start + "<![CDATA[".len() + self.text.len() + "]]>".len()
     }
 }
 
 impl Ends for XmlDecl {
+    fn get_startpos(&self) -> usize {
+        self.start
+    }
+
     fn get_endpos(&self) -> usize {
         self.end
     }
 }
 
 impl Ends for VersionInfo {
+    fn get_startpos(&self) -> usize {
+        self.start
+    }
+
     fn get_endpos(&self) -> usize {
         self.end
     }
 }
 
 impl Ends for Encoding {
+    fn get_startpos(&self) -> usize {
+        self.start
+    }
+
     fn get_endpos(&self) -> usize {
         self.end
     }
 }
 
 impl Ends for SDDecl {
+    fn get_startpos(&self) -> usize {
+        self.start
+    }
+
     fn get_endpos(&self) -> usize {
         self.end
     }
 }
 
 impl Ends for ExternalID {
+    fn get_startpos(&self) -> usize {
+        match &self {
+            ExternalID::System { start, .. } => *start,
+            ExternalID::Public { start, .. } => *start,
+        }
+    }
+
     fn get_endpos(&self) -> usize {
         match &self {
-            ExternalID::System {
-                start,
-                end,
-                sys_lit,
-            } => *end,
-            ExternalID::Public {
-                start,
-                end,
-                pub_lit,
-                sys_lit,
-            } => *end,
+            ExternalID::System { end, .. } => *end,
+            ExternalID::Public { end, .. } => *end,
         }
     }
 }
 
 impl Ends for IntSubsetItem {
+    fn get_startpos(&self) -> usize {
+        match &self {
+            IntSubsetItem::Blank(ws) => ws.get_startpos(),
+            IntSubsetItem::PEReference { start, .. } => *start,
+            IntSubsetItem::ParamEntity { start, .. } => *start,
+            IntSubsetItem::IncludeSect { start, .. } => *start,
+            IntSubsetItem::IgnoreSect { start, .. } => *start,
+            IntSubsetItem::ElemDecl(elemdecl) => elemdecl.get_startpos(),
+            IntSubsetItem::AttlistDecl(attlist) => attlist.get_startpos(),
+            IntSubsetItem::EntityDecl(entity) => entity.get_startpos(),
+            IntSubsetItem::NotationDecl(notation) => notation.get_startpos(),
+            IntSubsetItem::ProcInstr(pi) => pi.get_startpos(),
+            IntSubsetItem::Comment(comment) => comment.get_startpos(),
+        }
+    }
+
     fn get_endpos(&self) -> usize {
-        unimplemented!();
+        match &self {
+            IntSubsetItem::Blank(ws) => ws.get_endpos(),
+            IntSubsetItem::PEReference { start, reference } => start + reference.textlen(),
+            IntSubsetItem::ParamEntity { start, reference, .. } => start + reference.textlen(),
+            IntSubsetItem::IncludeSect { end, .. } => *end,
+            IntSubsetItem::IgnoreSect { end, .. } => *end,
+            IntSubsetItem::ElemDecl(elemdecl) => elemdecl.get_endpos(),
+            IntSubsetItem::AttlistDecl(attlist) => attlist.get_endpos(),
+            IntSubsetItem::EntityDecl(entity) => entity.get_endpos(),
+            IntSubsetItem::NotationDecl(notation) => notation.get_endpos(),
+            IntSubsetItem::ProcInstr(pi) => pi.get_endpos(),
+            IntSubsetItem::Comment(comment) => comment.get_endpos(),
+        }
     }
 }
 
 impl Ends for IntSubset {
+    fn get_startpos(&self) -> usize {
+        self.start
+    }
+
     fn get_endpos(&self) -> usize {
-        unimplemented!();
+        self.end
     }
 }
 
 impl Ends for DoctypeDecl {
+    fn get_startpos(&self) -> usize {
+        self.start
+    }
+
     fn get_endpos(&self) -> usize {
         self.end
     }
 }
 
 impl Ends for PublicID {
+    fn get_startpos(&self) -> usize {
+        self.start
+    }
+
     fn get_endpos(&self) -> usize {
         self.end
     }
 }
 
 impl Ends for NotationID {
+    fn get_startpos(&self) -> usize {
+        match &self {
+            NotationID::External(extid) => extid.get_startpos(),
+            NotationID::Public(pubid) => pubid.get_startpos(),
+        }
+    }
+
     fn get_endpos(&self) -> usize {
         match &self {
             NotationID::External(extid) => extid.get_endpos(),
@@ -243,24 +397,43 @@ impl Ends for NotationID {
 }
 
 impl Ends for NotationDecl {
+    fn get_startpos(&self) -> usize {
+        self.start
+    }
+
     fn get_endpos(&self) -> usize {
         self.end
     }
 }
 
 impl Ends for NDataDecl {
+    fn get_startpos(&self) -> usize {
+        self.start
+    }
+
     fn get_endpos(&self) -> usize {
         self.end
     }
 }
 
 impl Ends for EntityValue {
+    fn get_startpos(&self) -> usize {
+        self.start
+    }
+
     fn get_endpos(&self) -> usize {
         self.start + self.text.len() + 2 // take qoute chars into account
     }
 }
 
 impl Ends for PEDef {
+    fn get_startpos(&self) -> usize {
+        match &self {
+            PEDef::EntityValue(value) => value.get_startpos(),
+            PEDef::ExternalID(ext_id) => ext_id.get_startpos(),
+        }
+    }
+
     fn get_endpos(&self) -> usize {
         match &self {
             PEDef::EntityValue(value) => value.get_endpos(),
@@ -270,6 +443,13 @@ impl Ends for PEDef {
 }
 
 impl Ends for EntityDef {
+    fn get_startpos(&self) -> usize {
+        match &self {
+            EntityDef::EntityValue(eval) => eval.get_startpos(),
+            EntityDef::External { ext_id, .. } => ext_id.get_startpos(),
+        }
+    }
+
     fn get_endpos(&self) -> usize {
         match &self {
             EntityDef::EntityValue(eval) => eval.get_endpos(),
@@ -284,18 +464,83 @@ impl Ends for EntityDef {
 }
 
 impl Ends for GEDecl {
+    fn get_startpos(&self) -> usize {
+        self.start
+    }
+
     fn get_endpos(&self) -> usize {
         self.end
     }
 }
 
 impl Ends for PEDecl {
+    fn get_startpos(&self) -> usize {
+        self.start
+    }
+
+    fn get_endpos(&self) -> usize {
+        self.end
+    }
+}
+
+impl Ends for ElemDecl {
+    fn get_startpos(&self) -> usize {
+        self.start
+    }
+
+    fn get_endpos(&self) -> usize {
+        self.end
+    }
+}
+
+impl Ends for AttlistDecl {
+    fn get_startpos(&self) -> usize {
+        self.start
+    }
+
+    fn get_endpos(&self) -> usize {
+        self.end
+    }
+}
+
+impl Ends for AttDef {
+    fn get_startpos(&self) -> usize {
+        self.start
+    }
+
+    fn get_endpos(&self) -> usize {
+        self.end
+    }
+}
+
+impl Ends for Mixed {
+    fn get_startpos(&self) -> usize {
+        self.start
+    }
+
+    fn get_endpos(&self) -> usize {
+        self.end
+    }
+}
+
+impl Ends for ContentParticle {
+    fn get_startpos(&self) -> usize {
+        self.start
+    }
+
     fn get_endpos(&self) -> usize {
         self.end
     }
 }
 
 impl Ends for EntityDecl {
+    fn get_startpos(&self) -> usize {
+        match &self {
+            EntityDecl::GEDecl(gedecl) => gedecl.get_startpos(),
+            EntityDecl::PEDecl(pedecl) => pedecl.get_startpos(),
+        }
+    }
+
     fn get_endpos(&self) -> usize {
         match &self {
             EntityDecl::GEDecl(gedecl) => gedecl.get_endpos(),
@@ -311,32 +556,457 @@ pub struct Doc {
     pub tail: Vec<Misc>,
 }
 
-pub fn parse_doc(text: &[char]) -> Result<Doc, XmlError> {
+impl Doc {
+    /// The root element of the document
+    pub fn root(&self) -> &Elem {
+        &self.elem
+    }
+
+    /// Everything before the root element
+    pub fn prolog(&self) -> &Prolog {
+        &self.prolog
+    }
+
+    /// Comments, processing instructions and whitespace after the root element
+    pub fn tail(&self) -> &[Misc] {
+        &self.tail
+    }
+
+    /// The version of XML the document is written in
+    pub fn version(&self) -> XmlVersion {
+        self.prolog.xml_decl.as_ref().map_or(XmlVersion::V1_0, XmlDecl::xml_version)
+    }
+}
+
+/// Parse a complete document from UTF-8 text.
+///
+/// All `start`/`end` positions recorded in the resulting tree are byte
+/// offsets into `text`, so `&text[start..end]` yields the source of a node.
+///
+/// Any input, however malformed, produces either a document or an error;
+/// the parser does not panic. Elements may nest at most 256 deep, the
+/// default `ParseOptions::max_depth`. Namespaces are not processed unless
+/// `ParseOptions::namespaces` is set.
+pub fn parse_str(text: &str) -> Result<Doc, XmlError> {
+    parse_str_with(text, &ParseOptions::default())
+}
+
+/// Options controlling how a document is parsed
+#[derive(Debug, Clone)]
+pub struct ParseOptions {
+    /// Replace references to internal general entities in content and
+    /// attribute values with their parsed replacement text. Off by default,
+    /// leaving such references as `ContentItem::Reference` nodes.
+    pub expand_entities: bool,
+    /// How deeply elements may nest, counting the root element as the first
+    /// level. Nested elements are parsed recursively, so this keeps hostile
+    /// input from overflowing the stack. The default of 256 is the same as
+    /// libxml2 and needs about 2 MiB of stack in a debug build; raise it
+    /// only on threads with a larger stack.
+    pub max_depth: usize,
+    /// How deeply entity references may nest within replacement text
+    pub max_entity_depth: usize,
+    /// How many characters of replacement text may be expanded in total,
+    /// counting every expansion of nested entities
+    pub max_expanded_chars: usize,
+    /// How many characters may be expanded for each byte of the document.
+    /// Together with `max_expanded_chars` this stops small documents with
+    /// exponentially nested entities, the "billion laughs" attack.
+    pub max_expansion_ratio: usize,
+    /// Loads external entities when they are expanded. The default
+    /// `DenyResolver` loads nothing, so a document cannot make the parser
+    /// read files or fetch URLs unless a resolver is set here.
+    pub resolver: Arc<dyn EntityResolver>,
+    /// The URI of the document, against which relative system identifiers
+    /// are resolved
+    pub base_uri: Option<String>,
+    /// Load the external DTD subset named by the document type declaration,
+    /// and external parameter entities referenced in the DTD, with the
+    /// resolver. Declarations in the internal subset take precedence over
+    /// those in the external subset. Off by default.
+    pub load_external_dtd: bool,
+    /// Process the document as XML Namespaces 1.0 requires: element and
+    /// attribute names must be qualified names, their prefixes are resolved
+    /// to namespace names, and namespace constraints are errors. Off by
+    /// default, so that any Name is accepted and no namespace names are
+    /// known.
+    pub namespaces: bool,
+}
+
+impl Default for ParseOptions {
+    fn default() -> ParseOptions {
+        ParseOptions {
+            expand_entities: false,
+            max_depth: DEFAULT_MAX_DEPTH,
+            max_entity_depth: 16,
+            max_expanded_chars: 10_000_000,
+            max_expansion_ratio: 100,
+            resolver: Arc::new(DenyResolver),
+            base_uri: None,
+            load_external_dtd: false,
+            namespaces: false,
+        }
+    }
+}
+
+/// Parse a complete document from UTF-8 text, as `parse_str` does, with
+/// the given options
+pub fn parse_str_with(text: &str, options: &ParseOptions) -> Result<Doc, XmlError> {
+    parse_document(text, options).map_err(|e| e.locate(text))
+}
+
+/// Parse a document and check it against its DTD. Entities are always
+/// expanded, since their content must be validated too; the external subset
+/// is only read when `load_external_dtd` is set.
+///
+/// A well-formedness error stops parsing and is returned as the error, while
+/// validity errors are all collected and returned with the document.
+pub fn parse_str_validating(text: &str, options: &ParseOptions) -> Result<(Doc, Vec<XmlError>), XmlError> {
+    let options = ParseOptions {
+        expand_entities: true,
+        ..options.clone()
+    };
+    let doc = parse_str_with(text, &options)?;
+    let errors = validate(&doc).into_iter().map(|e| e.locate(text)).collect();
+    Ok((doc, errors))
+}
+
+fn parse_document(text: &str, options: &ParseOptions) -> Result<Doc, XmlError> {
     let prolog = parse_prolog(text, 0)?;
     let p_end = prolog.get_endpos();
-    let elem = parse_elem(text, p_end, 0)?;
+    let elem = parse_elem(text, p_end, 0, options.max_depth)?;
     let e_end = elem.get_endpos();
     let tail = parse_tail(text, e_end)?;
-    let doc = Doc {
-        prolog: prolog,
-        elem: elem,
-        tail: tail,
-    };
+    let mut doc = Doc { prolog, elem, tail };
+    check_version(&doc, text)?;
+    let mut budget = entity::Budget::new(options, text.len());
+    if let Some(doctype) = doc.prolog.doctype_decl.as_mut() {
+        entity::expand_dtd(doctype, &mut budget)?;
+    }
+    check_entities_declared(&doc)?;
+    if options.expand_entities {
+        entity::expand_entities(&mut doc, &mut budget)?;
+    }
+    apply_attribute_decls(&mut doc);
+    if options.namespaces {
+        namespace::resolve_namespaces(&mut doc)?;
+    }
     Ok(doc)
 }
 
-fn parse_prolog(text: &[char], start: usize) -> Result<Prolog, XmlError> {
+/// Apply the attribute-list declarations of the DTD to every element, adding
+/// attributes that were left out but have a default value, and noting which
+/// attributes have types whose values are normalized further, and in which
+/// version of XML
+fn apply_attribute_decls(doc: &mut Doc) {
+    let version = doc.version();
+    let Doc { prolog, elem, .. } = doc;
+    let att_defs = prolog.doctype_decl.as_ref().map(DoctypeDecl::att_defs_by_elem).unwrap_or_default();
+    if !att_defs.is_empty() || version != XmlVersion::V1_0 {
+        apply_elem_attribute_decls(elem, &att_defs, version);
+    }
+}
+
+fn apply_elem_attribute_decls(elem: &mut Elem, att_defs: &HashMap<&str, Vec<&AttDef>>, version: XmlVersion) {
+    let (pos, name, attribs, content) = match elem {
+        Elem::Empty(empty) => (empty.start, &empty.name, &mut empty.attribs, None),
+        Elem::Full(full) => (full.start.start, &full.start.name, &mut full.start.attribs, full.content.as_mut()),
+    };
+    if let Some(defs) = att_defs.get(name.as_str()) {
+        for attrib in attribs.iter_mut() {
+            if let Some(def) = defs.iter().find(|def| def.name() == attrib.name()) {
+                attrib.tokenized = def.att_type() != &AttType::CData;
+            }
+        }
+        for def in defs {
+            if let Some(value) = def.default_decl().value()
+                && !attribs.iter().any(|attrib| attrib.name() == def.name())
+            {
+                attribs.push(Attribute {
+                    start: pos,
+                    end: pos,
+                    name: def.name.clone(),
+                    value: value.clone(),
+                    specified: false,
+                    tokenized: def.att_type() != &AttType::CData,
+                    namespace: None,
+                    version,
+                });
+            }
+        }
+    }
+    for attrib in attribs.iter_mut() {
+        attrib.version = version;
+    }
+    if let Some(content) = content {
+        apply_content_attribute_decls(&mut content.items, att_defs, version);
+    }
+}
+
+fn apply_content_attribute_decls(
+    items: &mut [ContentItem],
+    att_defs: &HashMap<&str, Vec<&AttDef>>,
+    version: XmlVersion,
+) {
+    for item in items {
+        match item {
+            ContentItem::Elem(elem) => apply_elem_attribute_decls(elem, att_defs, version),
+            ContentItem::Entity { items, .. } => apply_content_attribute_decls(items, att_defs, version),
+            _ => (),
+        };
+    }
+}
+
+/// Check what XML 1.0 and 1.1 differ on: a 1.0 document may not refer to
+/// characters that only 1.1 allows, while a 1.1 document may only contain
+/// restricted characters as references
+fn check_version(doc: &Doc, text: &str) -> Result<(), XmlError> {
+    let version = doc.version();
+    check_restricted_chars(text, 0, text.len(), version)?;
+    if let Some(subset) = doc.prolog.doctype_decl.as_ref().and_then(|doctype| doctype.int_subset.as_ref()) {
+        check_decl_char_refs(&subset.items, version)?;
+    }
+    check_elem_char_refs(&doc.elem, version)
+}
+
+/// Report the first character from `start` to `end` in `text` that XML
+/// `version` only allows to be written as a character reference
+fn check_restricted_chars(text: &str, start: usize, end: usize, version: XmlVersion) -> Result<(), XmlError> {
+    if version == XmlVersion::V1_0 {
+        return Ok(());
+    }
+    match text[start..end].char_indices().find(|&(_, c)| version.is_restricted(c)) {
+        Some((idx, c)) => Err(XmlError::new(XmlErrorKind::BadChar(c), start + idx, "document")),
+        None => Ok(()),
+    }
+}
+
+fn check_char_ref(reference: &Reference, start: usize, version: XmlVersion) -> Result<(), XmlError> {
+    match reference {
+        Reference::CharRef(ref_text) if !reference.decode().is_some_and(|c| version.is_char(c)) => {
+            Err(XmlError::new(XmlErrorKind::InvalidCharRef(ref_text.clone()), start, "CharRef"))
+        }
+        _ => Ok(()),
+    }
+}
+
+fn check_elem_char_refs(elem: &Elem, version: XmlVersion) -> Result<(), XmlError> {
+    for attrib in elem.attributes() {
+        check_attvalue_char_refs(&attrib.value, version)?;
+    }
+    for item in elem.children() {
+        match item {
+            ContentItem::Elem(child) => check_elem_char_refs(child, version)?,
+            ContentItem::Reference { start, reference } => check_char_ref(reference, *start, version)?,
+            _ => (),
+        };
+    }
+    Ok(())
+}
+
+fn check_attvalue_char_refs(value: &AttValue, version: XmlVersion) -> Result<(), XmlError> {
+    // skip the opening qoute
+    let mut pos = value.start + 1;
+    for item in &value.items {
+        if let AttValueItem::Reference(reference) = item {
+            check_char_ref(reference, pos, version).map_err(|e| e.within("AttValue"))?;
+        }
+        pos += item.text_len();
+    }
+    Ok(())
+}
+
+fn check_decl_char_refs(items: &[IntSubsetItem], version: XmlVersion) -> Result<(), XmlError> {
+    for item in items {
+        match item {
+            IntSubsetItem::AttlistDecl(decl) => {
+                for value in decl.att_defs.iter().filter_map(|def| def.default_decl.value()) {
+                    check_attvalue_char_refs(value, version)?;
+                }
+            }
+            IntSubsetItem::EntityDecl(EntityDecl::GEDecl(GEDecl { entity_def: EntityDef::EntityValue(value), .. }))
+            | IntSubsetItem::EntityDecl(EntityDecl::PEDecl(PEDecl { pedef: PEDef::EntityValue(value), .. })) => {
+                let literal = &value.text;
+                let mut here = 0;
+                while let Some(idx) = rest(literal, here).find('&') {
+                    here += idx;
+                    if let Ok(reference) = parse_reference(literal, here) {
+                        // the literal begins after the opening qoute
+                        let start = value.start + 1 + here;
+                        check_char_ref(&reference, start, version).map_err(|e| e.within("EntityValue"))?;
+                    }
+                    here += 1;
+                }
+            }
+            _ => (),
+        };
+    }
+    Ok(())
+}
+
+/// Check the Entity Declared constraint, that every entity reference names a
+/// predefined entity or one declared in the internal subset, as do the
+/// references in the replacement text of the entities referenced and in
+/// attribute defaults. When the DTD has an external subset or parameter
+/// entity references, which may hold further declarations, the constraint
+/// only applies to standalone documents.
+///
+/// The other constraints on references that do not depend on expanding them
+/// are checked along the way: that content does not refer to unparsed
+/// entities, that attribute values do not refer to external entities or take
+/// `<` from replacement text, and that no entity refers to itself.
+fn check_entities_declared(doc: &Doc) -> Result<(), XmlError> {
+    let standalone = doc.prolog.xml_decl.as_ref().and_then(|decl| decl.standalone()) == Some(true);
+    let doctype = doc.prolog.doctype_decl.as_ref();
+    let declared = declared_entities(doctype, standalone);
+    if let Some(doctype) = doctype {
+        check_default_entities(doctype, &declared)?;
+    }
+    check_elem_entities(&doc.elem, &declared)
+}
+
+/// The general entities a document may reference. References to undeclared
+/// entities are allowed when declarations may lie in parts of the DTD that
+/// were not read.
+fn declared_entities(doctype: Option<&DoctypeDecl>, standalone: bool) -> DeclaredEntities {
+    let external = doctype.is_some_and(|doctype| doctype.ext_id.is_some() || doctype.has_pe_refs());
+    let table = doctype.map_or_else(EntityTable::new, DoctypeDecl::entities);
+    DeclaredEntities::new(table, !external || standalone)
+}
+
+/// Check the references in the default values of the attribute-list
+/// declarations in the internal subset
+fn check_default_entities(doctype: &DoctypeDecl, declared: &DeclaredEntities) -> Result<(), XmlError> {
+    let items = doctype.int_subset.iter().flat_map(|subset| subset.items.iter());
+    for item in items {
+        if let IntSubsetItem::AttlistDecl(decl) = item {
+            for value in decl.att_defs.iter().filter_map(|def| def.default_decl.value()) {
+                check_attvalue_entities(value, declared).map_err(|e| e.within("AttlistDecl"))?;
+            }
+        }
+    }
+    Ok(())
+}
+
+fn check_elem_entities(elem: &Elem, declared: &DeclaredEntities) -> Result<(), XmlError> {
+    for attrib in elem.attributes() {
+        check_attvalue_entities(&attrib.value, declared)?;
+    }
+    for item in elem.children() {
+        match item {
+            ContentItem::Elem(child) => check_elem_entities(child, declared)?,
+            ContentItem::Reference { start, reference } => declared.check(reference, *start)?,
+            _ => (),
+        };
+    }
+    Ok(())
+}
+
+fn check_attvalue_entities(value: &AttValue, declared: &DeclaredEntities) -> Result<(), XmlError> {
+    // skip the opening qoute
+    let mut pos = value.start + 1;
+    for item in &value.items {
+        if let AttValueItem::Reference(reference) = item {
+            declared.check_in_attvalue(reference, pos).map_err(|e| e.within("AttValue"))?;
+        }
+        pos += item.text_len();
+    }
+    Ok(())
+}
+
+/// Parse a complete document from a buffer of bytes, read in the encoding
+/// that `encoding::decode` chooses for it.
+///
+/// Positions in the resulting tree are byte offsets into the text that
+/// `encoding::decode` returns, which for UTF-8 without a byte order mark is
+/// `text` itself. UTF-8 input is parsed in place without being copied.
+pub fn parse_bytes(text: &[u8]) -> Result<Doc, XmlError> {
+    let (decoded, _) = encoding::decode(text)?;
+    parse_str(&decoded)
+}
+
+/// Parse a complete document from a slice of characters.
+///
+/// The characters are re-encoded as UTF-8 before parsing, so positions in
+/// the resulting tree are byte offsets into that encoding rather than
+/// indices into `text`. Prefer `parse_str` or `parse_bytes`.
+pub fn parse_doc(text: &[char]) -> Result<Doc, XmlError> {
+    let utf8: String = text.iter().collect();
+    parse_str(&utf8)
+}
+
+/// Limit on how deeply the recursive productions of the DTD and entity
+/// expansion may nest, so that hostile input cannot overflow the stack.
+/// Element nesting is limited separately by `ParseOptions::max_depth`.
+const MAX_RECUR_DEPTH: usize = 512;
+
+/// The default for `ParseOptions::max_depth`
+const DEFAULT_MAX_DEPTH: usize = 256;
+
+/// Return the character beginning at byte offset `pos`, reporting the end of
+/// text as an error of the production named by `context`
+fn char_at(text: &str, pos: usize, context: &str) -> Result<char, XmlError> {
+    text.get(pos..)
+        .and_then(|rest| rest.chars().next())
+        .ok_or_else(|| XmlError::new(XmlErrorKind::TextEnd, pos, context))
+}
+
+/// Return the text remaining after byte offset `pos`, which is empty when
+/// `pos` lies past the end of the text
+fn rest(text: &str, pos: usize) -> &str {
+    text.get(pos..).unwrap_or("")
+}
+
+/// Of two errors from failed alternatives, keep the one that got further
+/// into the text, since it is most likely to describe the real problem
+fn furthest(a: XmlError, b: XmlError) -> XmlError {
+    if reach(&b) > reach(&a) { b } else { a }
+}
+
+/// The error for a production none of whose alternatives match at `start`,
+/// given the error of the one that got furthest. Errors that no alternative
+/// could get past, such as a character outside the Char production, a
+/// reference to one, or an attribute given twice, are reported as they are.
+fn no_valid_variant(cause: XmlError, start: usize, context: &str) -> XmlError {
+    let decisive = match cause.kind() {
+        XmlErrorKind::BadChar(c) => !is_xml_char(*c),
+        XmlErrorKind::InvalidCharRef(_) | XmlErrorKind::DuplicateAttribute(_) => true,
+        _ => false,
+    };
+    if decisive {
+        return cause;
+    }
+    XmlError::new(XmlErrorKind::NoValidVariant, start, context).caused_by(cause)
+}
+
+/// How far into the text parsing got before failing, which may be further
+/// than the error itself when it wraps the error of a nested production
+fn reach(error: &XmlError) -> usize {
+    let mut idx = error.doc_idx();
+    let mut cause = error.underlying();
+    while let Some(e) = cause {
+        idx = idx.max(e.doc_idx());
+        cause = e.underlying();
+    }
+    idx
+}
+
+fn parse_prolog(text: &str, start: usize) -> Result<Prolog, XmlError> {
     let maybe_decl = parse_xmldecl(text, start);
     let (xdecl, pos) = match maybe_decl {
         Ok(xmldecl) => {
             let newpos = xmldecl.get_endpos();
             (Some(xmldecl), newpos)
         }
-        Err(_e) => (None, start),
+        Err(e) => match e.kind() {
+            XmlErrorKind::BadXDeclStart => (None, start),
+            _ => return Err(e),
+        },
     };
     let mut here = pos;
     let mut miscs = Vec::new();
-    while let Ok(misc) = parse_misc(text, here) {
+    while starts_misc(text, here) {
+        let misc = parse_misc(text, here)?;
         here = misc.get_endpos();
         miscs.push(misc);
     }
@@ -346,25 +1016,33 @@ fn parse_prolog(text: &[char], start: usize) -> Result<Prolog, XmlError> {
             let newpos = doctypedecl.get_endpos();
             (Some(doctypedecl), newpos)
         }
-        Err(_e) => (None, here),
+        Err(e) => match e.kind() {
+            XmlErrorKind::KeywordMatchFail => (None, here),
+            _ => return Err(e),
+        },
     };
     let mut here2 = pos1;
-    while let Ok(misc) = parse_misc(text, here2) {
+    while starts_misc(text, here2) {
+        let misc = parse_misc(text, here2)?;
         here2 = misc.get_endpos();
         miscs.push(misc);
     }
     let prolog = Prolog {
+        start,
+        end: here2,
         xml_decl: xdecl,
         doctype_decl: docdecl,
-        miscs: miscs,
+        miscs,
     };
     Ok(prolog)
 }
 
-fn parse_xmldecl(text: &[char], start: usize) -> Result<XmlDecl, XmlError> {
-    let subtext = &text[start..];
-    let needle: Vec<char> = "<?xml".chars().collect();
-    if subtext.starts_with(&needle) {
+fn parse_xmldecl(text: &str, start: usize) -> Result<XmlDecl, XmlError> {
+    let needle = "<?xml";
+    let subtext = rest(text, start);
+    let is_decl = subtext.starts_with(needle)
+        && subtext[needle.len()..].starts_with([' ', '\t', '\r', '\n']);
+    if is_decl {
         let mut here = start + needle.len();
         let version = parse_version(text, here)?;
         here = version.get_endpos();
@@ -391,223 +1069,220 @@ fn parse_xmldecl(text: &[char], start: usize) -> Result<XmlDecl, XmlError> {
             }
             Err(_e) => (),
         };
-        let c_pen = text.get(here).ok_or(XmlError::TextEnd)?;
-        if c_pen == &'?' {
-            let c_ult = text.get(here + 1).ok_or(XmlError::TextEnd)?;
-            if c_ult == &'>' {
+        let c_pen = char_at(text, here, "XMLDecl")?;
+        if c_pen == '?' {
+            let c_ult = char_at(text, here + 1, "XMLDecl")?;
+            if c_ult == '>' {
                 let xmldecl = XmlDecl {
-                    start: start,
+                    start,
                     end: here + 2,
-                    version: version,
+                    version,
                     encoding: enc,
                     standalone: sddecl,
                 };
                 Ok(xmldecl)
             } else {
-                Err(XmlError::BadChar(*c_ult))
+                Err(XmlError::new(XmlErrorKind::BadChar(c_ult), here + 1, "XMLDecl"))
             }
         } else {
-            Err(XmlError::BadChar(*c_pen))
+            Err(XmlError::new(XmlErrorKind::BadChar(c_pen), here, "XMLDecl"))
         }
     } else {
-        Err(XmlError::BadXDeclStart)
+        Err(XmlError::new(XmlErrorKind::BadXDeclStart, start, "XMLDecl"))
+    }
+}
+
+/// Parse the text declaration which may begin an external entity. Unlike
+/// the XML declaration, the version is optional and the encoding required.
+fn parse_textdecl(text: &str, start: usize) -> Result<TextDeclHelper, XmlError> {
+    let needle = "<?xml";
+    let subtext = rest(text, start);
+    let is_decl = subtext.starts_with(needle)
+        && subtext[needle.len()..].starts_with([' ', '\t', '\r', '\n']);
+    if !is_decl {
+        return Err(XmlError::new(XmlErrorKind::BadXDeclStart, start, "TextDecl"));
+    }
+    let mut here = start + needle.len();
+    if let Ok(version) = parse_version(text, here) {
+        here = version.get_endpos();
+    }
+    let encoding = parse_encoding(text, here).map_err(|e| e.within("TextDecl"))?;
+    here = encoding.get_endpos();
+    if let Ok(ws) = parse_ws(text, here) {
+        here = ws.get_endpos();
+    }
+    if rest(text, here).starts_with("?>") {
+        Ok(TextDeclHelper { end: here + 2 })
+    } else {
+        let c = char_at(text, here, "TextDecl")?;
+        Err(XmlError::new(XmlErrorKind::BadChar(c), here, "TextDecl"))
     }
 }
 
-fn parse_eq(text: &[char], start: usize) -> Result<EqHelper, XmlError> {
+fn parse_eq(text: &str, start: usize) -> Result<EqHelper, XmlError> {
     let pos1 = match parse_ws(text, start) {
         Ok(ws) => ws.get_endpos(),
         Err(_e) => start,
     };
-    let c1 = text.get(pos1).ok_or(XmlError::TextEnd)?;
-    if c1 == &'=' {
+    let c1 = char_at(text, pos1, "Eq")?;
+    if c1 == '=' {
         let pos2 = match parse_ws(text, pos1 + 1) {
             Ok(ws) => ws.get_endpos(),
             Err(_e) => pos1 + 1,
         };
-        let eq = EqHelper {
-            start: start,
-            end: pos2,
-        };
+        let eq = EqHelper { end: pos2 };
         Ok(eq)
     } else {
-        Err(XmlError::BadChar(*c1))
+        Err(XmlError::new(XmlErrorKind::BadChar(c1), pos1, "Eq"))
     }
 }
 
-fn parse_standalone(text: &[char], start: usize) -> Result<SDDecl, XmlError> {
+fn parse_standalone(text: &str, start: usize) -> Result<SDDecl, XmlError> {
     let lead_ws = parse_ws(text, start)?;
     let pos = lead_ws.get_endpos();
-    let subtext = &text[pos..];
-    let needle: Vec<char> = "standalone".chars().collect();
-    if subtext.starts_with(&needle) {
+    let needle = "standalone";
+    if rest(text, pos).starts_with(needle) {
         let pos1 = pos + needle.len();
         let eq = parse_eq(text, pos1)?;
         let pos2 = eq.end;
-        let subtext2 = &text[pos2..];
-        let needle1: Vec<char> = "\"yes\"".chars().collect();
-        let needle2: Vec<char> = "\'yes\'".chars().collect();
-        let needle3: Vec<char> = "\"no\"".chars().collect();
-        let needle4: Vec<char> = "\'no\'".chars().collect();
+        let subtext2 = rest(text, pos2);
         let mut here = pos2;
-        let is_standalone = if subtext2.starts_with(&needle1) {
-            here += 5;
-            true
-        } else if subtext2.starts_with(&needle2) {
+        let is_standalone = if subtext2.starts_with("\"yes\"") || subtext2.starts_with("\'yes\'") {
             here += 5;
             true
-        } else if subtext2.starts_with(&needle3) {
-            here += 4;
-            false
-        } else if subtext.starts_with(&needle4) {
+        } else if subtext2.starts_with("\"no\"") || subtext2.starts_with("\'no\'") {
             here += 4;
             false
         } else {
-            return Err(XmlError::KeywordMatchFail);
+            return Err(XmlError::new(XmlErrorKind::KeywordMatchFail, pos2, "SDDecl"));
         };
         let standalone = SDDecl {
-            start: start,
+            start,
             end: here,
-            is_standalone: is_standalone,
+            is_standalone,
         };
 
         Ok(standalone)
     } else {
-        Err(XmlError::KeywordMatchFail)
+        Err(XmlError::new(XmlErrorKind::KeywordMatchFail, pos, "SDDecl"))
     }
 }
 
-fn parse_encoding(text: &[char], start: usize) -> Result<Encoding, XmlError> {
+fn parse_encoding(text: &str, start: usize) -> Result<Encoding, XmlError> {
     let lead_ws = parse_ws(text, start)?;
     let pos = lead_ws.get_endpos();
-    let subtext = &text[pos..];
-    let needle: Vec<char> = "encoding".chars().collect();
-    if subtext.starts_with(&needle) {
+    let needle = "encoding";
+    if rest(text, pos).starts_with(needle) {
         let pos1 = pos + needle.len();
         let eq = parse_eq(text, pos1)?;
         let pos2 = eq.end;
-        let c0 = text.get(pos2).ok_or(XmlError::TextEnd)?;
-        let single_qoute = c0 == &'\'';
-        if c0 == &'"' || single_qoute {
+        let c0 = char_at(text, pos2, "EncodingDecl")?;
+        let single_qoute = c0 == '\'';
+        if c0 == '"' || single_qoute {
             let mut here = pos2 + 1;
-            let mut cur_char = text.get(here).ok_or(XmlError::TextEnd)?;
+            let mut cur_char = char_at(text, here, "EncodingDecl")?;
             let mut arena = String::new();
             let mut first = true;
             while cur_char != c0 {
                 if first {
-                    match *cur_char {
+                    match cur_char {
                         'A'..='Z' | 'a'..='z' => {
-                            arena.push(*cur_char);
+                            arena.push(cur_char);
                         }
                         _ => {
-                            return Err(XmlError::BadChar(*cur_char));
+                            return Err(XmlError::new(XmlErrorKind::BadChar(cur_char), here, "EncodingDecl"));
                         }
                     };
                 } else {
-                    match *cur_char {
+                    match cur_char {
                         'A'..='Z' | 'a'..='z' | '0'..='9' | '.' | '_' | '-' => {
-                            arena.push(*cur_char);
+                            arena.push(cur_char);
                         }
                         _ => {
-                            return Err(XmlError::BadChar(*cur_char));
+                            return Err(XmlError::new(XmlErrorKind::BadChar(cur_char), here, "EncodingDecl"));
                         }
                     };
                 }
                 first = false;
-                here += 1;
-                cur_char = text.get(here).ok_or(XmlError::TextEnd)?;
+                here += cur_char.len_utf8();
+                cur_char = char_at(text, here, "EncodingDecl")?;
             }
             let encoding = Encoding {
-                start: start,
+                start,
                 end: here + 1,
                 enc_name: arena,
             };
             Ok(encoding)
         } else {
-            Err(XmlError::BadChar(*c0))
+            Err(XmlError::new(XmlErrorKind::BadChar(c0), pos2, "EncodingDecl"))
         }
     } else {
-        Err(XmlError::KeywordMatchFail)
+        Err(XmlError::new(XmlErrorKind::KeywordMatchFail, pos, "EncodingDecl"))
     }
 }
 
-fn parse_version(text: &[char], start: usize) -> Result<VersionInfo, XmlError> {
+fn parse_version(text: &str, start: usize) -> Result<VersionInfo, XmlError> {
     let lead_ws = parse_ws(text, start)?;
     let pos = lead_ws.get_endpos();
-    let subtext = &text[pos..];
-    let needle: Vec<char> = "version".chars().collect();
-    if subtext.starts_with(&needle) {
+    let needle = "version";
+    if rest(text, pos).starts_with(needle) {
         let pos1 = pos + needle.len();
         let pos2 = match parse_ws(text, pos1) {
             Ok(ws) => ws.get_endpos(),
             Err(_) => pos1,
         };
-        let c_eq = text.get(pos2).ok_or(XmlError::TextEnd)?;
-        if c_eq == &'=' {
+        let c_eq = char_at(text, pos2, "VersionInfo")?;
+        if c_eq == '=' {
             let pos3 = pos2 + 1;
             let mut here = match parse_ws(text, pos3) {
                 Ok(ws) => ws.get_endpos(),
                 Err(_) => pos3,
             };
-            let c0 = text.get(here).ok_or(XmlError::TextEnd)?;
-            let single_qoute = c0 == &'\'';
-            if single_qoute || c0 == &'\"' {
+            let c0 = char_at(text, here, "VersionInfo")?;
+            let single_qoute = c0 == '\'';
+            if single_qoute || c0 == '\"' {
                 here += 1;
-                let mut seen_dot = false;
+                let num_start = here;
                 let mut arena = String::new();
-                let mut cur_char = text.get(here).ok_or(XmlError::TextEnd)?;
+                let mut cur_char = char_at(text, here, "VersionInfo")?;
                 while cur_char != c0 {
-                    match *cur_char {
-                        '0'..='9' => {
-                            arena.push(*cur_char);
-                        }
-                        '.' => {
-                            if !seen_dot {
-                                seen_dot = true;
-                                arena.push(*cur_char);
-                            } else {
-                                return Err(XmlError::BadChar(*cur_char));
-                            }
+                    match cur_char {
+                        '0'..='9' | '.' => {
+                            arena.push(cur_char);
                         }
                         _ => {
-                            return Err(XmlError::BadChar(*cur_char));
+                            return Err(XmlError::new(XmlErrorKind::BadChar(cur_char), here, "VersionInfo"));
                         }
                     };
-                    here += 1;
-                    cur_char = text.get(here).ok_or(XmlError::TextEnd)?;
+                    here += cur_char.len_utf8();
+                    cur_char = char_at(text, here, "VersionInfo")?;
                 }
-                let maybe_version_num = arena.parse::<f32>();
-                let version_num = match maybe_version_num {
-                    Ok(num) => num,
-                    Err(_e) => {
-                        return Err(XmlError::KeywordMatchFail);
-                    }
-                };
-                if version_num >= 2.0 {
-                    Err(XmlError::KeywordMatchFail)
+                // VersionNum is `1.` followed by digits
+                let minor = arena.strip_prefix("1.").unwrap_or_default();
+                if minor.is_empty() || !minor.bytes().all(|b| b.is_ascii_digit()) {
+                    Err(XmlError::new(XmlErrorKind::KeywordMatchFail, num_start, "VersionInfo"))
                 } else {
                     let version_info = VersionInfo {
-                        start: start,
+                        start,
                         end: here + 1,
-                        ver_num: version_num,
+                        ver_num: arena,
                     };
                     Ok(version_info)
                 }
             } else {
-                Err(XmlError::BadChar(*c0))
+                Err(XmlError::new(XmlErrorKind::BadChar(c0), here, "VersionInfo"))
             }
         } else {
-            Err(XmlError::BadChar(*c_eq))
+            Err(XmlError::new(XmlErrorKind::BadChar(c_eq), pos2, "VersionInfo"))
         }
     } else {
-        Err(XmlError::KeywordMatchFail)
+        Err(XmlError::new(XmlErrorKind::KeywordMatchFail, pos, "VersionInfo"))
     }
 }
 
-fn parse_doctype(text: &[char], start: usize) -> Result<DoctypeDecl, XmlError> {
-    let subtext = &text[start..];
-    let needle: Vec<char> = "<!DOCTYPE".chars().collect();
-    if subtext.starts_with(&needle) {
+fn parse_doctype(text: &str, start: usize) -> Result<DoctypeDecl, XmlError> {
+    let needle = "<!DOCTYPE";
+    if rest(text, start).starts_with(needle) {
         let mut here = start + needle.len();
         let spacer1 = parse_ws(text, here)?;
         here = spacer1.get_endpos();
@@ -624,13 +1299,16 @@ fn parse_doctype(text: &[char], start: usize) -> Result<DoctypeDecl, XmlError> {
                 here = ending;
                 Some(ex_id)
             },
-            Err(_e) => None,
+            Err(e) => match e.kind() {
+                XmlErrorKind::KeywordMatchFail => None,
+                _ => return Err(e.within("doctypedecl")),
+            },
         };
         match parse_ws(text, here) {
             Ok(ws) => {here = ws.get_endpos();},
             Err(_e) => (),
         };
-        let c0 = *text.get(here).ok_or(XmlError::TextEnd)?;
+        let c0 = char_at(text, here, "doctypedecl")?;
         if c0 == '[' {
             here += 1;
             let maybe_intsub = parse_intsubset(text, here);
@@ -639,90 +1317,91 @@ fn parse_doctype(text: &[char], start: usize) -> Result<DoctypeDecl, XmlError> {
                     here = isub.get_endpos();
                     Some(isub)
                 },
-                Err(_e) => None,
+                Err(e) => match e.kind() {
+                    XmlErrorKind::NoData => None,
+                    _ => return Err(e.within("doctypedecl")),
+                },
             };
-            let c1 = *text.get(here).ok_or(XmlError::TextEnd)?;
+            let c1 = char_at(text, here, "doctypedecl")?;
             if c1 == ']' {
                 here += 1;
                 match parse_ws(text, here) {
                     Ok(ws) => {here = ws.get_endpos();},
                     Err(_e) => (),
                 };
-                let c2 = *text.get(here).ok_or(XmlError::TextEnd)?;
+                let c2 = char_at(text, here, "doctypedecl")?;
                 if c2 == '>' {
                     let docdecl = DoctypeDecl {
-                        start : start,
+                        start,
                         end : here + 1,
-                        name : name,
+                        name,
                         ext_id : extid,
                         int_subset : intsub,
+                        ext_subset : None,
                     };
                     Ok(docdecl)
                 } else {
-                    Err(XmlError::BadChar(c2))
+                    Err(XmlError::new(XmlErrorKind::BadChar(c2), here, "doctypedecl"))
                 }
             } else {
-                Err(XmlError::BadChar(c1))
+                Err(XmlError::new(XmlErrorKind::BadChar(c1), here, "doctypedecl"))
             }
         } else if c0 == '>' {
             let docdecl = DoctypeDecl {
-                start : start,
+                start,
                 end : here + 1,
-                name : name,
+                name,
                 ext_id : extid,
                 int_subset : None,
+                ext_subset : None,
             };
             Ok(docdecl)
         } else {
-            Err(XmlError::BadChar(c0))
+            Err(XmlError::new(XmlErrorKind::BadChar(c0), here, "doctypedecl"))
         }
     } else {
-        Err(XmlError::KeywordMatchFail)
+        Err(XmlError::new(XmlErrorKind::KeywordMatchFail, start, "doctypedecl"))
     }
 }
 
-fn parse_syslit(text: &[char], start: usize) -> Result<String, XmlError> {
-    let c0 = text.get(start).ok_or(XmlError::TextEnd)?;
-    let single_qoute = c0 == &'\'';
-    if c0 == &'\"' || single_qoute {
-        let mut here = start + 1;
+fn parse_syslit(text: &str, start: usize) -> Result<String, XmlError> {
+    let c0 = char_at(text, start, "SystemLiteral")?;
+    let single_qoute = c0 == '\'';
+    if c0 == '\"' || single_qoute {
         let mut arena = String::new();
-        while let Some(c) = text.get(here) {
-            match *c {
-                '\'' => {
-                    if single_qoute {
-                        return Ok(arena);
-                    }
+        for (offset, c) in rest(text, start + 1).char_indices() {
+            match c {
+                '\'' if single_qoute => {
+                    return Ok(arena);
                 }
-                '\"' => {
-                    if !single_qoute {
-                        return Ok(arena);
-                    }
+                '\"' if !single_qoute => {
+                    return Ok(arena);
+                }
+                _ if !is_xml_char(c) => {
+                    return Err(XmlError::new(XmlErrorKind::BadChar(c), start + 1 + offset, "SystemLiteral"));
                 }
                 _ => (),
             };
-            arena.push(*c);
-            here += 1;
+            arena.push(c);
         }
-        Err(XmlError::TextEnd)
+        Err(XmlError::new(XmlErrorKind::TextEnd, text.len(), "SystemLiteral"))
     } else {
-        Err(XmlError::BadChar(*c0))
+        Err(XmlError::new(XmlErrorKind::BadChar(c0), start, "SystemLiteral"))
     }
 }
 
-fn parse_pubidlit(text: &[char], start: usize) -> Result<String, XmlError> {
-    let c0 = text.get(start).ok_or(XmlError::TextEnd)?;
-    let single_qoute = c0 == &'\'';
-    if c0 == &'\"' || single_qoute {
-        let mut here = start + 1;
+fn parse_pubidlit(text: &str, start: usize) -> Result<String, XmlError> {
+    let c0 = char_at(text, start, "PubidLiteral")?;
+    let single_qoute = c0 == '\'';
+    if c0 == '\"' || single_qoute {
         let mut arena = String::new();
-        while let Some(c) = text.get(here) {
-            match *c {
+        for (offset, c) in rest(text, start + 1).char_indices() {
+            match c {
                 '\'' => {
                     if single_qoute {
                         return Ok(arena);
                     } else {
-                        arena.push(*c);
+                        arena.push(c);
                     }
                 }
                 '\"' => {
@@ -754,36 +1433,35 @@ fn parse_pubidlit(text: &[char], start: usize) -> Result<String, XmlError> {
                 | '$'
                 | '_'
                 | '%' => {
-                    arena.push(*c);
+                    arena.push(c);
                 }
                 _ => {
-                    return Err(XmlError::BadChar(*c));
+                    return Err(XmlError::new(XmlErrorKind::BadChar(c), start + 1 + offset, "PubidLiteral"));
                 }
             }
-            here += 1;
         }
-        Err(XmlError::TextEnd)
+        Err(XmlError::new(XmlErrorKind::TextEnd, text.len(), "PubidLiteral"))
     } else {
-        Err(XmlError::BadChar(*c0))
+        Err(XmlError::new(XmlErrorKind::BadChar(c0), start, "PubidLiteral"))
     }
 }
 
-fn parse_externalid(text: &[char], start: usize) -> Result<ExternalID, XmlError> {
-    let subtext = &text[start..];
-    let needle1: Vec<char> = "SYSTEM".chars().collect();
-    let needle2: Vec<char> = "PUBLIC".chars().collect();
-    if subtext.starts_with(&needle1) {
+fn parse_externalid(text: &str, start: usize) -> Result<ExternalID, XmlError> {
+    let subtext = rest(text, start);
+    let needle1 = "SYSTEM";
+    let needle2 = "PUBLIC";
+    if subtext.starts_with(needle1) {
         let pos = start + needle1.len();
         let spacer = parse_ws(text, pos)?;
         let syslit_start = spacer.get_endpos();
         let syslit = parse_syslit(text, syslit_start)?;
         let ext_id = ExternalID::System {
-            start: start,
+            start,
             end: syslit_start + syslit.len() + 2, // account for qoute characters
             sys_lit: syslit,
         };
         Ok(ext_id)
-    } else if subtext.starts_with(&needle2) {
+    } else if subtext.starts_with(needle2) {
         let pos = start + needle2.len();
         let spacer1 = parse_ws(text, pos)?;
         let pubid_start = spacer1.get_endpos();
@@ -793,79 +1471,94 @@ fn parse_externalid(text: &[char], start: usize) -> Result<ExternalID, XmlError>
         let syslit_start = spacer2.get_endpos();
         let syslit = parse_syslit(text, syslit_start)?;
         let ext_id = ExternalID::Public {
-            start: start,
+            start,
             end: syslit_start + syslit.len() + 2, // account for qoute characters
             pub_lit: pubid_lit,
             sys_lit: syslit,
         };
         Ok(ext_id)
     } else {
-        Err(XmlError::KeywordMatchFail)
+        Err(XmlError::new(XmlErrorKind::KeywordMatchFail, start, "ExternalID"))
     }
 }
 
-fn parse_intsubset(text: &[char], start: usize) -> Result<IntSubset, XmlError> {
+fn parse_intsubset(text: &str, start: usize) -> Result<IntSubset, XmlError> {
     let mut items = Vec::new();
     let mut here = start;
-    while let Ok(item) = parse_int_subset_item(text, here) {
+    while here < text.len() && !rest(text, here).starts_with(']') {
+        let item = parse_int_subset_item(text, here)?;
         here = item.get_endpos();
         items.push(item);
     }
-    if items.len() > 0 {
+    if !items.is_empty() {
         let subset = IntSubset {
-            items : items,
+            start,
+            end : here,
+            items,
         };
         Ok(subset)
     } else {
-        Err(XmlError::NoData)
-    }
-}
-
-fn parse_int_subset_item(text :&[char], start :usize) -> Result<IntSubsetItem, XmlError> {
-    if let Ok(ws) = parse_ws(text, start) {
-        Ok(IntSubsetItem::Blank(ws))
-    } else if let Ok(peref) = parse_pereference(text, start) {
-        Ok(IntSubsetItem::PEReference(peref))
-    } else if let Ok(elemdecl) = parse_elemdecl(text, start) {
-        Ok(IntSubsetItem::ElemDecl(elemdecl))
-    } else if let Ok(attlist) = parse_attlistdecl(text, start) {
-        Ok(IntSubsetItem::AttlistDecl(attlist))
-    } else if let Ok(entity) = parse_entitydecl(text, start) {
-        Ok(IntSubsetItem::EntityDecl(entity))
-    } else if let Ok(notation) = parse_notationdecl(text, start) {
-        Ok(IntSubsetItem::NotationDecl(notation))
-    } else if let Ok(proc_instr) = parse_pi(text, start) {
-        Ok(IntSubsetItem::ProcInstr(proc_instr))
-    } else if let Ok(comment) = parse_comment(text, start) {
-        Ok(IntSubsetItem::Comment(comment))
-    } else {
-        Err(XmlError::NoValidVariant)
+        Err(XmlError::new(XmlErrorKind::NoData, start, "intSubset"))
     }
 }
 
-fn parse_publicid(text :&[char], start :usize) -> Result<PublicID, XmlError> {
-    let subtext = &text[start..];
-    let needle :Vec<char> = "PUBLIC".chars().collect();
-    if subtext.starts_with(&needle) {
+fn parse_int_subset_item(text :&str, start :usize) -> Result<IntSubsetItem, XmlError> {
+    let mut cause = match parse_ws(text, start) {
+        Ok(ws) => return Ok(IntSubsetItem::Blank(ws)),
+        Err(e) => e,
+    };
+    cause = match parse_pereference(text, start) {
+        Ok(peref) => return Ok(IntSubsetItem::PEReference { start, reference : peref }),
+        Err(e) => furthest(cause, e),
+    };
+    cause = match parse_elemdecl(text, start) {
+        Ok(elemdecl) => return Ok(IntSubsetItem::ElemDecl(elemdecl)),
+        Err(e) => furthest(cause, e),
+    };
+    cause = match parse_attlistdecl(text, start) {
+        Ok(attlist) => return Ok(IntSubsetItem::AttlistDecl(attlist)),
+        Err(e) => furthest(cause, e),
+    };
+    cause = match parse_entitydecl(text, start) {
+        Ok(entity) => return Ok(IntSubsetItem::EntityDecl(entity)),
+        Err(e) => furthest(cause, e),
+    };
+    cause = match parse_notationdecl(text, start) {
+        Ok(notation) => return Ok(IntSubsetItem::NotationDecl(notation)),
+        Err(e) => furthest(cause, e),
+    };
+    cause = match parse_pi(text, start) {
+        Ok(proc_instr) => return Ok(IntSubsetItem::ProcInstr(proc_instr)),
+        Err(e) => furthest(cause, e),
+    };
+    cause = match parse_comment(text, start) {
+        Ok(comment) => return Ok(IntSubsetItem::Comment(comment)),
+        Err(e) => furthest(cause, e),
+    };
+    Err(no_valid_variant(cause, start, "intSubset"))
+}
+
+fn parse_publicid(text :&str, start :usize) -> Result<PublicID, XmlError> {
+    let needle = "PUBLIC";
+    if rest(text, start).starts_with(needle) {
         let mut here = start + needle.len();
         let ws = parse_ws(text, here)?;
         here = ws.get_endpos();
         let pub_lit = parse_pubidlit(text, here)?;
         let pub_id = PublicID {
-            start : start,
+            start,
             end : here + pub_lit.len() + 2,
             text : pub_lit,
         };
         Ok(pub_id)
     } else {
-        Err(XmlError::KeywordMatchFail)
+        Err(XmlError::new(XmlErrorKind::KeywordMatchFail, start, "PublicID"))
     }
 }
 
-fn parse_notationdecl(text :&[char], start :usize) -> Result<NotationDecl, XmlError> {
-    let subtext = &text[start..];
-    let needle :Vec<char> = "<!NOTATION".chars().collect();
-    if subtext.starts_with(&needle) {
+fn parse_notationdecl(text :&str, start :usize) -> Result<NotationDecl, XmlError> {
+    let needle = "<!NOTATION";
+    if rest(text, start).starts_with(needle) {
         let mut here = start + needle.len();
         let spacer = parse_ws(text, here)?;
         here = spacer.get_endpos();
@@ -878,7 +1571,7 @@ fn parse_notationdecl(text :&[char], start :usize) -> Result<NotationDecl, XmlEr
             Ok(extid) => NotationID::External(extid),
             Err(_e) => match parse_publicid(text, here) {
                 Ok(pubid) => NotationID::Public(pubid),
-                Err(_e2) => {return Err(_e2);},
+                Err(_e2) => {return Err(_e2.within("NotationDecl"));},
             },
         };
         here = note_id.get_endpos();
@@ -887,45 +1580,397 @@ fn parse_notationdecl(text :&[char], start :usize) -> Result<NotationDecl, XmlEr
             Ok(ws) => {here = ws.get_endpos();},
             Err(_e) => (),
         };
-        let clast = text.get(here).ok_or(XmlError::TextEnd)?;
-        if clast == &'>' {
+        let clast = char_at(text, here, "NotationDecl")?;
+        if clast == '>' {
             let note_decl = NotationDecl {
-                start : start,
+                start,
                 end : here + 1,
-                name : name,
+                name,
                 notation_id : note_id,
             };
             Ok(note_decl)
         } else {
-            Err(XmlError::BadChar(*clast))
+            Err(XmlError::new(XmlErrorKind::BadChar(clast), here, "NotationDecl"))
+        }
+    } else {
+        Err(XmlError::new(XmlErrorKind::KeywordMatchFail, start, "NotationDecl"))
+    }
+}
+
+fn parse_attlistdecl(text :&str, start :usize) -> Result<AttlistDecl, XmlError> {
+    let needle = "<!ATTLIST";
+    if rest(text, start).starts_with(needle) {
+        let spacer1 = parse_ws(text, start + needle.len())?;
+        let name = parse_name(text, spacer1.get_endpos())?;
+        let context = format!("AttlistDecl `{}`", name);
+        let mut here = spacer1.get_endpos() + name.0.len();
+        let mut att_defs = Vec::new();
+        loop {
+            let def_start = match parse_ws(text, here) {
+                Ok(ws) => ws.get_endpos(),
+                Err(_e) => here,
+            };
+            let c = char_at(text, def_start, &context)?;
+            if c == '>' {
+                here = def_start + 1;
+                break;
+            }
+            // each attribute definition must be preceded by white space
+            if def_start == here {
+                return Err(XmlError::new(XmlErrorKind::BadChar(c), here, &context));
+            }
+            let att_def = parse_attdef(text, def_start).map_err(|e| e.within(&context))?;
+            here = att_def.get_endpos();
+            att_defs.push(att_def);
+        }
+        let attlist = AttlistDecl {
+            start,
+            end : here,
+            name,
+            att_defs,
+        };
+        Ok(attlist)
+    } else {
+        Err(XmlError::new(XmlErrorKind::KeywordMatchFail, start, "AttlistDecl"))
+    }
+}
+
+fn parse_attdef(text :&str, start :usize) -> Result<AttDef, XmlError> {
+    let name = parse_name(text, start).map_err(|e| e.within("AttDef"))?;
+    let context = format!("AttDef `{}`", name);
+    let spacer1 = parse_ws(text, start + name.0.len()).map_err(|e| e.within(&context))?;
+    let att_type = parse_atttype(text, spacer1.get_endpos()).map_err(|e| e.within(&context))?;
+    let spacer2 = parse_ws(text, att_type.end).map_err(|e| e.within(&context))?;
+    let default_decl = parse_defaultdecl(text, spacer2.get_endpos()).map_err(|e| e.within(&context))?;
+    let att_def = AttDef {
+        start,
+        end : default_decl.end,
+        name,
+        att_type : att_type.att_type,
+        default_decl : default_decl.default_decl,
+    };
+    Ok(att_def)
+}
+
+fn parse_atttype(text :&str, start :usize) -> Result<AttTypeHelper, XmlError> {
+    // longer keywords come first so `IDREFS` is not read as `ID`
+    let keywords = [
+        ("CDATA", AttType::CData),
+        ("IDREFS", AttType::IdRefs),
+        ("IDREF", AttType::IdRef),
+        ("ID", AttType::Id),
+        ("ENTITY", AttType::Entity),
+        ("ENTITIES", AttType::Entities),
+        ("NMTOKENS", AttType::NmTokens),
+        ("NMTOKEN", AttType::NmToken),
+    ];
+    let subtext = rest(text, start);
+    for (keyword, att_type) in keywords {
+        if subtext.starts_with(keyword) {
+            let helper = AttTypeHelper {
+                end : start + keyword.len(),
+                att_type,
+            };
+            return Ok(helper);
+        }
+    }
+    let needle = "NOTATION";
+    if subtext.starts_with(needle) {
+        let spacer = parse_ws(text, start + needle.len()).map_err(|e| e.within("NotationType"))?;
+        let (names, end) = parse_enumeration(text, spacer.get_endpos(), "NotationType", |text, start| {
+            parse_name(text, start).map(|name| name.0)
+        })?;
+        let helper = AttTypeHelper {
+            end,
+            att_type : AttType::Notation(names.into_iter().map(Name).collect()),
+        };
+        Ok(helper)
+    } else if subtext.starts_with('(') {
+        let (tokens, end) = parse_enumeration(text, start, "Enumeration", parse_nmtoken)?;
+        let helper = AttTypeHelper {
+            end,
+            att_type : AttType::Enumeration(tokens),
+        };
+        Ok(helper)
+    } else {
+        Err(XmlError::new(XmlErrorKind::KeywordMatchFail, start, "AttType"))
+    }
+}
+
+/// Parse a parenthesized, `|` separated list of tokens, as used by both
+/// `NotationType` and `Enumeration`, returning the tokens and the end position
+fn parse_enumeration(
+    text :&str,
+    start :usize,
+    context :&str,
+    token :fn(&str, usize) -> Result<String, XmlError>,
+) -> Result<(Vec<String>, usize), XmlError> {
+    let c0 = char_at(text, start, context)?;
+    if c0 != '(' {
+        return Err(XmlError::new(XmlErrorKind::BadChar(c0), start, context));
+    }
+    let mut here = start + 1;
+    let mut tokens = Vec::new();
+    loop {
+        if let Ok(ws) = parse_ws(text, here) {
+            here = ws.get_endpos();
+        }
+        let tok = token(text, here).map_err(|e| e.within(context))?;
+        here += tok.len();
+        tokens.push(tok);
+        if let Ok(ws) = parse_ws(text, here) {
+            here = ws.get_endpos();
+        }
+        let c = char_at(text, here, context)?;
+        match c {
+            '|' => here += 1,
+            ')' => return Ok((tokens, here + 1)),
+            _ => return Err(XmlError::new(XmlErrorKind::BadChar(c), here, context)),
+        };
+    }
+}
+
+fn parse_defaultdecl(text :&str, start :usize) -> Result<DefaultDeclHelper, XmlError> {
+    let subtext = rest(text, start);
+    let fixed = "#FIXED";
+    if subtext.starts_with("#REQUIRED") {
+        let helper = DefaultDeclHelper {
+            end : start + "#REQUIRED".len(),
+            default_decl : DefaultDecl::Required,
+        };
+        Ok(helper)
+    } else if subtext.starts_with("#IMPLIED") {
+        let helper = DefaultDeclHelper {
+            end : start + "#IMPLIED".len(),
+            default_decl : DefaultDecl::Implied,
+        };
+        Ok(helper)
+    } else if subtext.starts_with(fixed) {
+        let spacer = parse_ws(text, start + fixed.len()).map_err(|e| e.within("DefaultDecl"))?;
+        let value = parse_attvalue(text, spacer.get_endpos()).map_err(|e| e.within("DefaultDecl"))?;
+        let helper = DefaultDeclHelper {
+            end : value.get_endpos(),
+            default_decl : DefaultDecl::Fixed(value),
+        };
+        Ok(helper)
+    } else if subtext.starts_with('#') {
+        Err(XmlError::new(XmlErrorKind::KeywordMatchFail, start, "DefaultDecl"))
+    } else {
+        let value = parse_attvalue(text, start).map_err(|e| e.within("DefaultDecl"))?;
+        let helper = DefaultDeclHelper {
+            end : value.get_endpos(),
+            default_decl : DefaultDecl::Default(value),
+        };
+        Ok(helper)
+    }
+}
+
+fn parse_elemdecl(text :&str, start :usize) -> Result<ElemDecl, XmlError> {
+    let needle = "<!ELEMENT";
+    if rest(text, start).starts_with(needle) {
+        let spacer1 = parse_ws(text, start + needle.len())?;
+        let name = parse_name(text, spacer1.get_endpos())?;
+        let context = format!("elementdecl `{}`", name);
+        let spacer2 = parse_ws(text, spacer1.get_endpos() + name.0.len()).map_err(|e| e.within(&context))?;
+        let spec = parse_contentspec(text, spacer2.get_endpos()).map_err(|e| e.within(&context))?;
+        let mut here = spec.end;
+        if let Ok(ws) = parse_ws(text, here) {
+            here = ws.get_endpos();
+        }
+        let c_last = char_at(text, here, &context)?;
+        if c_last == '>' {
+            let elemdecl = ElemDecl {
+                start,
+                end : here + 1,
+                name,
+                content_spec : spec.spec,
+            };
+            Ok(elemdecl)
+        } else {
+            Err(XmlError::new(XmlErrorKind::BadChar(c_last), here, &context))
+        }
+    } else {
+        Err(XmlError::new(XmlErrorKind::KeywordMatchFail, start, "elementdecl"))
+    }
+}
+
+fn parse_contentspec(text :&str, start :usize) -> Result<ContentSpecHelper, XmlError> {
+    let subtext = rest(text, start);
+    if subtext.starts_with("EMPTY") {
+        let helper = ContentSpecHelper {
+            end : start + "EMPTY".len(),
+            spec : ContentSpec::Empty,
+        };
+        Ok(helper)
+    } else if subtext.starts_with("ANY") {
+        let helper = ContentSpecHelper {
+            end : start + "ANY".len(),
+            spec : ContentSpec::Any,
+        };
+        Ok(helper)
+    } else if subtext.starts_with('(') {
+        let pos = match parse_ws(text, start + 1) {
+            Ok(ws) => ws.get_endpos(),
+            Err(_e) => start + 1,
+        };
+        if rest(text, pos).starts_with("#PCDATA") {
+            let mixed = parse_mixed(text, start)?;
+            let helper = ContentSpecHelper {
+                end : mixed.get_endpos(),
+                spec : ContentSpec::Mixed(mixed),
+            };
+            Ok(helper)
+        } else {
+            let children = parse_children(text, start)?;
+            let helper = ContentSpecHelper {
+                end : children.get_endpos(),
+                spec : ContentSpec::Children(children),
+            };
+            Ok(helper)
         }
     } else {
-        Err(XmlError::KeywordMatchFail)
+        Err(XmlError::new(XmlErrorKind::KeywordMatchFail, start, "contentspec"))
     }
 }
 
-fn parse_attlistdecl(text :&[char], start :usize) -> Result<AttlistDecl, XmlError> {
-    unimplemented!();
+fn parse_mixed(text :&str, start :usize) -> Result<Mixed, XmlError> {
+    let c0 = char_at(text, start, "Mixed")?;
+    if c0 != '(' {
+        return Err(XmlError::new(XmlErrorKind::BadChar(c0), start, "Mixed"));
+    }
+    let mut here = start + 1;
+    if let Ok(ws) = parse_ws(text, here) {
+        here = ws.get_endpos();
+    }
+    let needle = "#PCDATA";
+    if !rest(text, here).starts_with(needle) {
+        return Err(XmlError::new(XmlErrorKind::KeywordMatchFail, here, "Mixed"));
+    }
+    here += needle.len();
+    let mut names = Vec::new();
+    loop {
+        if let Ok(ws) = parse_ws(text, here) {
+            here = ws.get_endpos();
+        }
+        let c = char_at(text, here, "Mixed")?;
+        if c == '|' {
+            here += 1;
+            if let Ok(ws) = parse_ws(text, here) {
+                here = ws.get_endpos();
+            }
+            let name = parse_name(text, here).map_err(|e| e.within("Mixed"))?;
+            here += name.0.len();
+            names.push(name);
+        } else if c == ')' {
+            here += 1;
+            break;
+        } else {
+            return Err(XmlError::new(XmlErrorKind::BadChar(c), here, "Mixed"));
+        }
+    }
+    // `(#PCDATA)` may omit the star, but a list of names must end with `)*`
+    if rest(text, here).starts_with('*') {
+        here += 1;
+    } else if !names.is_empty() {
+        let c = char_at(text, here, "Mixed")?;
+        return Err(XmlError::new(XmlErrorKind::BadChar(c), here, "Mixed"));
+    }
+    let mixed = Mixed {
+        start,
+        end : here,
+        names,
+    };
+    Ok(mixed)
 }
 
-fn parse_elemdecl(text :&[char], start :usize) -> Result<ElemDecl, XmlError> {
-    unimplemented!();
+fn parse_children(text :&str, start :usize) -> Result<ContentParticle, XmlError> {
+    let c0 = char_at(text, start, "children")?;
+    if c0 == '(' {
+        parse_cp(text, start, 0)
+    } else {
+        Err(XmlError::new(XmlErrorKind::BadChar(c0), start, "children"))
+    }
 }
 
-fn parse_entitydecl(text :&[char], start :usize) -> Result<EntityDecl, XmlError> {
-    if let Ok(gedecl) = parse_gedecl(text, start) {
-        Ok(EntityDecl::GEDecl(gedecl))
-    } else if let Ok(pedecl) = parse_pedecl(text, start) {
-        Ok(EntityDecl::PEDecl(pedecl))
+fn parse_cp(text :&str, start :usize, recurdepth :usize) -> Result<ContentParticle, XmlError> {
+    if recurdepth > MAX_RECUR_DEPTH {
+        return Err(XmlError::new(XmlErrorKind::MaxRecurDepth(recurdepth as u32), start, "cp"));
+    }
+    let c0 = char_at(text, start, "cp")?;
+    let (particle, mut here) = if c0 == '(' {
+        let mut here = start + 1;
+        let mut items = Vec::new();
+        let mut separator = None;
+        loop {
+            if let Ok(ws) = parse_ws(text, here) {
+                here = ws.get_endpos();
+            }
+            let item = parse_cp(text, here, recurdepth + 1)?;
+            here = item.get_endpos();
+            items.push(item);
+            if let Ok(ws) = parse_ws(text, here) {
+                here = ws.get_endpos();
+            }
+            let c = char_at(text, here, "cp")?;
+            match (c, separator) {
+                (')', _) => {
+                    here += 1;
+                    break;
+                }
+                ('|', None) | (',', None) => {
+                    separator = Some(c);
+                    here += 1;
+                }
+                ('|', Some('|')) | (',', Some(',')) => {
+                    here += 1;
+                }
+                _ => {
+                    let context = if separator == Some('|') { "choice" } else { "seq" };
+                    return Err(XmlError::new(XmlErrorKind::BadChar(c), here, context));
+                }
+            };
+        }
+        match separator {
+            Some('|') => (Particle::Choice(items), here),
+            _ => (Particle::Seq(items), here),
+        }
     } else {
-        Err(XmlError::NoValidVariant)
+        let name = parse_name(text, start).map_err(|e| e.within("cp"))?;
+        let here = start + name.0.len();
+        (Particle::Name(name), here)
+    };
+    let repeat = match rest(text, here).chars().next() {
+        Some('?') => Repetition::Optional,
+        Some('*') => Repetition::ZeroOrMore,
+        Some('+') => Repetition::OneOrMore,
+        _ => Repetition::Once,
+    };
+    if repeat != Repetition::Once {
+        here += 1;
+    }
+    let cp = ContentParticle {
+        start,
+        end : here,
+        particle,
+        repeat,
+    };
+    Ok(cp)
+}
+
+fn parse_entitydecl(text :&str, start :usize) -> Result<EntityDecl, XmlError> {
+    let cause = match parse_gedecl(text, start) {
+        Ok(gedecl) => return Ok(EntityDecl::GEDecl(gedecl)),
+        Err(e) => e,
+    };
+    match parse_pedecl(text, start) {
+        Ok(pedecl) => Ok(EntityDecl::PEDecl(pedecl)),
+        Err(e) => Err(no_valid_variant(furthest(cause, e), start, "EntityDecl")),
     }
 }
 
-fn parse_gedecl(text :&[char], start :usize) -> Result<GEDecl, XmlError> {
-    let subtext = &text[start..];
-    let needle :Vec<char> = "<!ENTITY".chars().collect();
-    if subtext.starts_with(&needle) {
+fn parse_gedecl(text :&str, start :usize) -> Result<GEDecl, XmlError> {
+    let needle = "<!ENTITY";
+    if rest(text, start).starts_with(needle) {
         let spacer1 = parse_ws(text, start + needle.len())?;
         let name = parse_name(text, spacer1.get_endpos())?;
         let spacer2 = parse_ws(text, spacer1.get_endpos() + name.0.len())?;
@@ -936,30 +1981,29 @@ fn parse_gedecl(text :&[char], start :usize) -> Result<GEDecl, XmlError> {
             Ok(ws) => {here = ws.get_endpos();},
             Err(_e) => (),
         };
-        let c_last = *text.get(here).ok_or(XmlError::TextEnd)?;
+        let c_last = char_at(text, here, "GEDecl")?;
         if c_last == '>' {
             let gedecl = GEDecl {
-                start : start,
+                start,
                 end : here + 1,
-                name : name,
-                entity_def : entity_def,
+                name,
+                entity_def,
             };
             Ok(gedecl)
         } else {
-            Err(XmlError::BadChar(c_last))
+            Err(XmlError::new(XmlErrorKind::BadChar(c_last), here, "GEDecl"))
         }
     } else {
-        Err(XmlError::KeywordMatchFail)
+        Err(XmlError::new(XmlErrorKind::KeywordMatchFail, start, "GEDecl"))
     }
 }
 
 
-fn parse_pedecl(text :&[char], start :usize) -> Result<PEDecl, XmlError> {
-    let subtext = &text[start..];
-    let needle :Vec<char> = "<!ENTITY".chars().collect();
-    if subtext.starts_with(&needle) {
+fn parse_pedecl(text :&str, start :usize) -> Result<PEDecl, XmlError> {
+    let needle = "<!ENTITY";
+    if rest(text, start).starts_with(needle) {
         let spacer1 = parse_ws(text, start + needle.len())?;
-        let c0 = *text.get(spacer1.get_endpos()).ok_or(XmlError::TextEnd)?;
+        let c0 = char_at(text, spacer1.get_endpos(), "PEDecl")?;
         if c0 == '%' {
             let spacer2 = parse_ws(text, spacer1.get_endpos() +1)?;
             let name = parse_name(text, spacer2.get_endpos())?;
@@ -971,229 +2015,210 @@ fn parse_pedecl(text :&[char], start :usize) -> Result<PEDecl, XmlError> {
                 Ok(ws) => {here = ws.get_endpos();},
                 Err(_e) => (),
             };
-            let c_last = *text.get(here).ok_or(XmlError::TextEnd)?;
+            let c_last = char_at(text, here, "PEDecl")?;
             if c_last == '>' {
                 let pe_decl = PEDecl {
-                    start : start,
+                    start,
                     end : here + 1,
-                    name : name,
+                    name,
                     pedef : pe_def,
                 };
                 Ok(pe_decl)
             } else {
-                Err(XmlError::BadChar(c_last))
+                Err(XmlError::new(XmlErrorKind::BadChar(c_last), here, "PEDecl"))
             }
         } else {
-            Err(XmlError::BadChar(c0))
+            Err(XmlError::new(XmlErrorKind::BadChar(c0), spacer1.get_endpos(), "PEDecl"))
         }
     } else {
-        Err(XmlError::KeywordMatchFail)
+        Err(XmlError::new(XmlErrorKind::KeywordMatchFail, start, "PEDecl"))
     }
 }
 
-fn parse_entitydef(text :&[char], start :usize) -> Result<EntityDef, XmlError> {
-    if let Ok(ent_val) = parse_entityvalue(text, start) {
-        Ok(EntityDef::EntityValue(ent_val))
-    } else {
-        let ext_id = parse_externalid(text, start)?;
-        let pos = ext_id.get_endpos();
-        let maybe_ndatadecl = parse_ndatadecl(text, pos);
-        match maybe_ndatadecl {
-            Ok(ndata) => {
-                let entity_def = EntityDef::External {
-                    ext_id : ext_id,
-                    ndatadecl : Some(ndata),
-                };
-                Ok(entity_def)
-            },
-            Err(_e) => {
-                let entity_def = EntityDef::External {
-                    ext_id : ext_id,
-                    ndatadecl : None,
-                };
-                Ok(entity_def)
-            }
+fn parse_entitydef(text :&str, start :usize) -> Result<EntityDef, XmlError> {
+    let cause = match parse_entityvalue(text, start) {
+        Ok(ent_val) => return Ok(EntityDef::EntityValue(ent_val)),
+        Err(e) => e,
+    };
+    let ext_id = parse_externalid(text, start).map_err(|e| no_valid_variant(furthest(cause, e), start, "EntityDef"))?;
+    let pos = ext_id.get_endpos();
+    let maybe_ndatadecl = parse_ndatadecl(text, pos);
+    match maybe_ndatadecl {
+        Ok(ndata) => {
+            let entity_def = EntityDef::External {
+                ext_id,
+                ndatadecl : Some(ndata),
+            };
+            Ok(entity_def)
+        },
+        Err(_e) => {
+            let entity_def = EntityDef::External {
+                ext_id,
+                ndatadecl : None,
+            };
+            Ok(entity_def)
         }
     }
 }
 
-fn parse_pedef(text :&[char], start :usize) -> Result<PEDef, XmlError> {
-    if let Ok(ent_val) = parse_entityvalue(text, start) {
-        Ok(PEDef::EntityValue(ent_val))
-    } else if let Ok(ext_id) = parse_externalid(text, start) {
-        Ok(PEDef::ExternalID(ext_id))
-    } else {
-        Err(XmlError::NoValidVariant)
+fn parse_pedef(text :&str, start :usize) -> Result<PEDef, XmlError> {
+    let cause = match parse_entityvalue(text, start) {
+        Ok(ent_val) => return Ok(PEDef::EntityValue(ent_val)),
+        Err(e) => e,
+    };
+    match parse_externalid(text, start) {
+        Ok(ext_id) => Ok(PEDef::ExternalID(ext_id)),
+        Err(e) => Err(no_valid_variant(furthest(cause, e), start, "PEDef")),
     }
 }
 
-fn parse_entityvalue(text :&[char], start :usize) -> Result<EntityValue, XmlError> {
+fn parse_entityvalue(text :&str, start :usize) -> Result<EntityValue, XmlError> {
     let mut arena = String::new();
-    let c0 = *text.get(start).ok_or(XmlError::TextEnd)?;
-    if c0 == '\"' {
+    let c0 = char_at(text, start, "EntityValue")?;
+    if c0 == '\"' || c0 == '\'' {
         let mut here = start + 1;
-        while let Ok(c) = text.get(here).ok_or(XmlError::TextEnd) {
-            match c {
-                '\"' => {
-                    let evalue = EntityValue {
-                        start : start,
-                        text :arena
-                    };
-                    return Ok(evalue);
-                },
-                _ => {
-                    arena.push(*c);
-                }
-            }
-            here += 1;
-        }
-        Err(XmlError::TextEnd)
-    } else if c0 == '\'' {
-        let mut here = start + 1;
-        while let Ok(c) = text.get(here).ok_or(XmlError::TextEnd) {
-            match c {
-                '\'' => {
+        loop {
+            let c = char_at(text, here, "EntityValue")?;
+            let len = match c {
+                _ if c == c0 => {
                     let evalue = EntityValue {
-                        start : start,
-                        text : arena
+                        start,
+                        text : arena,
                     };
                     return Ok(evalue);
-                },
-                _ => {
-                    arena.push(*c);
                 }
-            }
-            here += 1;
+                '&' => parse_reference(text, here).map_err(|e| e.within("EntityValue"))?.text_len(),
+                '%' => parse_pereference(text, here).map_err(|e| e.within("EntityValue"))?.textlen(),
+                _ if !is_xml_char(c) => return Err(XmlError::new(XmlErrorKind::BadChar(c), here, "EntityValue")),
+                _ => c.len_utf8(),
+            };
+            arena.push_str(&text[here..here + len]);
+            here += len;
         }
-        Err(XmlError::TextEnd)
     } else {
-        Err(XmlError::BadChar(c0))
+        Err(XmlError::new(XmlErrorKind::BadChar(c0), start, "EntityValue"))
     }
 }
 
-fn parse_pereference(text :&[char], start :usize) -> Result<PEReference, XmlError> {
-    let c0 = *text.get(start).ok_or(XmlError::TextEnd)?;
+fn parse_pereference(text :&str, start :usize) -> Result<PEReference, XmlError> {
+    let c0 = char_at(text, start, "PEReference")?;
     if c0 == '%' {
         let pos = start + 1;
         let name = parse_name(text, pos)?;
         let pos1 = pos + name.0.len();
-        let c1 = *text.get(pos1).ok_or(XmlError::TextEnd)?;
+        let c1 = char_at(text, pos1, "PEReference")?;
         if c1 == ';' {
             let peref = PEReference(name);
             Ok(peref)
         } else {
-            Err(XmlError::BadChar(c1))
+            Err(XmlError::new(XmlErrorKind::BadChar(c1), pos1, "PEReference"))
         }
     } else {
-        Err(XmlError::BadChar(c0))
+        Err(XmlError::new(XmlErrorKind::BadChar(c0), start, "PEReference"))
     }
 }
 
-fn parse_tail(text: &[char], start: usize) -> Result<Vec<Misc>, XmlError> {
+fn parse_tail(text: &str, start: usize) -> Result<Vec<Misc>, XmlError> {
     let mut buf = Vec::new();
     let mut pos = start;
-    let mut maybe_misc = parse_misc(text, pos);
-    while let Ok(misc) = maybe_misc {
+    // only Misc may follow the root element, so anything else is an error
+    while pos < text.len() {
+        let misc = parse_misc(text, pos)?;
         pos = misc.get_endpos();
         buf.push(misc);
-        maybe_misc = parse_misc(text, pos);
-    }
-    if let Err(xml_err) = maybe_misc {
-        match xml_err {
-            XmlError::TextEnd => Ok(buf),
-            _ => Err(xml_err),
-        }
-    } else {
-        unreachable!("Should always exhaust XML tail");
     }
+    Ok(buf)
 }
 
-fn parse_misc(text: &[char], start: usize) -> Result<Misc, XmlError> {
-    if let Ok(ws) = parse_ws(text, start) {
-        Ok(Misc::Ws(ws))
-    } else if let Ok(comment) = parse_comment(text, start) {
-        Ok(Misc::Comment(comment))
-    } else if let Ok(pi) = parse_pi(text, start) {
-        Ok(Misc::ProcInstr(pi))
-    } else if text.get(start).is_none() {
-        Err(XmlError::TextEnd)
-    } else {
-        Err(XmlError::NoValidVariant)
-    }
+/// Whether the text at `start` can only be a Misc, so that failing to parse
+/// one there is an error rather than the end of a run of Misc
+fn starts_misc(text: &str, start: usize) -> bool {
+    let subtext = rest(text, start);
+    subtext.starts_with("<!--") || subtext.starts_with("<?") || subtext.starts_with([' ', '\t', '\r', '\n'])
 }
 
-fn parse_comment(text: &[char], start: usize) -> Result<Comment, XmlError> {
-    let char0 = text.get(start).ok_or(XmlError::TextEnd)?;
-    if char0 == &'<' {
-        let char1 = text.get(start + 1).ok_or(XmlError::TextEnd)?;
-        if char1 == &'!' {
-            let char2 = text.get(start + 2).ok_or(XmlError::TextEnd)?;
-            let char3 = text.get(start + 3).ok_or(XmlError::TextEnd)?;
-            if char2 == &'-' && char3 == &'-' {
+fn parse_misc(text: &str, start: usize) -> Result<Misc, XmlError> {
+    if start >= text.len() {
+        return Err(XmlError::new(XmlErrorKind::TextEnd, start, "Misc"));
+    }
+    let mut cause = match parse_ws(text, start) {
+        Ok(ws) => return Ok(Misc::Ws(ws)),
+        Err(e) => e,
+    };
+    cause = match parse_comment(text, start) {
+        Ok(comment) => return Ok(Misc::Comment(comment)),
+        Err(e) => furthest(cause, e),
+    };
+    cause = match parse_pi(text, start) {
+        Ok(pi) => return Ok(Misc::ProcInstr(pi)),
+        Err(e) => furthest(cause, e),
+    };
+    Err(no_valid_variant(cause, start, "Misc"))
+}
+
+fn parse_comment(text: &str, start: usize) -> Result<Comment, XmlError> {
+    let char0 = char_at(text, start, "Comment")?;
+    if char0 == '<' {
+        let char1 = char_at(text, start + 1, "Comment")?;
+        if char1 == '!' {
+            let char2 = char_at(text, start + 2, "Comment")?;
+            let char3 = char_at(text, start + 3, "Comment")?;
+            if char2 == '-' && char3 == '-' {
+                let body = start + 4;
                 let mut buf = String::new();
                 let mut count = 0;
-                for c in &text[(start + 4)..] {
+                for (offset, c) in rest(text, body).char_indices() {
                     match c {
+                        _ if !is_xml_char(c) => {
+                            return Err(XmlError::new(XmlErrorKind::BadChar(c), body + offset, "Comment"));
+                        }
                         '-' => {
                             count += 1;
                         }
                         '>' => {
                             if count == 2 {
-                                match buf.pop() {
-                                    Some('-') => (),
-                                    Some(c) => {
-                                        return Err(XmlError::BadChar(c));
-                                    }
-                                    None => return Err(XmlError::TextEnd),
-                                };
-                                match buf.pop() {
-                                    Some('-') => (),
-                                    Some(c) => {
-                                        return Err(XmlError::BadChar(c));
-                                    }
-                                    None => return Err(XmlError::TextEnd),
-                                };
+                                // the two dashes are already in the buffer
+                                buf.pop();
+                                buf.pop();
                                 let comment = Comment {
-                                    start: start,
+                                    start,
                                     text: buf,
                                 };
                                 return Ok(comment);
                             } else if count > 2 {
-                                return Err(XmlError::IllegalSubstr);
+                                return Err(XmlError::new(XmlErrorKind::IllegalSubstr, body + offset - count, "Comment"));
                             } else {
                                 count = 0;
                             }
                         }
                         _ => {
                             if count >= 2 {
-                                return Err(XmlError::IllegalSubstr);
+                                return Err(XmlError::new(XmlErrorKind::IllegalSubstr, body + offset - count, "Comment"));
                             }
                             count = 0;
                         }
                     };
-                    buf.push(*c);
+                    buf.push(c);
                 }
-                Err(XmlError::TextEnd)
+                Err(XmlError::new(XmlErrorKind::TextEnd, text.len(), "Comment"))
+            } else if char2 == '-' {
+                Err(XmlError::new(XmlErrorKind::BadChar(char3), start + 3, "Comment"))
             } else {
-                if char2 == &'-' {
-                    Err(XmlError::BadChar(*char3))
-                } else {
-                    Err(XmlError::BadChar(*char2))
-                }
+                Err(XmlError::new(XmlErrorKind::BadChar(char2), start + 2, "Comment"))
             }
         } else {
-            Err(XmlError::BadChar(*char1))
+            Err(XmlError::new(XmlErrorKind::BadChar(char1), start + 1, "Comment"))
         }
     } else {
-        Err(XmlError::BadChar(*char0))
+        Err(XmlError::new(XmlErrorKind::BadChar(char0), start, "Comment"))
     }
 }
 
-fn parse_pi(text: &[char], start: usize) -> Result<ProcInstr, XmlError> {
-    let char0 = text.get(start).ok_or(XmlError::TextEnd)?;
-    if char0 == &'<' {
-        let char1 = text.get(start + 1).ok_or(XmlError::TextEnd)?;
-        if char1 == &'?' {
+fn parse_pi(text: &str, start: usize) -> Result<ProcInstr, XmlError> {
+    let char0 = char_at(text, start, "PI")?;
+    if char0 == '<' {
+        let char1 = char_at(text, start + 1, "PI")?;
+        if char1 == '?' {
             let target = parse_pitarget(text, start + 2)?;
             let target_end = start + target.name.0.len() + 2;
             let maybe_blank = parse_ws(text, target_end);
@@ -1202,81 +2227,95 @@ fn parse_pi(text: &[char], start: usize) -> Result<ProcInstr, XmlError> {
                     let blank_end = ws.get_endpos();
                     let mut buf = String::new();
                     let mut seen = false;
-                    for c in &text[blank_end..] {
-                        match *c {
+                    for (offset, c) in rest(text, blank_end).char_indices() {
+                        match c {
+                            _ if !is_xml_char(c) => {
+                                return Err(XmlError::new(XmlErrorKind::BadChar(c), blank_end + offset, "PI"));
+                            }
                             '?' => {
                                 seen = true;
                             }
-                            '>' => {
-                                if seen {
-                                    let last = buf.pop().ok_or(XmlError::TextEnd)?;
-                                    if last == '?' {
-                                        let pi = ProcInstr {
-                                            start: start,
-                                            target: target,
-                                            space: Some(ws),
-                                            arg: Some(buf),
-                                        };
-                                        return Ok(pi);
-                                    } else {
-                                        return Err(XmlError::BadChar(last));
-                                    }
-                                }
+                            '>' if seen => {
+                                // drop the '?' of the closing delimiter
+                                buf.pop();
+                                let pi = ProcInstr {
+                                    start,
+                                    target,
+                                    space: Some(ws),
+                                    arg: Some(buf),
+                                };
+                                return Ok(pi);
                             }
                             _ => {
                                 seen = false;
                             }
                         };
-                        buf.push(*c);
+                        buf.push(c);
                     }
-                    Err(XmlError::TextEnd)
+                    Err(XmlError::new(XmlErrorKind::TextEnd, text.len(), "PI"))
                 }
-                Err(xml_err) => match xml_err {
-                    XmlError::BadChar('?') => {
-                        let charlast = text.get(target_end + 1).ok_or(XmlError::TextEnd)?;
-                        if charlast == &'>' {
+                Err(xml_err) => match xml_err.kind() {
+                    XmlErrorKind::BadChar('?') => {
+                        let charlast = char_at(text, target_end + 1, "PI")?;
+                        if charlast == '>' {
                             let pi = ProcInstr {
-                                start: start,
-                                target: target,
+                                start,
+                                target,
                                 space: None,
                                 arg: None,
                             };
                             Ok(pi)
                         } else {
-                            Err(XmlError::BadChar(*charlast))
+                            Err(XmlError::new(XmlErrorKind::BadChar(charlast), target_end + 1, "PI"))
                         }
                     }
-                    _ => Err(xml_err),
+                    _ => Err(xml_err.within("PI")),
                 },
             }
         } else {
-            Err(XmlError::BadChar(*char1))
+            Err(XmlError::new(XmlErrorKind::BadChar(char1), start + 1, "PI"))
         }
     } else {
-        Err(XmlError::BadChar(*char0))
+        Err(XmlError::new(XmlErrorKind::BadChar(char0), start, "PI"))
     }
 }
 
-fn parse_pitarget(text: &[char], start: usize) -> Result<PITarget, XmlError> {
+fn parse_pitarget(text: &str, start: usize) -> Result<PITarget, XmlError> {
     let name = parse_name(text, start)?;
     if name.0.to_lowercase() == "xml" {
-        Err(XmlError::ReservedNameXml)
+        Err(XmlError::new(XmlErrorKind::ReservedNameXml, start, "PITarget"))
     } else {
-        let target = PITarget { name: name };
+        let target = PITarget { name };
         Ok(target)
     }
 }
 
+/// Whether `c` matches the `Char` production, the characters allowed in a document
+fn is_xml_char(c: char) -> bool {
+    matches!(
+        c as u32,
+        0x9 | 0xA | 0xD | 0x20..=0xD7FF | 0xE000..=0xFFFD | 0x10000..=0x10FFFF
+    )
+}
+
 fn is_namestart(c: char) -> bool {
     match c {
         ':' | '_' | 'a'..='z' | 'A'..='Z' => true,
-        _ => match c as u32 {
-            0xC0..=0xD6 | 0xD8..=0xF6 | 0xF8..=0x2FF | 0x370..=0x37D => true,
-            0x37F..=0x1FFF | 0x200C..=0x200D | 0x2070..=0x218F | 0x2C00..=0x2FEF => true,
-            0x3001..=0xD7FF => true,
-            0xF900..=0xFDCF | 0xFDF0..=0xFFFD | 0x10000..=0xEFFFF => true,
-            _ => false,
-        },
+        _ => matches!(
+            c as u32,
+            0xC0..=0xD6
+                | 0xD8..=0xF6
+                | 0xF8..=0x2FF
+                | 0x370..=0x37D
+                | 0x37F..=0x1FFF
+                | 0x200C..=0x200D
+                | 0x2070..=0x218F
+                | 0x2C00..=0x2FEF
+                | 0x3001..=0xD7FF
+                | 0xF900..=0xFDCF
+                | 0xFDF0..=0xFFFD
+                | 0x10000..=0xEFFFF
+        ),
     }
 }
 
@@ -1286,141 +2325,137 @@ fn is_namec(c: char) -> bool {
     } else {
         match c {
             '-' | '.' | '0'..='9' => true,
-            _ => match c as u32 {
-                0xB7 | 0x300..=0x36F | 0x203F..=0x2040 => true,
-                _ => false,
-            },
+            _ => matches!(c as u32, 0xB7 | 0x300..=0x36F | 0x203F..=0x2040),
         }
     }
 }
 
-fn parse_name(text: &[char], start: usize) -> Result<Name, XmlError> {
+fn parse_name(text: &str, start: usize) -> Result<Name, XmlError> {
     let mut buf = String::new();
-    let c0 = text.get(start).ok_or(XmlError::TextEnd)?;
-    if is_namestart(*c0) {
-        buf.push(*c0);
-        for c in &text[(start + 1)..] {
-            if is_namec(*c) {
-                buf.push(*c);
+    let c0 = char_at(text, start, "Name")?;
+    if is_namestart(c0) {
+        buf.push(c0);
+        for c in rest(text, start + c0.len_utf8()).chars() {
+            if is_namec(c) {
+                buf.push(c);
             } else {
                 break;
             }
         }
         Ok(Name(buf))
     } else {
-        Err(XmlError::BadChar(*c0))
+        Err(XmlError::new(XmlErrorKind::BadChar(c0), start, "Name"))
     }
 }
 
-fn parse_ws(text: &[char], start: usize) -> Result<Ws, XmlError> {
-    let char0 = match text.get(start) {
-        Some(c) => c,
-        None => {
-            return Err(XmlError::TextEnd);
-        }
-    };
+fn parse_nmtoken(text: &str, start: usize) -> Result<String, XmlError> {
+    let buf: String = rest(text, start).chars().take_while(|c| is_namec(*c)).collect();
+    if buf.is_empty() {
+        let c0 = char_at(text, start, "Nmtoken")?;
+        Err(XmlError::new(XmlErrorKind::BadChar(c0), start, "Nmtoken"))
+    } else {
+        Ok(buf)
+    }
+}
+
+fn parse_ws(text: &str, start: usize) -> Result<Ws, XmlError> {
+    let char0 = char_at(text, start, "S")?;
     match char0 {
         ' ' | '\t' | '\n' | '\r' => {
             let mut buf = String::new();
-            buf.push(*char0);
-            for c in &text[(start + 1)..] {
+            buf.push(char0);
+            for c in rest(text, start + 1).chars() {
                 match c {
                     ' ' | '\t' | '\n' | '\r' => {
-                        buf.push(*c);
+                        buf.push(c);
                     }
                     _ => break,
                 };
             }
-            let ws = Ws {
-                start: start,
-                text: buf,
-            };
+            let ws = Ws { start, text: buf };
             Ok(ws)
         }
-        _ => {
-            return Err(XmlError::BadChar(*char0));
-        }
+        _ => Err(XmlError::new(XmlErrorKind::BadChar(char0), start, "S")),
     }
 }
 
-fn parse_elem(text: &[char], start: usize, recurdepth: usize) -> Result<Elem, XmlError> {
-    let maybe_empty = parse_empty_elem(text, start);
-    match maybe_empty {
+/// Parse an element nested inside `depth` others, failing if that would
+/// nest it more than `max_depth` deep
+fn parse_elem(text: &str, start: usize, depth: usize, max_depth: usize) -> Result<Elem, XmlError> {
+    if depth >= max_depth {
+        return Err(XmlError::new(XmlErrorKind::MaxRecurDepth(depth as u32 + 1), start, "element"));
+    }
+    match parse_empty_elem(text, start) {
         Ok(empty) => Ok(Elem::Empty(empty)),
-        Err(e) => match e {
-            XmlError::TextEnd => Err(e),
-            _ => {
-                let maybe_full = parse_full_elem(text, start, recurdepth + 1);
-                match maybe_full {
-                    Ok(full) => Ok(Elem::Full(full)),
-                    Err(e) => Err(e),
-                }
-            }
-        },
+        Err(e) if matches!(e.kind(), XmlErrorKind::TextEnd) => Err(e),
+        Err(_) => parse_full_elem(text, start, depth, max_depth).map(Elem::Full),
     }
 }
 
-fn parse_empty_elem(text: &[char], start: usize) -> Result<EmptyElem, XmlError> {
-    let c0 = text.get(start).ok_or(XmlError::TextEnd)?;
-    if c0 == &'<' {
+fn parse_empty_elem(text: &str, start: usize) -> Result<EmptyElem, XmlError> {
+    let c0 = char_at(text, start, "EmptyElemTag")?;
+    if c0 == '<' {
         let name = parse_name(text, start + 1)?;
+        let context = format!("EmptyElemTag `{}`", name);
         let pos = start + 1 + name.0.len();
-        let c1 = text.get(pos).ok_or(XmlError::TextEnd)?;
-        if c1 == &'/' {
-            let c2 = text.get(pos + 1).ok_or(XmlError::TextEnd)?;
-            if c2 == &'>' {
+        let c1 = char_at(text, pos, &context)?;
+        if c1 == '/' {
+            let c2 = char_at(text, pos + 1, &context)?;
+            if c2 == '>' {
                 let empty = EmptyElem {
-                    start: start,
+                    start,
                     end: pos + 2,
-                    name: name,
+                    name,
                     attribs: Vec::new(),
+                    namespace: None,
                 };
                 Ok(empty)
             } else {
-                Err(XmlError::BadChar(*c2))
+                Err(XmlError::new(XmlErrorKind::BadChar(c2), pos + 1, &context))
             }
         } else {
             let mut here = pos;
             let mut attribs = Vec::new();
-            while text.get(here).ok_or(XmlError::TextEnd)? != &'/' {
-                let blank = parse_ws(text, here)?;
+            while char_at(text, here, &context)? != '/' {
+                let blank = parse_ws(text, here).map_err(|e| e.within(&context))?;
                 here = blank.get_endpos();
                 let maybe_attrib = parse_attribute(text, here);
                 match maybe_attrib {
                     Ok(attrib) => {
                         here = attrib.get_endpos();
-                        attribs.push(attrib);
+                        push_attribute(&mut attribs, attrib, &context)?;
                     }
-                    Err(e) => match e {
-                        XmlError::BadChar('/') => break,
-                        _ => return Err(e),
+                    Err(e) => match e.kind() {
+                        XmlErrorKind::BadChar('/') if e.doc_idx() == here => break,
+                        _ => return Err(e.within(&context)),
                     },
                 };
             }
-            let c_here = text.get(here).ok_or(XmlError::TextEnd)?;
-            if c_here == &'/' {
-                let c_last = text.get(here + 1).ok_or(XmlError::TextEnd)?;
-                if c_last == &'>' {
+            let c_here = char_at(text, here, &context)?;
+            if c_here == '/' {
+                let c_last = char_at(text, here + 1, &context)?;
+                if c_last == '>' {
                     let empty = EmptyElem {
-                        name: name,
-                        start: start,
+                        name,
+                        start,
                         end: here + 2,
-                        attribs: attribs,
+                        attribs,
+                        namespace: None,
                     };
                     Ok(empty)
                 } else {
-                    Err(XmlError::BadChar(*c_last))
+                    Err(XmlError::new(XmlErrorKind::BadChar(c_last), here + 1, &context))
                 }
             } else {
-                Err(XmlError::BadChar(*c_here))
+                Err(XmlError::new(XmlErrorKind::BadChar(c_here), here, &context))
             }
         }
     } else {
-        Err(XmlError::BadChar(*c0))
+        Err(XmlError::new(XmlErrorKind::BadChar(c0), start, "EmptyElemTag"))
     }
 }
 
-fn parse_attribute(text: &[char], start: usize) -> Result<Attribute, XmlError> {
+fn parse_attribute(text: &str, start: usize) -> Result<Attribute, XmlError> {
     let name = parse_name(text, start)?;
     let pos = start + name.0.len();
     let maybe_space1 = parse_ws(text, pos);
@@ -1428,8 +2463,8 @@ fn parse_attribute(text: &[char], start: usize) -> Result<Attribute, XmlError> {
         Ok(ws) => ws.get_endpos(),
         Err(_e) => pos,
     };
-    let echar = text.get(pos1).ok_or(XmlError::TextEnd)?;
-    if *echar == '=' {
+    let echar = char_at(text, pos1, "Attribute")?;
+    if echar == '=' {
         let maybe_space2 = parse_ws(text, pos1 + 1);
         let pos2 = match maybe_space2 {
             Ok(ws) => ws.get_endpos(),
@@ -1437,261 +2472,250 @@ fn parse_attribute(text: &[char], start: usize) -> Result<Attribute, XmlError> {
         };
         let value = parse_attvalue(text, pos2)?;
         let attribute = Attribute {
-            start: start,
+            start,
             end: value.get_endpos(),
-            name: name,
-            value: value,
+            name,
+            value,
+            specified: true,
+            tokenized: false,
+            namespace: None,
+            version: XmlVersion::V1_0,
         };
         Ok(attribute)
     } else {
-        Err(XmlError::BadChar(*echar))
+        Err(XmlError::new(XmlErrorKind::BadChar(echar), pos1, "Attribute"))
     }
 }
 
-fn parse_full_elem(text: &[char], start: usize, recurdepth: usize) -> Result<FullElem, XmlError> {
+fn parse_full_elem(text: &str, start: usize, depth: usize, max_depth: usize) -> Result<FullElem, XmlError> {
     let start = parse_starttag(text, start)?;
     let pos = start.get_endpos();
-    let maybe_content = parse_content(text, pos, recurdepth + 1);
-    let mut pos2 = pos;
-    let content = match maybe_content {
-        Ok(content) => {
-            pos2 = content.get_endpos();
-            Some(content)
-        }
-        Err(_e) => None,
+    let content = parse_content(text, pos, depth + 1, max_depth)?;
+    let pos2 = content.get_endpos();
+    let content = if content.items.is_empty() {
+        None
+    } else {
+        Some(content)
     };
     let etag = parse_endtag(text, pos2)?;
     if start.name.0 != etag.name.0 {
-        Err(XmlError::MismatchedTags(start.name.0, etag.name.0))
+        Err(XmlError::new(
+            XmlErrorKind::MismatchedTags(start.name.0, etag.name.0),
+            etag.start,
+            "ETag",
+        )
+        .with_related(start.start))
     } else {
         let full = FullElem {
-            start: start,
-            content: content,
+            start,
+            content,
             end: etag,
         };
         Ok(full)
     }
 }
 
-fn parse_starttag(text: &[char], start: usize) -> Result<STag, XmlError> {
-    let c0 = *text.get(start).ok_or(XmlError::TextEnd)?;
+/// Add an attribute parsed from a tag to those before it, checking the
+/// Unique Att Spec constraint that no name appears twice in the same tag
+fn push_attribute(attribs: &mut Vec<Attribute>, attrib: Attribute, context: &str) -> Result<(), XmlError> {
+    if let Some(first) = attribs.iter().find(|prev| prev.name() == attrib.name()) {
+        let kind = XmlErrorKind::DuplicateAttribute(String::from(attrib.name()));
+        return Err(XmlError::new(kind, attrib.start, context).with_related(first.start));
+    }
+    attribs.push(attrib);
+    Ok(())
+}
+
+fn parse_starttag(text: &str, start: usize) -> Result<STag, XmlError> {
+    let c0 = char_at(text, start, "STag")?;
     if c0 == '<' {
         let name = parse_name(text, start + 1)?;
+        let context = format!("STag `{}`", name);
         let pos = start + 1 + name.0.len();
-        let c1 = *text.get(pos).ok_or(XmlError::TextEnd)?;
+        let c1 = char_at(text, pos, &context)?;
         if c1 == '>' {
             let starttag = STag {
-                start: start,
+                start,
                 end: pos + 1,
-                name: name,
+                name,
                 attribs: Vec::new(),
+                namespace: None,
             };
             Ok(starttag)
         } else {
             let mut here = pos;
             let mut attribs = Vec::new();
-            while text.get(here).ok_or(XmlError::TextEnd)? != &'>' {
-                let blank = parse_ws(text, here)?;
+            while char_at(text, here, &context)? != '>' {
+                let blank = parse_ws(text, here).map_err(|e| e.within(&context))?;
                 here = blank.get_endpos();
                 let maybe_attrib = parse_attribute(text, here);
                 match maybe_attrib {
                     Ok(attrib) => {
                         here = attrib.get_endpos();
-                        attribs.push(attrib);
+                        push_attribute(&mut attribs, attrib, &context)?;
                     }
-                    Err(e) => match e {
-                        XmlError::BadChar('>') => break,
+                    Err(e) => match e.kind() {
+                        XmlErrorKind::BadChar('>') if e.doc_idx() == here => break,
                         _ => {
-                            return Err(e);
+                            return Err(e.within(&context));
                         }
                     },
                 };
             }
-            let c_last = *text.get(here).ok_or(XmlError::TextEnd)?;
+            let c_last = char_at(text, here, &context)?;
             if c_last == '>' {
                 let starttag = STag {
-                    start: start,
+                    start,
                     end: here + 1,
-                    name: name,
-                    attribs: attribs,
+                    name,
+                    attribs,
+                    namespace: None,
                 };
                 Ok(starttag)
             } else {
-                Err(XmlError::BadChar(c_last))
+                Err(XmlError::new(XmlErrorKind::BadChar(c_last), here, &context))
             }
         }
     } else {
-        Err(XmlError::BadChar(c0))
+        Err(XmlError::new(XmlErrorKind::BadChar(c0), start, "STag"))
     }
 }
 
-fn parse_content(text: &[char], start: usize, recurdepth: usize) -> Result<Content, XmlError> {
+/// Parse content that is inside `depth` elements
+fn parse_content(text: &str, start: usize, depth: usize, max_depth: usize) -> Result<Content, XmlError> {
     let mut items = Vec::new();
     let mut position = start;
-    while let Ok(item) = parse_content_item(text, position, recurdepth + 1) {
+    // content runs until the end tag of the enclosing element
+    while position < text.len() && !rest(text, position).starts_with("</") {
+        let item = parse_content_item(text, position, depth, max_depth)?;
         position = item.get_endpos();
         items.push(item);
     }
-    let content = Content {
-        start: start,
-        items: items,
-    };
+    let content = Content { start, items };
     Ok(content)
 }
 
-fn parse_chardata(text: &[char], start: usize) -> Result<CharData, XmlError> {
+fn parse_chardata(text: &str, start: usize) -> Result<CharData, XmlError> {
     let mut data = String::new();
     let mut count = 0;
-    let mut hit_bad_substring = false;
     let mut here = start;
 
-    while !hit_bad_substring {
-        let c = text.get(here).ok_or(XmlError::TextEnd)?;
-        match *c {
-            '<' => {
-                if data.len() > 0 {
-                    let cdata = CharData {
-                        start: start,
-                        text: data,
-                    };
-                    return Ok(cdata);
-                } else {
-                    return Err(XmlError::NoData);
-                }
-            }
-            '&' => {
-                if data.len() > 0 {
-                    let cdata = CharData {
-                        start: start,
-                        text: data,
-                    };
+    loop {
+        let c = match char_at(text, here, "CharData") {
+            Ok(c) => c,
+            // the replacement text of an entity may end in character data
+            Err(e) if data.is_empty() => return Err(e),
+            Err(_e) => return Ok(CharData { start, text: data }),
+        };
+        match c {
+            '<' | '&' => {
+                if !data.is_empty() {
+                    let cdata = CharData { start, text: data };
                     return Ok(cdata);
                 } else {
-                    return Err(XmlError::NoData);
+                    return Err(XmlError::new(XmlErrorKind::NoData, start, "CharData"));
                 }
             }
             ']' => {
                 count += 1;
-                data.push(*c);
+                data.push(c);
             }
-            '>' => {
-                if count >= 2 {
-                    hit_bad_substring = true;
-                    here += 1;
-                    continue;
-                } else {
-                    count = 0;
-                    data.push(*c);
-                }
+            '>' if count >= 2 => {
+                return Err(XmlError::new(XmlErrorKind::IllegalSubstr, here - 2, "CharData"));
+            }
+            _ if !is_xml_char(c) => {
+                return Err(XmlError::new(XmlErrorKind::BadChar(c), here, "CharData"));
             }
             _ => {
                 count = 0;
-                data.push(*c);
+                data.push(c);
             }
         };
-        here += 1;
+        here += c.len_utf8();
     }
-    Err(XmlError::IllegalSubstr)
 }
 
-fn parse_cdsect(text: &[char], start: usize) -> Result<CDSect, XmlError> {
-    let subtext = &text[start..];
-    let start_needle: Vec<char> = "<![CDATA[".chars().collect();
-    if subtext.starts_with(&start_needle) {
+fn parse_cdsect(text: &str, start: usize) -> Result<CDSect, XmlError> {
+    let start_needle = "<![CDATA[";
+    if rest(text, start).starts_with(start_needle) {
         let pos = start + start_needle.len();
         let mut count = 0;
         let mut data = String::new();
-        for c in &text[pos..] {
-            match *c {
+        for (offset, c) in rest(text, pos).char_indices() {
+            match c {
+                _ if !is_xml_char(c) => {
+                    return Err(XmlError::new(XmlErrorKind::BadChar(c), pos + offset, "CDSect"));
+                }
                 ']' => {
                     count += 1;
-                    data.push(*c);
+                    data.push(c);
                 }
-                '>' => {
-                    if count >= 2 {
-                        let c_ult = data.pop();
-                        let c_pen = data.pop();
-                        match c_pen {
-                            Some(']') => match c_ult {
-                                Some(']') => {
-                                    let cdsect = CDSect {
-                                        start: start,
-                                        text: data,
-                                    };
-                                    return Ok(cdsect);
-                                }
-                                Some(c) => {
-                                    return Err(XmlError::BadChar(c));
-                                }
-                                None => {
-                                    unreachable!(
-                                        "hit unreachable condition when checking close delim for CDSect"
-                                    );
-                                }
-                            },
-                            Some(c) => {
-                                return Err(XmlError::BadChar(c));
-                            }
-                            None => {
-                                unreachable!(
-                                    "hit unreachable condition when checking close delim for CDSect"
-                                );
-                            }
-                        }
-                    } else {
-                        count = 0;
-                        data.push(*c);
-                    }
+                '>' if count >= 2 => {
+                    // drop the `]]` of the closing delimiter
+                    data.truncate(data.len() - 2);
+                    let cdsect = CDSect { start, text: data };
+                    return Ok(cdsect);
                 }
                 _ => {
                     count = 0;
-                    data.push(*c);
+                    data.push(c);
                 }
             }
         }
-        Err(XmlError::TextEnd)
+        Err(XmlError::new(XmlErrorKind::TextEnd, text.len(), "CDSect"))
     } else {
-        Err(XmlError::BadCDATAStart)
+        Err(XmlError::new(XmlErrorKind::BadCDATAStart, start, "CDSect"))
     }
 }
 
 fn parse_content_item(
-    text: &[char],
+    text: &str,
     start: usize,
-    recurdepth: usize,
+    depth: usize,
+    max_depth: usize,
 ) -> Result<ContentItem, XmlError> {
-    if let Ok(reference) = parse_reference(text, start) {
-        let item = ContentItem::Reference {
-            start: start,
-            reference: reference,
-        };
-        Ok(item)
-    } else if let Ok(comment) = parse_comment(text, start) {
-        let item = ContentItem::Comment(comment);
-        Ok(item)
-    } else if let Ok(pi) = parse_pi(text, start) {
-        let item = ContentItem::ProcInstr(pi);
-        Ok(item)
-    } else if let Ok(chardata) = parse_chardata(text, start) {
-        let item = ContentItem::CharData(chardata);
-        Ok(item)
-    } else if let Ok(cdsect) = parse_cdsect(text, start) {
-        let item = ContentItem::CDSect(cdsect);
-        Ok(item)
-    } else if let Ok(elem) = parse_elem(text, start, recurdepth + 1) {
-        let boxed_elem = Box::new(elem);
-        let item = ContentItem::Elem(boxed_elem);
-        Ok(item)
-    } else {
-        let err = XmlError::NoValidVariant;
-        Err(err)
+    let cause = match parse_leaf_item(text, start) {
+        Ok(item) => return Ok(item),
+        Err(e) => e,
+    };
+    let cause = match parse_elem(text, start, depth, max_depth) {
+        Ok(elem) => return Ok(ContentItem::Elem(Box::new(elem))),
+        // no other alternative can succeed where nesting is too deep
+        Err(e) if matches!(e.kind(), XmlErrorKind::MaxRecurDepth(_)) => return Err(e),
+        Err(e) => furthest(cause, e),
+    };
+    Err(no_valid_variant(cause, start, "content"))
+}
+
+/// Parse the items of content other than elements, returning the error
+/// that got furthest if none match
+fn parse_leaf_item(text: &str, start: usize) -> Result<ContentItem, XmlError> {
+    let mut cause = match parse_reference(text, start) {
+        Ok(reference) => return Ok(ContentItem::Reference { start, reference }),
+        Err(e) => e,
+    };
+    cause = match parse_comment(text, start) {
+        Ok(comment) => return Ok(ContentItem::Comment(comment)),
+        Err(e) => furthest(cause, e),
+    };
+    cause = match parse_pi(text, start) {
+        Ok(pi) => return Ok(ContentItem::ProcInstr(pi)),
+        Err(e) => furthest(cause, e),
+    };
+    cause = match parse_chardata(text, start) {
+        Ok(chardata) => return Ok(ContentItem::CharData(chardata)),
+        Err(e) => furthest(cause, e),
+    };
+    match parse_cdsect(text, start) {
+        Ok(cdsect) => Ok(ContentItem::CDSect(cdsect)),
+        Err(e) => Err(furthest(cause, e)),
     }
 }
 
-fn parse_endtag(text: &[char], start: usize) -> Result<ETag, XmlError> {
-    let c0 = *text.get(start).ok_or(XmlError::TextEnd)?;
+fn parse_endtag(text: &str, start: usize) -> Result<ETag, XmlError> {
+    let c0 = char_at(text, start, "ETag")?;
     if c0 == '<' {
-        let c1 = *text.get(start + 1).ok_or(XmlError::TextEnd)?;
+        let c1 = char_at(text, start + 1, "ETag")?;
         if c1 == '/' {
             let name = parse_name(text, start + 2)?;
             let pos = start + 2 + name.0.len();
@@ -1699,151 +2723,157 @@ fn parse_endtag(text: &[char], start: usize) -> Result<ETag, XmlError> {
                 Ok(ws) => ws.get_endpos(),
                 Err(_) => pos,
             };
-            let c_last = *text.get(closepos).ok_or(XmlError::TextEnd)?;
+            let c_last = char_at(text, closepos, "ETag")?;
             if c_last == '>' {
                 let end = closepos + 1;
-                let etag = ETag {
-                    start: start,
-                    end: end,
-                    name: name,
-                };
+                let etag = ETag { start, end, name };
                 Ok(etag)
             } else {
-                Err(XmlError::BadChar(c_last))
+                Err(XmlError::new(XmlErrorKind::BadChar(c_last), closepos, "ETag"))
             }
         } else {
-            Err(XmlError::BadChar(c1))
+            Err(XmlError::new(XmlErrorKind::BadChar(c1), start + 1, "ETag"))
         }
     } else {
-        Err(XmlError::BadChar(c0))
+        Err(XmlError::new(XmlErrorKind::BadChar(c0), start, "ETag"))
     }
 }
 
-fn parse_attvalue(text: &[char], start: usize) -> Result<AttValue, XmlError> {
-    let c0 = *text.get(start).ok_or(XmlError::TextEnd)?;
+fn parse_attvalue(text: &str, start: usize) -> Result<AttValue, XmlError> {
+    let c0 = char_at(text, start, "AttValue")?;
     let single_qoute = c0 == '\'';
+    if !single_qoute && c0 != '\"' {
+        return Err(XmlError::new(XmlErrorKind::BadChar(c0), start, "AttValue"));
+    }
     let mut items: Vec<AttValueItem> = Vec::new();
-    let mut end_hit = false;
     let mut idx = start + 1;
     let mut current_item = String::new();
-    while !end_hit {
-        let c = *text.get(idx).ok_or(XmlError::TextEnd)?;
-        if c == '\'' && single_qoute {
-            end_hit = true;
-            if current_item.len() > 0 {
-                let item = AttValueItem::Text(current_item);
-                items.push(item);
-            }
-            break;
-        } else if c == '\"' && !single_qoute {
-            end_hit = true;
-            if current_item.len() > 0 {
+    loop {
+        let c = char_at(text, idx, "AttValue")?;
+        if c == c0 {
+            if !current_item.is_empty() {
                 let item = AttValueItem::Text(current_item);
                 items.push(item);
             }
             break;
-        } else if c == '<' {
-            let err = XmlError::BadChar(c);
+        } else if c == '<' || !is_xml_char(c) {
+            let err = XmlError::new(XmlErrorKind::BadChar(c), idx, "AttValue");
             return Err(err);
         } else if c == '&' {
-            if current_item.len() > 0 {
+            if !current_item.is_empty() {
                 let item = AttValueItem::Text(current_item);
                 items.push(item);
                 current_item = String::new();
             }
-            let reference = parse_reference(text, idx)?;
+            let reference = parse_reference(text, idx).map_err(|e| e.within("AttValue"))?;
             let item = AttValueItem::Reference(reference);
             let length = item.text_len();
             items.push(item);
             idx += length;
         } else {
             current_item.push(c);
-            idx += 1;
+            idx += c.len_utf8();
         }
     }
 
-    let attvalue = AttValue {
-        start: start,
-        items: items,
-    };
+    let attvalue = AttValue { start, items };
 
     Ok(attvalue)
 }
 
-fn parse_reference(text: &[char], start: usize) -> Result<Reference, XmlError> {
-    let c0 = *text.get(start).ok_or(XmlError::TextEnd)?;
+fn parse_reference(text: &str, start: usize) -> Result<Reference, XmlError> {
+    let c0 = char_at(text, start, "Reference")?;
     if c0 == '&' {
-        let c1 = *text.get(start + 1).ok_or(XmlError::TextEnd)?;
+        let c1 = char_at(text, start + 1, "Reference")?;
         if c1 == '#' {
-            let mut ref_text = String::new();
-            let mut at_start = true;
-            for c in &text[(start + 2)..] {
-                if c == &';' {
-                    break;
-                } else {
-                    match *c {
-                        '0'..='9' | 'a'..='f' | 'A'..='F' => {
-                            ref_text.push(*c);
-                        }
-                        'x' => {
-                            if at_start {
-                                ref_text.push(*c);
-                            } else {
-                                return Err(XmlError::BadChar(*c));
-                            }
-                        }
-                        _ => {
-                            return Err(XmlError::BadChar(*c));
-                        }
-                    };
-                }
-                at_start = false;
-            }
-            let reference = Reference::CharRef(ref_text);
-            Ok(reference)
-        } else {
-            let name = parse_name(text, start + 1)?;
-            let pos = start + 1 + name.0.len();
-            let c_last = *text.get(pos).ok_or(XmlError::TextEnd)?;
+            let hex = rest(text, start + 2).starts_with('x');
+            let digits_start = if hex { start + 3 } else { start + 2 };
+            let radix = if hex { 16 } else { 10 };
+            let mut value: Option<u32> = Some(0);
+            for (offset, c) in rest(text, digits_start).char_indices() {
+                match (c, c.to_digit(radix)) {
+                    (';', _) if offset > 0 => {
+                        let ref_text = String::from(&text[start + 2..digits_start + offset]);
+                        return match value.and_then(char::from_u32) {
+                            // references that only XML 1.1 allows are checked
+                            // against the version of the document later
+                            Some(c) if XmlVersion::V1_1.is_char(c) => Ok(Reference::CharRef(ref_text)),
+                            _ => Err(XmlError::new(XmlErrorKind::InvalidCharRef(ref_text), start, "CharRef")),
+                        };
+                    }
+                    (_, Some(digit)) => {
+                        // values too large for a u32 become None and are rejected above
+                        value = value
+                            .and_then(|v| v.checked_mul(radix))
+                            .and_then(|v| v.checked_add(digit));
+                    }
+                    _ => {
+                        return Err(XmlError::new(XmlErrorKind::BadChar(c), digits_start + offset, "CharRef"));
+                    }
+                };
+            }
+            Err(XmlError::new(XmlErrorKind::TextEnd, text.len(), "CharRef"))
+        } else {
+            let name = parse_name(text, start + 1).map_err(|e| e.within("EntityRef"))?;
+            let pos = start + 1 + name.0.len();
+            let c_last = char_at(text, pos, "EntityRef")?;
             if c_last == ';' {
                 let reference = Reference::EntityRef(name);
                 Ok(reference)
             } else {
-                Err(XmlError::BadChar(c_last))
+                Err(XmlError::new(XmlErrorKind::BadChar(c_last), pos, "EntityRef"))
             }
         }
     } else {
-        Err(XmlError::BadChar(c0))
+        Err(XmlError::new(XmlErrorKind::BadChar(c0), start, "Reference"))
     }
 }
 
-fn parse_ndatadecl(text :&[char], start :usize) -> Result<NDataDecl, XmlError> {
+fn parse_ndatadecl(text :&str, start :usize) -> Result<NDataDecl, XmlError> {
     let leadspace = parse_ws(text, start)?;
     let pos1 = leadspace.get_endpos();
-    let subtext = &text[pos1..];
-    let needle :Vec<char> = "NDATA".chars().collect();
-    if subtext.starts_with(&needle) {
+    let needle = "NDATA";
+    if rest(text, pos1).starts_with(needle) {
         let pos2 = pos1 + needle.len();
         let spacer = parse_ws(text, pos2)?;
         let pos3 = spacer.get_endpos();
         let name = parse_name(text, pos3)?;
         let ndatadecl = NDataDecl {
-            start : start,
+            start,
             end : pos3 + name.0.len(),
-            name : name,
+            name,
         };
         Ok(ndatadecl)
     } else {
-        Err(XmlError::KeywordMatchFail)
+        Err(XmlError::new(XmlErrorKind::KeywordMatchFail, pos1, "NDataDecl"))
     }
 }
 
 pub struct Prolog {
+    start: usize,
+    end: usize,
     xml_decl: Option<XmlDecl>,
     doctype_decl: Option<DoctypeDecl>,
     miscs: Vec<Misc>,
 }
 
+impl Prolog {
+    /// The `<?xml ...?>` declaration, if the document has one
+    pub fn xml_decl(&self) -> Option<&XmlDecl> {
+        self.xml_decl.as_ref()
+    }
+
+    /// The `<!DOCTYPE ...>` declaration, if the document has one
+    pub fn doctype(&self) -> Option<&DoctypeDecl> {
+        self.doctype_decl.as_ref()
+    }
+
+    /// Comments, processing instructions and whitespace in the prolog
+    pub fn miscs(&self) -> &[Misc] {
+        &self.miscs
+    }
+}
+
 pub struct XmlDecl {
     start: usize,
     end: usize,
@@ -1852,10 +2882,78 @@ pub struct XmlDecl {
     standalone: Option<SDDecl>,
 }
 
+impl XmlDecl {
+    /// The declared version number, as written
+    pub fn version(&self) -> &str {
+        &self.version.ver_num
+    }
+
+    /// The version of XML the document is parsed as. Version numbers other
+    /// than `1.1` are read as XML 1.0.
+    pub fn xml_version(&self) -> XmlVersion {
+        if self.version.ver_num == "1.1" { XmlVersion::V1_1 } else { XmlVersion::V1_0 }
+    }
+
+    /// The declared encoding name, if present
+    pub fn encoding(&self) -> Option<&str> {
+        self.encoding.as_ref().map(|enc| enc.enc_name.as_str())
+    }
+
+    /// The standalone document declaration, if present
+    pub fn standalone(&self) -> Option<bool> {
+        self.standalone.as_ref().map(|sd| sd.is_standalone)
+    }
+}
+
 struct VersionInfo {
     start: usize,
     end: usize,
-    ver_num: f32,
+    ver_num: String,
+}
+
+/// A version of XML. The versions share their name characters, but XML 1.1
+/// documents may refer to C0 control characters, must write most C1 control
+/// characters as references, and also end lines at NEL (U+0085) and U+2028.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum XmlVersion {
+    #[default]
+    V1_0,
+    V1_1,
+}
+
+impl XmlVersion {
+    /// The version declared by the XML declaration at the start of `text`,
+    /// or 1.0 if it has none
+    pub fn of(text: &str) -> XmlVersion {
+        parse_xmldecl(text, 0).map_or(XmlVersion::V1_0, |decl| decl.xml_version())
+    }
+
+    /// Whether `c` matches the Char production, and so may be referred to
+    /// by a character reference
+    pub fn is_char(self, c: char) -> bool {
+        match self {
+            XmlVersion::V1_0 => is_xml_char(c),
+            XmlVersion::V1_1 => matches!(c as u32, 0x1..=0xD7FF | 0xE000..=0xFFFD | 0x10000..=0x10FFFF),
+        }
+    }
+
+    /// Whether `c` matches the RestrictedChar production of XML 1.1, and
+    /// so may only be written as a character reference
+    pub fn is_restricted(self, c: char) -> bool {
+        self == XmlVersion::V1_1 && matches!(c as u32, 0x1..=0x8 | 0xB..=0xC | 0xE..=0x1F | 0x7F..=0x84 | 0x86..=0x9F)
+    }
+
+    /// Whether `c` ends a line when it follows `prev`. A `\r` followed by
+    /// `\n`, or by NEL in XML 1.1, ends a single line.
+    pub(crate) fn is_line_end(self, prev: char, c: char) -> bool {
+        match c {
+            '\n' => prev != '\r',
+            '\r' => true,
+            '\u{85}' => self == XmlVersion::V1_1 && prev != '\r',
+            '\u{2028}' => self == XmlVersion::V1_1,
+            _ => false,
+        }
+    }
 }
 
 struct Encoding {
@@ -1870,16 +2968,107 @@ struct SDDecl {
     is_standalone: bool,
 }
 
-struct DoctypeDecl {
+pub struct DoctypeDecl {
     start: usize,
     end: usize,
     name: Name,
     ext_id: Option<ExternalID>,
     int_subset: Option<IntSubset>,
+    ext_subset: Option<ExtSubset>,
+}
+
+impl DoctypeDecl {
+    /// The declared name of the root element
+    pub fn name(&self) -> &str {
+        self.name.as_str()
+    }
+
+    /// The external identifier of the external DTD subset, if present
+    pub fn external_id(&self) -> Option<&ExternalID> {
+        self.ext_id.as_ref()
+    }
+
+    /// The URI the external subset was loaded from, if it was loaded
+    pub fn external_subset_uri(&self) -> Option<&str> {
+        self.ext_subset.as_ref().map(|ext| ext.uri.as_str())
+    }
+
+    /// The element type declarations in the DTD, internal subset first
+    pub fn elem_decls(&self) -> impl Iterator<Item = &ElemDecl> {
+        self.decls().filter_map(|item| match item {
+            IntSubsetItem::ElemDecl(elemdecl) => Some(elemdecl),
+            _ => None,
+        })
+    }
+
+    /// The attribute-list declarations in the DTD, internal subset first
+    pub fn attlist_decls(&self) -> impl Iterator<Item = &AttlistDecl> {
+        self.decls().filter_map(|item| match item {
+            IntSubsetItem::AttlistDecl(attlist) => Some(attlist),
+            _ => None,
+        })
+    }
+
+    /// The notation declarations in the DTD, internal subset first
+    pub fn notation_decls(&self) -> impl Iterator<Item = &NotationDecl> {
+        self.decls().filter_map(|item| match item {
+            IntSubsetItem::NotationDecl(notation) => Some(notation),
+            _ => None,
+        })
+    }
+
+    /// The attribute definitions for each element type. When an attribute
+    /// is defined more than once, the first definition is binding.
+    pub(crate) fn att_defs_by_elem(&self) -> HashMap<&str, Vec<&AttDef>> {
+        let mut att_defs: HashMap<&str, Vec<&AttDef>> = HashMap::new();
+        for decl in self.attlist_decls() {
+            let defs = att_defs.entry(decl.name()).or_default();
+            for def in decl.att_defs() {
+                if !defs.iter().any(|prev| prev.name() == def.name()) {
+                    defs.push(def);
+                }
+            }
+        }
+        att_defs
+    }
+
+    /// The general and parameter entities declared in the DTD
+    pub fn entities(&self) -> EntityTable {
+        EntityTable::from_doctype(self)
+    }
+
+    /// The items of the internal subset followed by those of the external
+    /// subset, including those from expanded parameter entities and
+    /// included conditional sections
+    fn decls(&self) -> impl Iterator<Item = &IntSubsetItem> {
+        let internal = self.int_subset.iter().flat_map(|subset| subset.flattened());
+        internal.chain(self.ext_subset.iter().flat_map(|ext| ext.flattened()))
+    }
+
+    /// The items of `decls` with where each is in the document: where it
+    /// starts in the internal subset, or otherwise where the parameter entity
+    /// reference or document type declaration that brought it in starts
+    pub(crate) fn located_decls(&self) -> Vec<(usize, &IntSubsetItem)> {
+        let mut items = Vec::new();
+        if let Some(subset) = &self.int_subset {
+            locate_decls(&subset.items, None, &mut items);
+        }
+        if let Some(ext_subset) = &self.ext_subset {
+            locate_decls(&ext_subset.items, Some(self.start), &mut items);
+        }
+        items
+    }
+
+    /// Whether the DTD references parameter entities
+    fn has_pe_refs(&self) -> bool {
+        self.decls().any(|item| {
+            matches!(item, IntSubsetItem::PEReference { .. } | IntSubsetItem::ParamEntity { .. })
+        })
+    }
 }
 
 #[derive(Debug)]
-enum ExternalID {
+pub enum ExternalID {
     System {
         start: usize,
         end: usize,
@@ -1893,13 +3082,67 @@ enum ExternalID {
     },
 }
 
+impl ExternalID {
+    /// The system identifier literal
+    pub fn system_id(&self) -> &str {
+        match &self {
+            ExternalID::System { sys_lit, .. } => sys_lit,
+            ExternalID::Public { sys_lit, .. } => sys_lit,
+        }
+    }
+
+    /// The public identifier literal, only present for `PUBLIC` identifiers
+    pub fn public_id(&self) -> Option<&str> {
+        match &self {
+            ExternalID::System { .. } => None,
+            ExternalID::Public { pub_lit, .. } => Some(pub_lit),
+        }
+    }
+}
+
 struct IntSubset {
+    start :usize,
+    end :usize,
     items: Vec<IntSubsetItem>,
 }
 
+impl IntSubset {
+    /// The items of the subset in document order, with the declarations
+    /// parsed from an expanded parameter entity following the reference to it
+    fn flattened(&self) -> Vec<&IntSubsetItem> {
+        let mut items = Vec::new();
+        flatten_decls(&self.items, &mut items);
+        items
+    }
+}
+
+/// The external DTD subset, as loaded by the resolver
+struct ExtSubset {
+    uri :String,
+    /// positions of the items are byte offsets into the external subset
+    items :Vec<IntSubsetItem>,
+}
+
+impl ExtSubset {
+    fn flattened(&self) -> Vec<&IntSubsetItem> {
+        let mut items = Vec::new();
+        flatten_decls(&self.items, &mut items);
+        items
+    }
+}
+
 enum IntSubsetItem {
     Blank(Ws),
-    PEReference(PEReference),
+    PEReference { start :usize, reference :PEReference },
+    /// a reference to a parameter entity, replaced by the declarations parsed
+    /// from its replacement text. Positions of the items are byte offsets
+    /// into the replacement text rather than the document. `uri` is where an
+    /// external entity was loaded from.
+    ParamEntity { start :usize, reference :PEReference, uri :Option<String>, items :Vec<IntSubsetItem> },
+    /// `<![INCLUDE[ ... ]]>`, only allowed in external DTD text
+    IncludeSect { start :usize, end :usize, items :Vec<IntSubsetItem> },
+    /// `<![IGNORE[ ... ]]>`, only allowed in external DTD text
+    IgnoreSect { start :usize, end :usize },
     ElemDecl(ElemDecl),
     AttlistDecl(AttlistDecl),
     EntityDecl(EntityDecl),
@@ -1908,6 +3151,31 @@ enum IntSubsetItem {
     Comment(Comment),
 }
 
+/// Flatten `items` as `flatten_decls` does, placing each at `anchor` if
+/// given, or else where it starts
+fn locate_decls<'a>(items: &'a [IntSubsetItem], anchor: Option<usize>, out: &mut Vec<(usize, &'a IntSubsetItem)>) {
+    for item in items {
+        out.push((anchor.unwrap_or(item.get_startpos()), item));
+        match item {
+            IntSubsetItem::ParamEntity { start, items, .. } => locate_decls(items, anchor.or(Some(*start)), out),
+            IntSubsetItem::IncludeSect { items, .. } => locate_decls(items, anchor, out),
+            _ => (),
+        };
+    }
+}
+
+fn flatten_decls<'a>(items: &'a [IntSubsetItem], out: &mut Vec<&'a IntSubsetItem>) {
+    for item in items {
+        out.push(item);
+        match item {
+            IntSubsetItem::ParamEntity { items, .. } | IntSubsetItem::IncludeSect { items, .. } => {
+                flatten_decls(items, out);
+            }
+            _ => (),
+        };
+    }
+}
+
 struct PEReference(Name);
 
 impl PEReference {
@@ -1916,16 +3184,169 @@ impl PEReference {
     }
 }
 
-struct ElemDecl;
+pub struct ElemDecl {
+    start :usize,
+    end :usize,
+    name :Name,
+    content_spec :ContentSpec,
+}
+
+impl ElemDecl {
+    /// The element type being declared
+    pub fn name(&self) -> &str {
+        self.name.as_str()
+    }
+
+    /// The declared content model
+    pub fn content_spec(&self) -> &ContentSpec {
+        &self.content_spec
+    }
+}
+
+/// The content allowed in an element, as declared by `<!ELEMENT>`
+pub enum ContentSpec {
+    /// `EMPTY`: the element must have no content
+    Empty,
+    /// `ANY`: any content is allowed
+    Any,
+    /// `(#PCDATA|a|b)*`: character data mixed with the listed elements
+    Mixed(Mixed),
+    /// element content described by a content model such as `(a,(b|c)*)`
+    Children(ContentParticle),
+}
+
+impl fmt::Display for ContentSpec {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self {
+            ContentSpec::Empty => write!(f, "EMPTY"),
+            ContentSpec::Any => write!(f, "ANY"),
+            ContentSpec::Mixed(mixed) => write!(f, "{}", mixed),
+            ContentSpec::Children(cp) => write!(f, "{}", cp),
+        }
+    }
+}
+
+pub struct Mixed {
+    start :usize,
+    end :usize,
+    names :Vec<Name>,
+}
+
+impl Mixed {
+    /// The element types allowed among the character data
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.names.iter().map(|name| name.as_str())
+    }
+}
+
+impl fmt::Display for Mixed {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "(#PCDATA")?;
+        for name in &self.names {
+            write!(f, "|{}", name)?;
+        }
+        if self.names.is_empty() {
+            write!(f, ")")
+        } else {
+            write!(f, ")*")
+        }
+    }
+}
 
-struct AttlistDecl {
+/// A content particle: an element name, a choice or a sequence, together
+/// with how often it may occur
+pub struct ContentParticle {
+    start :usize,
+    end :usize,
+    particle :Particle,
+    repeat :Repetition,
+}
+
+impl ContentParticle {
+    pub fn particle(&self) -> &Particle {
+        &self.particle
+    }
+
+    pub fn repetition(&self) -> Repetition {
+        self.repeat
+    }
+}
+
+impl fmt::Display for ContentParticle {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.particle {
+            Particle::Name(name) => write!(f, "{}", name)?,
+            Particle::Choice(items) | Particle::Seq(items) => {
+                let separator = match &self.particle {
+                    Particle::Choice(_) => "|",
+                    _ => ",",
+                };
+                write!(f, "(")?;
+                for (idx, item) in items.iter().enumerate() {
+                    if idx > 0 {
+                        write!(f, "{}", separator)?;
+                    }
+                    write!(f, "{}", item)?;
+                }
+                write!(f, ")")?;
+            }
+        };
+        write!(f, "{}", self.repeat)
+    }
+}
+
+pub enum Particle {
+    /// a single element type
+    Name(Name),
+    /// `(a|b|c)`: exactly one of the particles
+    Choice(Vec<ContentParticle>),
+    /// `(a,b,c)`: each of the particles in order
+    Seq(Vec<ContentParticle>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Repetition {
+    /// no suffix: exactly once
+    Once,
+    /// `?`: zero or one times
+    Optional,
+    /// `*`: zero or more times
+    ZeroOrMore,
+    /// `+`: one or more times
+    OneOrMore,
+}
+
+impl fmt::Display for Repetition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self {
+            Repetition::Once => Ok(()),
+            Repetition::Optional => write!(f, "?"),
+            Repetition::ZeroOrMore => write!(f, "*"),
+            Repetition::OneOrMore => write!(f, "+"),
+        }
+    }
+}
+
+pub struct AttlistDecl {
     start :usize,
     end :usize,
     name :Name,
     att_defs :Vec<AttDef>,
 }
 
-struct AttDef {
+impl AttlistDecl {
+    /// The element type whose attributes are being declared
+    pub fn name(&self) -> &str {
+        self.name.as_str()
+    }
+
+    /// The attribute definitions, in declaration order
+    pub fn att_defs(&self) -> &[AttDef] {
+        &self.att_defs
+    }
+}
+
+pub struct AttDef {
     start :usize,
     end :usize,
     name :Name,
@@ -1933,9 +3354,89 @@ struct AttDef {
     default_decl :DefaultDecl,
 }
 
-enum AttType {}
+impl AttDef {
+    /// The attribute being defined
+    pub fn name(&self) -> &str {
+        self.name.as_str()
+    }
+
+    /// The declared type of the attribute
+    pub fn att_type(&self) -> &AttType {
+        &self.att_type
+    }
+
+    /// Whether the attribute is required, and its default value if any
+    pub fn default_decl(&self) -> &DefaultDecl {
+        &self.default_decl
+    }
+}
+
+/// The type of an attribute, as declared by `<!ATTLIST>`
+#[derive(Debug, PartialEq)]
+pub enum AttType {
+    /// `CDATA`: any string
+    CData,
+    /// `ID`: a name unique within the document
+    Id,
+    /// `IDREF`: the name of an `ID` in the document
+    IdRef,
+    /// `IDREFS`: white space separated `IDREF`s
+    IdRefs,
+    /// `ENTITY`: the name of an unparsed entity
+    Entity,
+    /// `ENTITIES`: white space separated `ENTITY`s
+    Entities,
+    /// `NMTOKEN`: a single name token
+    NmToken,
+    /// `NMTOKENS`: white space separated name tokens
+    NmTokens,
+    /// `NOTATION (a|b)`: one of the listed notation names
+    Notation(Vec<Name>),
+    /// `(a|b)`: one of the listed name tokens
+    Enumeration(Vec<String>),
+}
+
+impl fmt::Display for AttType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self {
+            AttType::CData => write!(f, "CDATA"),
+            AttType::Id => write!(f, "ID"),
+            AttType::IdRef => write!(f, "IDREF"),
+            AttType::IdRefs => write!(f, "IDREFS"),
+            AttType::Entity => write!(f, "ENTITY"),
+            AttType::Entities => write!(f, "ENTITIES"),
+            AttType::NmToken => write!(f, "NMTOKEN"),
+            AttType::NmTokens => write!(f, "NMTOKENS"),
+            AttType::Notation(names) => {
+                let names: Vec<&str> = names.iter().map(|name| name.as_str()).collect();
+                write!(f, "NOTATION ({})", names.join("|"))
+            }
+            AttType::Enumeration(tokens) => write!(f, "({})", tokens.join("|")),
+        }
+    }
+}
+
+/// Whether an attribute must be given, and the value used when it is not
+pub enum DefaultDecl {
+    /// `#REQUIRED`: the attribute must always be given
+    Required,
+    /// `#IMPLIED`: the attribute may be left out, and has no default
+    Implied,
+    /// `#FIXED "v"`: the attribute always has the given value
+    Fixed(AttValue),
+    /// `"v"`: the value used when the attribute is left out
+    Default(AttValue),
+}
 
-enum DefaultDecl {}
+impl DefaultDecl {
+    /// The declared default value, for `#FIXED` and plain defaults
+    pub fn value(&self) -> Option<&AttValue> {
+        match &self {
+            DefaultDecl::Fixed(value) | DefaultDecl::Default(value) => Some(value),
+            _ => None,
+        }
+    }
+}
 
 enum EntityDecl{
     GEDecl(GEDecl),
@@ -1980,7 +3481,7 @@ struct EntityValue {
     text :String,
 }
 
-struct NotationDecl {
+pub struct NotationDecl {
     start :usize,
     end :usize,
     name :Name,
@@ -1992,6 +3493,30 @@ enum NotationID {
     Public(PublicID),
 }
 
+impl NotationDecl {
+    /// The notation being declared
+    pub fn name(&self) -> &str {
+        self.name.as_str()
+    }
+
+    /// The public identifier of the notation, if given
+    pub fn public_id(&self) -> Option<&str> {
+        match &self.notation_id {
+            NotationID::External(extid) => extid.public_id(),
+            NotationID::Public(pubid) => Some(&pubid.text),
+        }
+    }
+
+    /// The system identifier of the notation, only present for `SYSTEM`
+    /// identifiers and `PUBLIC` identifiers that give one
+    pub fn system_id(&self) -> Option<&str> {
+        match &self.notation_id {
+            NotationID::External(extid) => Some(extid.system_id()),
+            NotationID::Public(_) => None,
+        }
+    }
+}
+
 struct PublicID {
     start :usize,
     end :usize,
@@ -2003,11 +3528,118 @@ pub enum Elem {
     Full(FullElem),
 }
 
+impl Elem {
+    /// The element type name
+    pub fn name(&self) -> &str {
+        match &self {
+            Elem::Empty(empty) => empty.name.as_str(),
+            Elem::Full(full) => full.start.name.as_str(),
+        }
+    }
+
+    /// The prefix of the element type name, if it has one
+    pub fn prefix(&self) -> Option<&str> {
+        split_qname(self.name()).0
+    }
+
+    /// The element type name without its prefix
+    pub fn local_name(&self) -> &str {
+        split_qname(self.name()).1
+    }
+
+    /// The namespace name of the element, from the declaration of its
+    /// prefix, or of the default namespace, in scope. Always `None` when
+    /// namespace processing is turned off.
+    pub fn namespace_uri(&self) -> Option<&str> {
+        match &self {
+            Elem::Empty(empty) => empty.namespace.as_deref(),
+            Elem::Full(full) => full.start.namespace.as_deref(),
+        }
+    }
+
+    /// The element type name as `{uri}local`, or just the local part when
+    /// the element is in no namespace
+    pub fn expanded_name(&self) -> String {
+        expanded_name(self.namespace_uri(), self.local_name())
+    }
+
+    /// The attributes of the element: those specified, in document order,
+    /// followed by any added from defaults declared in the DTD
+    pub fn attributes(&self) -> &[Attribute] {
+        match &self {
+            Elem::Empty(empty) => &empty.attribs,
+            Elem::Full(full) => &full.start.attribs,
+        }
+    }
+
+    /// Look up an attribute by name
+    pub fn attribute(&self, name: &str) -> Option<&Attribute> {
+        self.attributes().iter().find(|attrib| attrib.name() == name)
+    }
+
+    /// The content of the element, in document order. Empty elements and
+    /// elements written as `<a></a>` have no children.
+    pub fn children(&self) -> &[ContentItem] {
+        match &self {
+            Elem::Empty(_empty) => &[],
+            Elem::Full(full) => match &full.content {
+                Some(content) => &content.items,
+                None => &[],
+            },
+        }
+    }
+
+    /// Iterate over the child elements, including those within expanded
+    /// entities, skipping text, comments and other content
+    pub fn child_elems(&self) -> impl Iterator<Item = &Elem> {
+        let mut elems = Vec::new();
+        collect_elems(self.children(), &mut elems);
+        elems.into_iter()
+    }
+
+    /// The text of the element and all its descendants, with character
+    /// references and predefined entities decoded. Comments and processing
+    /// instructions are skipped.
+    pub fn text(&self) -> String {
+        let mut buf = String::new();
+        for item in self.children() {
+            item.push_text(&mut buf);
+        }
+        buf
+    }
+}
+
+/// Split a qualified name at its first colon into prefix and local part
+fn split_qname(name: &str) -> (Option<&str>, &str) {
+    match name.split_once(':') {
+        Some((prefix, local)) => (Some(prefix), local),
+        None => (None, name),
+    }
+}
+
+fn expanded_name(uri: Option<&str>, local: &str) -> String {
+    match uri {
+        Some(uri) => format!("{{{}}}{}", uri, local),
+        None => String::from(local),
+    }
+}
+
+fn collect_elems<'a>(items: &'a [ContentItem], elems: &mut Vec<&'a Elem>) {
+    for item in items {
+        match item {
+            ContentItem::Elem(elem) => elems.push(elem),
+            ContentItem::Entity { items, .. } => collect_elems(items, elems),
+            _ => (),
+        };
+    }
+}
+
 pub struct EmptyElem {
     start: usize,
     end: usize,
     name: Name,
     attribs: Vec<Attribute>,
+    namespace: Option<String>,
 }
 
 pub struct FullElem {
@@ -2021,6 +3653,9 @@ struct STag {
     end: usize,
     name: Name,
     attribs: Vec<Attribute>,
+    /// the namespace name the element's prefix resolves to, once namespaces
+    /// have been processed
+    namespace: Option<String>,
 }
 
 struct ETag {
@@ -2034,16 +3669,143 @@ pub struct Attribute {
     end: usize,
     name: Name,
     value: AttValue,
+    /// false for attributes added from a default in the DTD
+    specified: bool,
+    /// whether the attribute is declared with a type other than `CDATA`
+    tokenized: bool,
+    namespace: Option<String>,
+    /// the version of the document, which decides the line ends that
+    /// normalization turns into spaces
+    version: XmlVersion,
+}
+
+impl Attribute {
+    /// The attribute name
+    pub fn name(&self) -> &str {
+        self.name.as_str()
+    }
+
+    /// The attribute value, normalized as the XML spec requires: character
+    /// references and predefined entities are decoded, white space
+    /// characters become spaces, and for attributes declared with a type
+    /// other than `CDATA`, spaces are then collapsed and trimmed. References
+    /// to other entities are kept as written unless they were expanded.
+    pub fn value(&self) -> String {
+        self.value.normalize(self.tokenized, self.version)
+    }
+
+    /// The attribute value as written between the qoutes, with any
+    /// references left in place
+    pub fn raw_value(&self) -> String {
+        self.value.raw()
+    }
+
+    /// Whether the attribute was given in the start tag, rather than added
+    /// from a default value declared in the DTD. Defaulted attributes take
+    /// the position of their element, while the positions within their
+    /// value refer to the declaration.
+    pub fn is_specified(&self) -> bool {
+        self.specified
+    }
+
+    /// The prefix of the attribute name, if it has one
+    pub fn prefix(&self) -> Option<&str> {
+        split_qname(self.name()).0
+    }
+
+    /// The attribute name without its prefix
+    pub fn local_name(&self) -> &str {
+        split_qname(self.name()).1
+    }
+
+    /// The namespace name of the attribute. Unprefixed attributes are in no
+    /// namespace, apart from `xmlns` itself.
+    pub fn namespace_uri(&self) -> Option<&str> {
+        self.namespace.as_deref()
+    }
+
+    /// The attribute name as `{uri}local`, or just the local part when the
+    /// attribute is in no namespace
+    pub fn expanded_name(&self) -> String {
+        expanded_name(self.namespace_uri(), self.local_name())
+    }
 }
 
-struct AttValue {
+/// A quoted attribute value, as given in a start tag or as a default in `<!ATTLIST>`
+#[derive(Clone)]
+pub struct AttValue {
     start: usize,
     items: Vec<AttValueItem>,
 }
 
+impl AttValue {
+    /// Reassemble the literal text between the qoutes
+    pub fn raw(&self) -> String {
+        let mut buf = String::new();
+        for item in &self.items {
+            match item {
+                AttValueItem::Text(s) => buf.push_str(s),
+                AttValueItem::Reference(reference) => buf.push_str(&reference.to_string()),
+                AttValueItem::Entity { name, .. } => buf.push_str(&format!("&{};", name)),
+            }
+        }
+        buf
+    }
+
+    /// The value with character references and predefined entities
+    /// decoded. References to other entities are kept as written.
+    pub fn value(&self) -> String {
+        let mut buf = String::new();
+        for item in &self.items {
+            match item {
+                AttValueItem::Text(s) => buf.push_str(s),
+                AttValueItem::Reference(reference) => push_decoded(&mut buf, reference),
+                AttValueItem::Entity { text, .. } => buf.push_str(text),
+            }
+        }
+        buf
+    }
+
+    /// The value normalized for an attribute of type `att_type`, as
+    /// `Attribute::value` describes
+    pub fn normalized(&self, att_type: &AttType, version: XmlVersion) -> String {
+        self.normalize(att_type != &AttType::CData, version)
+    }
+
+    fn normalize(&self, tokenized: bool, version: XmlVersion) -> String {
+        let mut buf = String::new();
+        for item in &self.items {
+            match item {
+                // a line break written as `\r\n` is a single character
+                AttValueItem::Text(s) | AttValueItem::Entity { text: s, .. } => {
+                    let mut prev = '\0';
+                    for c in s.chars() {
+                        if c == '\t' || version.is_line_end(prev, c) {
+                            buf.push(' ');
+                        } else if !(prev == '\r' && version.is_line_end('\0', c)) {
+                            buf.push(c);
+                        }
+                        prev = c;
+                    }
+                }
+                // white space written as a character reference is kept
+                AttValueItem::Reference(reference) => push_decoded(&mut buf, reference),
+            };
+        }
+        if tokenized {
+            buf.split(' ').filter(|token| !token.is_empty()).collect::<Vec<_>>().join(" ")
+        } else {
+            buf
+        }
+    }
+}
+
+#[derive(Clone)]
 enum AttValueItem {
     Text(String),
     Reference(Reference),
+    /// a reference to an internal entity, with the text it expands to
+    Entity { name: Name, text: String },
 }
 
 impl AttValueItem {
@@ -2051,16 +3813,45 @@ impl AttValueItem {
         match &self {
             AttValueItem::Text(s) => s.len(),
             AttValueItem::Reference(reference) => reference.text_len(),
+            AttValueItem::Entity { name, .. } => name.0.len() + 2,
         }
     }
 }
 
-enum Reference {
+#[derive(Debug, Clone)]
+pub enum Reference {
+    /// a general entity reference such as `&amp;`
     EntityRef(Name),
+    /// a character reference such as `&#65;` or `&#x41;`, holding the text
+    /// between `&#` and `;`
     CharRef(String),
 }
 
+impl fmt::Display for Reference {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self {
+            Reference::EntityRef(name) => write!(f, "&{};", name),
+            Reference::CharRef(s) => write!(f, "&#{};", s),
+        }
+    }
+}
+
 impl Reference {
+    /// The character the reference stands for. Character references give
+    /// the referenced character, and the five predefined entities `lt`,
+    /// `gt`, `amp`, `apos` and `quot` give their replacement. Other entity
+    /// references depend on declarations in the DTD and give `None`.
+    pub fn decode(&self) -> Option<char> {
+        match &self {
+            Reference::CharRef(s) => match s.strip_prefix('x') {
+                Some(hex) => u32::from_str_radix(hex, 16).ok(),
+                None => s.parse::<u32>().ok(),
+            }
+            .and_then(char::from_u32),
+            Reference::EntityRef(name) => predefined_entity(name.as_str()),
+        }
+    }
+
     fn text_len(&self) -> usize {
         match &self {
             Reference::EntityRef(name) => name.0.len() + 2,
@@ -2069,30 +3860,103 @@ impl Reference {
     }
 }
 
+/// The replacement of one of the five entities every document may use undeclared
+fn predefined_entity(name: &str) -> Option<char> {
+    match name {
+        "lt" => Some('<'),
+        "gt" => Some('>'),
+        "amp" => Some('&'),
+        "apos" => Some('\''),
+        "quot" => Some('"'),
+        _ => None,
+    }
+}
+
+/// Append the decoded form of `reference` to `buf`, keeping references
+/// that cannot be decoded as written
+fn push_decoded(buf: &mut String, reference: &Reference) {
+    match reference.decode() {
+        Some(c) => buf.push(c),
+        None => buf.push_str(&reference.to_string()),
+    }
+}
+
 struct Content {
     start: usize,
     items: Vec<ContentItem>,
 }
 
-enum ContentItem {
+pub enum ContentItem {
     Elem(Box<Elem>),
     Reference { start: usize, reference: Reference },
+    /// a reference to a parsed entity, replaced by the content parsed from
+    /// its replacement text. Positions of the nodes in `items` are byte
+    /// offsets into the replacement text rather than the document.
+    Entity { start: usize, name: Name, items: Vec<ContentItem> },
     ProcInstr(ProcInstr),
     Comment(Comment),
     CharData(CharData),
     CDSect(CDSect),
 }
 
-struct CDSect {
+impl ContentItem {
+    /// The decoded text this item contributes to its element, or `None`
+    /// for comments and processing instructions
+    pub fn text(&self) -> Option<String> {
+        match &self {
+            ContentItem::ProcInstr(_) | ContentItem::Comment(_) => None,
+            _ => {
+                let mut buf = String::new();
+                self.push_text(&mut buf);
+                Some(buf)
+            }
+        }
+    }
+
+    fn push_text(&self, buf: &mut String) {
+        match &self {
+            ContentItem::Elem(elem) => {
+                for item in elem.children() {
+                    item.push_text(buf);
+                }
+            }
+            ContentItem::Reference { reference, .. } => push_decoded(buf, reference),
+            ContentItem::Entity { items, .. } => {
+                for item in items {
+                    item.push_text(buf);
+                }
+            }
+            ContentItem::CharData(chardata) => buf.push_str(&chardata.text),
+            ContentItem::CDSect(cdsect) => buf.push_str(&cdsect.text),
+            ContentItem::ProcInstr(_) | ContentItem::Comment(_) => (),
+        }
+    }
+}
+
+pub struct CDSect {
     start: usize,
     text: String,
 }
 
-struct CharData {
+impl CDSect {
+    /// The text between `<![CDATA[` and `]]>`
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+}
+
+pub struct CharData {
     start: usize,
     text: String,
 }
 
+impl CharData {
+    /// The character data as written in the document
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+}
+
 pub enum Misc {
     Ws(Ws),
     Comment(Comment),
@@ -2105,11 +3969,25 @@ pub struct Ws {
     text: String,
 }
 
+impl Ws {
+    /// The whitespace characters
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+}
+
 pub struct Comment {
     start: usize,
     text: String,
 }
 
+impl Comment {
+    /// The text between `<!--` and `-->`
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+}
+
 pub struct ProcInstr {
     start: usize,
     target: PITarget,
@@ -2117,13 +3995,56 @@ pub struct ProcInstr {
     arg: Option<String>,
 }
 
+impl ProcInstr {
+    /// The target name of the processing instruction
+    pub fn target(&self) -> &str {
+        self.target.name.as_str()
+    }
+
+    /// The instruction text following the target, if any
+    pub fn data(&self) -> Option<&str> {
+        self.arg.as_deref()
+    }
+}
+
 struct PITarget {
     name: Name,
 }
 
-struct Name(String);
+#[derive(Debug, Clone, PartialEq)]
+pub struct Name(String);
+
+impl Name {
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl fmt::Display for Name {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
 
 struct EqHelper {
-    start: usize,
     end: usize,
 }
+
+struct TextDeclHelper {
+    end: usize,
+}
+
+struct ContentSpecHelper {
+    end: usize,
+    spec: ContentSpec,
+}
+
+struct AttTypeHelper {
+    end: usize,
+    att_type: AttType,
+}
+
+struct DefaultDeclHelper {
+    end: usize,
+    default_decl: DefaultDecl,
+}
@@ -0,0 +1,182 @@
+use crate::error::{NamespaceError, XmlError, XmlErrorKind};
+use crate::{expanded_name, is_namestart, split_qname, Attribute, ContentItem, Doc, Elem, Ends, Name};
+
+/// The namespace name bound to the `xml` prefix in every document
+pub const XML_NAMESPACE: &str = "http://www.w3.org/XML/1998/namespace";
+
+/// The namespace name of `xmlns` attributes, which no prefix may be bound to
+pub const XMLNS_NAMESPACE: &str = "http://www.w3.org/2000/xmlns/";
+
+/// Resolve the prefixes of every element and attribute name in the document
+/// to namespace names, checking the namespace constraints as it goes
+pub(crate) fn resolve_namespaces(doc: &mut Doc) -> Result<(), XmlError> {
+    Scope::new().resolve_elem(&mut doc.elem)
+}
+
+/// The namespace declarations in scope, innermost last
+pub(crate) struct Scope {
+    /// prefixes, with the empty string for the default namespace, and the
+    /// namespace names bound to them. `None` undeclares the default namespace.
+    bindings: Vec<(String, Option<String>)>,
+}
+
+impl Scope {
+    pub(crate) fn new() -> Scope {
+        Scope {
+            bindings: vec![(String::from("xml"), Some(String::from(XML_NAMESPACE)))],
+        }
+    }
+
+    /// How many declarations are in scope, to be passed to `leave` when the
+    /// element that made the later ones ends
+    pub(crate) fn depth(&self) -> usize {
+        self.bindings.len()
+    }
+
+    pub(crate) fn leave(&mut self, depth: usize) {
+        self.bindings.truncate(depth);
+    }
+
+    fn lookup(&self, prefix: &str) -> Option<&str> {
+        self.bindings
+            .iter()
+            .rev()
+            .find(|(bound, _)| bound == prefix)
+            .and_then(|(_, uri)| uri.as_deref())
+    }
+
+    fn resolve_elem(&mut self, elem: &mut Elem) -> Result<(), XmlError> {
+        let (start, name, attribs, namespace, content, tag) = match elem {
+            Elem::Empty(empty) => (empty.start, &empty.name, &mut empty.attribs, &mut empty.namespace, None, "EmptyElemTag"),
+            Elem::Full(full) => (
+                full.start.start,
+                &full.start.name,
+                &mut full.start.attribs,
+                &mut full.start.namespace,
+                full.content.as_mut(),
+                "STag",
+            ),
+        };
+        let depth = self.depth();
+        *namespace = self.resolve_tag(start, name, attribs, tag)?;
+        if let Some(content) = content {
+            self.resolve_items(&mut content.items)?;
+        }
+        self.leave(depth);
+        Ok(())
+    }
+
+    /// Bring the namespace declarations among `attribs` into scope, then
+    /// resolve the names of the attributes, returning the namespace name of
+    /// the element. `tag` names the production the tag was parsed by.
+    pub(crate) fn resolve_tag(
+        &mut self,
+        start: usize,
+        name: &Name,
+        attribs: &mut [Attribute],
+        tag: &str,
+    ) -> Result<Option<String>, XmlError> {
+        let context = format!("{} `{}`", tag, name);
+        for attrib in attribs.iter() {
+            self.declare(attrib)
+                .map_err(|error| namespace_error(error, attrib.get_startpos(), &context))?;
+        }
+
+        check_qname(name.as_str()).map_err(|error| namespace_error(error, start, &context))?;
+        let namespace = match split_qname(name.as_str()).0 {
+            Some("xmlns") => {
+                let error = NamespaceError::ReservedPrefix(String::from("xmlns"));
+                return Err(namespace_error(error, start, &context));
+            }
+            Some(prefix) => match self.lookup(prefix) {
+                Some(uri) => Some(String::from(uri)),
+                None => {
+                    let error = NamespaceError::UndeclaredPrefix(String::from(prefix));
+                    return Err(namespace_error(error, start, &context));
+                }
+            },
+            None => self.lookup("").map(String::from),
+        };
+
+        for idx in 0..attribs.len() {
+            let attrib = &attribs[idx];
+            let pos = attrib.get_startpos();
+            let attrib_context = format!("Attribute `{}` in {}", attrib.name(), context);
+            check_qname(attrib.name()).map_err(|error| namespace_error(error, pos, &attrib_context))?;
+            let uri = match (attrib.prefix(), attrib.name()) {
+                (None, "xmlns") | (Some("xmlns"), _) => Some(String::from(XMLNS_NAMESPACE)),
+                (None, _) => None,
+                (Some(prefix), _) => match self.lookup(prefix) {
+                    Some(uri) => Some(String::from(uri)),
+                    None => {
+                        let error = NamespaceError::UndeclaredPrefix(String::from(prefix));
+                        return Err(namespace_error(error, pos, &attrib_context));
+                    }
+                },
+            };
+            let local = attrib.local_name();
+            if let Some(first) = attribs[..idx]
+                .iter()
+                .find(|prev| prev.namespace.as_deref() == uri.as_deref() && prev.local_name() == local)
+            {
+                let error = NamespaceError::DuplicateAttribute(expanded_name(uri.as_deref(), local));
+                return Err(namespace_error(error, pos, &attrib_context).with_related(first.get_startpos()));
+            }
+            attribs[idx].namespace = uri;
+        }
+        Ok(namespace)
+    }
+
+    fn resolve_items(&mut self, items: &mut [ContentItem]) -> Result<(), XmlError> {
+        for item in items.iter_mut() {
+            match item {
+                ContentItem::Elem(elem) => self.resolve_elem(elem)?,
+                // elements from an entity are in the scope of its reference
+                ContentItem::Entity { start, name, items } => self
+                    .resolve_items(items)
+                    .map_err(|e| e.relocate(*start).within(&format!("entity `{}`", name)))?,
+                _ => (),
+            };
+        }
+        Ok(())
+    }
+
+    /// Add the binding made by `attrib`, if it is a namespace declaration
+    fn declare(&mut self, attrib: &Attribute) -> Result<(), NamespaceError> {
+        let prefix = match (attrib.prefix(), attrib.name()) {
+            (None, "xmlns") => "",
+            (Some("xmlns"), _) => attrib.local_name(),
+            _ => return Ok(()),
+        };
+        let uri = attrib.value();
+        match prefix {
+            "xmlns" => return Err(NamespaceError::ReservedPrefix(String::from(prefix))),
+            "xml" if uri != XML_NAMESPACE => return Err(NamespaceError::ReservedPrefix(String::from(prefix))),
+            "xml" => (),
+            _ if uri == XML_NAMESPACE || uri == XMLNS_NAMESPACE => return Err(NamespaceError::ReservedNamespace(uri)),
+            "" => (),
+            _ if uri.is_empty() => return Err(NamespaceError::EmptyNamespace(String::from(prefix))),
+            _ => (),
+        };
+        let uri = if uri.is_empty() { None } else { Some(uri) };
+        self.bindings.push((String::from(prefix), uri));
+        Ok(())
+    }
+}
+
+/// Check that `name` matches the QName production: either an NCName, or two
+/// NCNames joined by a colon
+fn check_qname(name: &str) -> Result<(), NamespaceError> {
+    match split_qname(name) {
+        (Some(prefix), local)
+            if prefix.is_empty() || local.contains(':') || !local.starts_with(|c| c != ':' && is_namestart(c)) =>
+        {
+            Err(NamespaceError::BadQName(String::from(name)))
+        }
+        _ => Ok(()),
+    }
+}
+
+fn namespace_error(error: NamespaceError, doc_idx: usize, context: &str) -> XmlError {
+    XmlError::new(XmlErrorKind::Namespace(error), doc_idx, context)
+}
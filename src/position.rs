@@ -0,0 +1,67 @@
+use std::fmt;
+
+use crate::XmlVersion;
+
+/// A location in a document, as a byte offset together with the human
+/// readable line and column it falls on
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Position {
+    /// byte offset into the document
+    pub offset: usize,
+    /// line number, starting from 1
+    pub line: usize,
+    /// column number in characters, starting from 1
+    pub column: usize,
+}
+
+impl Position {
+    /// Compute the line and column of byte offset `offset` in `text`.
+    ///
+    /// Lines end at `\n`, at `\r`, or at the pair `\r\n`, which counts as a
+    /// single line end, matching the XML end-of-line handling rules. If
+    /// `text` declares itself XML 1.1, lines also end at NEL (U+0085), at
+    /// `\r` followed by NEL, and at U+2028. An
+    /// offset past the end of the text is clamped to the end, and an offset
+    /// inside a multibyte character is treated as the start of that character.
+    pub fn locate(text: &str, offset: usize) -> Position {
+        let start = Position {
+            offset: 0,
+            line: 1,
+            column: 1,
+        };
+        Position::locate_from(start, text, offset, XmlVersion::of(text))
+    }
+
+    /// Compute the position of byte offset `offset` in a document written in
+    /// XML `version`, given a part of it, `text`, which begins at `base`
+    pub(crate) fn locate_from(base: Position, text: &str, offset: usize, version: XmlVersion) -> Position {
+        let mut offset = offset.saturating_sub(base.offset).min(text.len());
+        while !text.is_char_boundary(offset) {
+            offset -= 1;
+        }
+        let mut line = base.line;
+        let mut column = base.column;
+        let mut prev = '\0';
+        for c in text[..offset].chars() {
+            if version.is_line_end(prev, c) {
+                line += 1;
+                column = 1;
+            } else if !(prev == '\r' && version.is_line_end('\0', c)) {
+                // the second character of a line end takes no column
+                column += 1;
+            }
+            prev = c;
+        }
+        Position {
+            offset: base.offset + offset,
+            line,
+            column,
+        }
+    }
+}
+
+impl fmt::Display for Position {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}, column {}", self.line, self.column)
+    }
+}
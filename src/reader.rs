@@ -0,0 +1,838 @@
+use std::io::{self, Read};
+
+use crate::encoding::{self, Decoder};
+use crate::entity::{self, Budget, DeclaredEntities};
+use crate::error::{XmlError, XmlErrorKind};
+use crate::namespace::Scope;
+use crate::position::Position;
+use crate::{
+    check_attvalue_char_refs, check_attvalue_entities, check_char_ref, check_decl_char_refs, check_default_entities,
+    check_restricted_chars, declared_entities, expanded_name, parse_cdsect, parse_chardata, parse_comment,
+    parse_doctype, parse_empty_elem, parse_endtag, parse_misc, parse_pi, parse_reference, parse_starttag,
+    parse_xmldecl, push_decoded, reach, rest, split_qname, starts_misc, Attribute, Comment, ContentParticle,
+    ContentSpec, DefaultDecl, DoctypeDecl, ETag, Ends, EntityDecl, EntityDef, ExternalID, IntSubsetItem, Misc, Name,
+    NotationID, PEDef, ParseOptions, Particle, ProcInstr, Reference, STag, XmlDecl, XmlVersion,
+};
+
+/// A pull parser, reading a document one event at a time without building
+/// a tree, so that only the current event and the names of the open
+/// elements are held in memory.
+///
+/// Events are read with the same grammar productions as `parse_str`, and a
+/// document produces an error from the reader wherever `parse_str` would
+/// fail, though the error may be reported differently. That includes
+/// elements nesting deeper than `ParseOptions::max_depth`. Positions in
+/// events are byte offsets into the text.
+///
+/// The reader does not expand entities or apply attribute defaults from the
+/// DTD. References to character and predefined entities are decoded into
+/// `Text`, while references to other entities are reported as `EntityRef`.
+pub struct Reader<'a> {
+    text: &'a str,
+    pos: usize,
+    /// where the event last returned starts
+    event_start: usize,
+    state: ReaderState,
+}
+
+/// Reads a document from a byte source, such as a file, a pipe or a
+/// decompressor, producing the same events as `Reader` while holding at most
+/// a fixed number of bytes of the document in memory.
+///
+/// The source is read in the encoding that `encoding::decode` would choose
+/// for it, and positions are byte offsets into the decoded text. Every
+/// construct read as a single event, such as a start tag, comment, CDATA
+/// section or document type declaration, must fit in the buffer. Character
+/// data is split over several `Text` events where it does not fit.
+pub struct StreamReader<R> {
+    source: R,
+    /// the text read from the source that has not been read as events yet
+    window: String,
+    /// bytes read from the source before there were enough to choose the
+    /// encoding to decode them in
+    raw: Vec<u8>,
+    /// decodes the source once its encoding is known
+    decoder: Option<Decoder>,
+    /// where the window starts in the document
+    base: Position,
+    /// how far into the window events have been read
+    pos: usize,
+    eof: bool,
+    capacity: usize,
+    event_start: usize,
+    state: ReaderState,
+}
+
+/// How the reader stands between events, whichever source it reads from
+struct ReaderState {
+    stage: Stage,
+    options: ParseOptions,
+    standalone: bool,
+    version: XmlVersion,
+    /// the general entities that may be referenced
+    declared: DeclaredEntities,
+    /// the open elements, with where each starts and the namespace scope
+    /// before it
+    open: Vec<(EndElement, usize, usize)>,
+    scope: Scope,
+    /// the end of an empty element, reported after its start
+    pending_end: Option<EndElement>,
+}
+
+/// A production read from the text, before it is checked against the
+/// state of the reader
+enum Token {
+    XmlDecl(XmlDecl),
+    DocType(DoctypeDecl),
+    Ws,
+    Comment(Comment),
+    PI(ProcInstr),
+    StartTag(STag, bool),
+    EndTag(ETag),
+    Text(String),
+    CData(String),
+    EntityRef(Name, usize),
+    End,
+}
+
+/// Where the reader is in the document
+#[derive(Clone, Copy, PartialEq)]
+enum Stage {
+    Start,
+    Prolog { doctype_seen: bool },
+    Content,
+    Tail,
+    Done,
+}
+
+/// A piece of a document, as read by a `Reader`
+pub enum Event {
+    /// the `<?xml ...?>` declaration
+    XmlDecl(XmlDecl),
+    /// the document type declaration, with parameter entities expanded as
+    /// `parse_str` does
+    DocType(DoctypeDecl),
+    /// a start tag, or an empty-element tag, which is followed by a matching
+    /// `EndElement`
+    StartElement(StartElement),
+    EndElement(EndElement),
+    /// character data, with character references and predefined entities
+    /// decoded
+    Text(String),
+    /// the content of a CDATA section
+    CData(String),
+    Comment(Comment),
+    PI(ProcInstr),
+    /// a reference to a general entity other than the predefined ones,
+    /// holding its name
+    EntityRef(String),
+}
+
+/// The start of an element, with its attributes
+pub struct StartElement {
+    tag: STag,
+    empty: bool,
+}
+
+impl StartElement {
+    /// The element type name
+    pub fn name(&self) -> &str {
+        self.tag.name.as_str()
+    }
+
+    /// The prefix of the element type name, if it has one
+    pub fn prefix(&self) -> Option<&str> {
+        split_qname(self.name()).0
+    }
+
+    /// The element type name without its prefix
+    pub fn local_name(&self) -> &str {
+        split_qname(self.name()).1
+    }
+
+    /// The namespace name of the element, as `Elem::namespace_uri` describes
+    pub fn namespace_uri(&self) -> Option<&str> {
+        self.tag.namespace.as_deref()
+    }
+
+    /// The element type name as `{uri}local`
+    pub fn expanded_name(&self) -> String {
+        expanded_name(self.namespace_uri(), self.local_name())
+    }
+
+    /// The attributes given in the tag, in document order
+    pub fn attributes(&self) -> &[Attribute] {
+        &self.tag.attribs
+    }
+
+    /// Look up an attribute by name
+    pub fn attribute(&self, name: &str) -> Option<&Attribute> {
+        self.attributes().iter().find(|attrib| attrib.name() == name)
+    }
+
+    /// Whether the element was written as an empty-element tag, `<a/>`
+    pub fn is_empty(&self) -> bool {
+        self.empty
+    }
+}
+
+/// The end of an element. For an empty-element tag, this is the empty
+/// range at the end of the tag.
+pub struct EndElement {
+    start: usize,
+    end: usize,
+    name: Name,
+    namespace: Option<String>,
+}
+
+impl EndElement {
+    /// The element type name
+    pub fn name(&self) -> &str {
+        self.name.as_str()
+    }
+
+    /// The element type name without its prefix
+    pub fn local_name(&self) -> &str {
+        split_qname(self.name()).1
+    }
+
+    /// The namespace name of the element
+    pub fn namespace_uri(&self) -> Option<&str> {
+        self.namespace.as_deref()
+    }
+}
+
+impl Ends for StartElement {
+    fn get_startpos(&self) -> usize {
+        self.tag.start
+    }
+
+    fn get_endpos(&self) -> usize {
+        self.tag.end
+    }
+}
+
+impl Ends for EndElement {
+    fn get_startpos(&self) -> usize {
+        self.start
+    }
+
+    fn get_endpos(&self) -> usize {
+        self.end
+    }
+}
+
+impl<'a> Reader<'a> {
+    pub fn new(text: &'a str) -> Reader<'a> {
+        Reader::with_options(text, &ParseOptions::default())
+    }
+
+    /// Create a reader honouring the options that apply to it: those for
+    /// reading the DTD, and namespace processing
+    pub fn with_options(text: &'a str, options: &ParseOptions) -> Reader<'a> {
+        Reader {
+            text,
+            pos: 0,
+            event_start: 0,
+            state: ReaderState::new(options),
+        }
+    }
+
+    /// The byte offset where the event last returned starts
+    pub fn offset(&self) -> usize {
+        self.event_start
+    }
+
+    /// Read the next event, or `None` once the document has ended. After an
+    /// error, no more events are read.
+    pub fn read_event(&mut self) -> Result<Option<Event>, XmlError> {
+        match self.next_event() {
+            Ok(event) => Ok(event),
+            Err(e) => {
+                self.state.stage = Stage::Done;
+                Err(e.locate(self.text))
+            }
+        }
+    }
+
+    fn next_event(&mut self) -> Result<Option<Event>, XmlError> {
+        if let Some(end) = self.state.pending_end.take() {
+            self.event_start = end.start;
+            return Ok(Some(Event::EndElement(end)));
+        }
+        while self.state.stage != Stage::Done {
+            let pos = self.pos;
+            // the whole text is available, so a token is always read
+            if let Some((token, end)) = self.state.read_token(self.text, pos, true, true)? {
+                self.pos = end;
+                self.event_start = pos;
+                if let Some(event) = self.state.apply(token, self.text.len())? {
+                    return Ok(Some(event));
+                }
+            }
+        }
+        Ok(None)
+    }
+}
+
+/// The buffer size used by `StreamReader::new`
+pub const DEFAULT_BUFFER_SIZE: usize = 1 << 20;
+
+/// How many bytes are read from the source at a time
+const READ_SIZE: usize = 8192;
+
+/// How much text must be available to tell which production comes next
+const LOOKAHEAD: usize = 16;
+
+impl<R: Read> StreamReader<R> {
+    pub fn new(source: R) -> StreamReader<R> {
+        StreamReader::with_options(source, &ParseOptions::default(), DEFAULT_BUFFER_SIZE)
+    }
+
+    /// Create a reader holding at most `capacity` bytes of the document, and
+    /// honouring the options that `Reader::with_options` does
+    pub fn with_options(source: R, options: &ParseOptions, capacity: usize) -> StreamReader<R> {
+        StreamReader {
+            source,
+            window: String::new(),
+            raw: Vec::new(),
+            decoder: None,
+            base: Position::locate("", 0),
+            pos: 0,
+            eof: false,
+            capacity: capacity.max(LOOKAHEAD),
+            event_start: 0,
+            state: ReaderState::new(options),
+        }
+    }
+
+    /// The byte offset in the document where the event last returned starts
+    pub fn offset(&self) -> usize {
+        self.event_start
+    }
+
+    /// Read the next event, or `None` once the document has ended. After an
+    /// error, no more events are read.
+    pub fn read_event(&mut self) -> Result<Option<Event>, XmlError> {
+        match self.next_event() {
+            Ok(event) => Ok(event),
+            Err(e) => {
+                self.state.stage = Stage::Done;
+                Err(e.locate_from(self.base, &self.window, self.state.version))
+            }
+        }
+    }
+
+    fn next_event(&mut self) -> Result<Option<Event>, XmlError> {
+        if let Some(end) = self.state.pending_end.take() {
+            self.event_start = end.start;
+            return Ok(Some(Event::EndElement(end)));
+        }
+        while self.state.stage != Stage::Done {
+            self.discard();
+            let full = self.window.len() >= self.capacity;
+            let pos = self.pos;
+            match self.state.read_token(&self.window, pos, self.eof, full) {
+                Ok(Some((mut token, end))) => {
+                    shift_token(&mut token, self.base.offset);
+                    self.pos = end;
+                    self.event_start = self.base.offset + pos;
+                    if let Some(event) = self.state.apply(token, self.window.len())? {
+                        return Ok(Some(event));
+                    }
+                }
+                // what was read may continue past the end of the window
+                Ok(None) | Err(_) if !self.eof && !full => self.fill()?,
+                Err(e) if self.eof || self.is_complete(&e) => return Err(e.shift(self.base.offset)),
+                // the window is full, yet what was read runs to its end
+                Ok(None) | Err(_) => {
+                    let error = XmlErrorKind::BufferLimit(self.capacity);
+                    return Err(XmlError::new(error, self.base.offset + pos, "document"));
+                }
+            };
+        }
+        Ok(None)
+    }
+
+    /// Whether `error` was found in text that runs on far enough in the
+    /// window not to have been cut off. Before the document type
+    /// declaration, a production just short of the end of a full window may
+    /// fail only because the text that follows is missing.
+    fn is_complete(&self, error: &XmlError) -> bool {
+        let margin = match self.state.stage {
+            Stage::Start | Stage::Prolog { doctype_seen: false } => LOOKAHEAD,
+            _ => 0,
+        };
+        reach(error) + margin < self.window.len()
+    }
+
+    /// Drop the text that has been read from the window
+    fn discard(&mut self) {
+        self.base = Position::locate_from(self.base, &self.window, self.base.offset + self.pos, self.state.version);
+        self.window.drain(..self.pos);
+        self.pos = 0;
+    }
+
+    /// Read more text from the source into the window
+    fn fill(&mut self) -> Result<(), XmlError> {
+        let mut chunk = vec![0; READ_SIZE.min(self.capacity - self.window.len())];
+        let end = self.base.offset + self.window.len();
+        let count = loop {
+            match self.source.read(&mut chunk) {
+                Ok(count) => break count,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => (),
+                Err(e) => {
+                    let error = XmlErrorKind::Io(e.kind(), e.to_string().into());
+                    return Err(XmlError::new(error, end, "document"));
+                }
+            };
+        };
+        self.eof = count == 0;
+        self.raw.extend_from_slice(&chunk[..count]);
+        if self.decoder.is_none() {
+            if !self.eof && !encoding::can_detect(&self.raw) {
+                return Ok(());
+            }
+            let (encoding, bom) = encoding::detect_encoding(&self.raw)?;
+            self.raw.drain(..bom);
+            self.decoder = Some(Decoder::new(encoding));
+        }
+        if let Some(decoder) = self.decoder.as_mut() {
+            decoder.decode(&self.raw, &mut self.window, self.base.offset)?;
+            self.raw.clear();
+            if self.eof {
+                decoder.finish(self.base.offset + self.window.len())?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl ReaderState {
+    fn new(options: &ParseOptions) -> ReaderState {
+        ReaderState {
+            stage: Stage::Start,
+            options: options.clone(),
+            standalone: false,
+            version: XmlVersion::V1_0,
+            declared: declared_entities(None, false),
+            open: Vec::new(),
+            scope: Scope::new(),
+            pending_end: None,
+        }
+    }
+
+    /// Read the production at `pos`, returning it with where it ends, or
+    /// `None` if more text is needed to tell. `eof` says whether `text`
+    /// runs to the end of the document, and `full` whether no more text can
+    /// be made available.
+    fn read_token(&self, text: &str, pos: usize, eof: bool, full: bool) -> Result<Option<(Token, usize)>, XmlError> {
+        let token = self.read_production(text, pos, eof, full)?;
+        if let Some((_, end)) = &token {
+            check_restricted_chars(text, pos, *end, self.version)?;
+        }
+        Ok(token)
+    }
+
+    fn read_production(&self, text: &str, pos: usize, eof: bool, full: bool) -> Result<Option<(Token, usize)>, XmlError> {
+        let after = rest(text, pos);
+        if !eof && !full && after.len() < LOOKAHEAD {
+            return Ok(None);
+        }
+        let token = match self.stage {
+            Stage::Start | Stage::Prolog { .. } => {
+                if self.stage == Stage::Start {
+                    match parse_xmldecl(text, pos) {
+                        Ok(decl) => {
+                            let end = decl.get_endpos();
+                            return Ok(Some((Token::XmlDecl(decl), end)));
+                        }
+                        Err(e) if matches!(e.kind(), XmlErrorKind::BadXDeclStart) => (),
+                        Err(e) => return Err(e),
+                    };
+                }
+                if starts_misc(text, pos) {
+                    return Ok(Some(misc_token(parse_misc(text, pos)?)));
+                }
+                if !matches!(self.stage, Stage::Prolog { doctype_seen: true }) {
+                    match parse_doctype(text, pos) {
+                        Ok(doctype) => {
+                            let end = doctype.get_endpos();
+                            return Ok(Some((Token::DocType(doctype), end)));
+                        }
+                        Err(e) if matches!(e.kind(), XmlErrorKind::KeywordMatchFail) => (),
+                        Err(e) => return Err(e),
+                    };
+                }
+                read_start_tag(text, pos)?
+            }
+            Stage::Content if after.is_empty() => return Err(XmlError::new(XmlErrorKind::TextEnd, pos, "ETag")),
+            Stage::Content if after.starts_with("</") => {
+                let etag = parse_endtag(text, pos)?;
+                let end = etag.end;
+                (Token::EndTag(etag), end)
+            }
+            Stage::Content if after.starts_with("<!--") => {
+                let comment = parse_comment(text, pos)?;
+                let end = comment.get_endpos();
+                (Token::Comment(comment), end)
+            }
+            Stage::Content if after.starts_with("<?") => {
+                let pi = parse_pi(text, pos)?;
+                let end = pi.get_endpos();
+                (Token::PI(pi), end)
+            }
+            Stage::Content if after.starts_with("<![") => {
+                let cdsect = parse_cdsect(text, pos)?;
+                let end = cdsect.get_endpos();
+                (Token::CData(cdsect.text), end)
+            }
+            Stage::Content if after.starts_with('<') => read_start_tag(text, pos)?,
+            Stage::Content => match read_text(text, pos, eof, full, self.version)? {
+                Some(token) => token,
+                None => return Ok(None),
+            },
+            Stage::Tail if after.is_empty() => (Token::End, pos),
+            // let the grammar describe what is wrong
+            Stage::Tail => misc_token(parse_misc(text, pos)?),
+            Stage::Done => (Token::End, pos),
+        };
+        Ok(Some(token))
+    }
+
+    /// Check a token against the state of the reader and update it,
+    /// returning the event to report, if any. `doc_len` is the size of the
+    /// text available, against which the expansion of entities in the DTD
+    /// is limited.
+    fn apply(&mut self, token: Token, doc_len: usize) -> Result<Option<Event>, XmlError> {
+        if self.stage == Stage::Start {
+            self.stage = Stage::Prolog { doctype_seen: false };
+        }
+        let event = match token {
+            Token::XmlDecl(decl) => {
+                self.standalone = decl.standalone() == Some(true);
+                self.version = decl.xml_version();
+                Event::XmlDecl(decl)
+            }
+            Token::DocType(mut doctype) => {
+                if let Some(subset) = &doctype.int_subset {
+                    check_decl_char_refs(&subset.items, self.version)?;
+                }
+                let mut budget = Budget::new(&self.options, doc_len);
+                entity::expand_dtd(&mut doctype, &mut budget)?;
+                self.declared = declared_entities(Some(&doctype), self.standalone);
+                check_default_entities(&doctype, &self.declared)?;
+                self.stage = Stage::Prolog { doctype_seen: true };
+                Event::DocType(doctype)
+            }
+            Token::Ws => return Ok(None),
+            Token::Comment(comment) => Event::Comment(comment),
+            Token::PI(pi) => Event::PI(pi),
+            Token::StartTag(tag, empty) => self.start_element(tag, empty)?,
+            Token::EndTag(etag) => self.end_element(etag)?,
+            Token::Text(text) => Event::Text(text),
+            Token::CData(text) => Event::CData(text),
+            Token::EntityRef(name, start) => {
+                self.declared.check(&Reference::EntityRef(name.clone()), start)?;
+                Event::EntityRef(name.0)
+            }
+            Token::End => {
+                self.stage = Stage::Done;
+                return Ok(None);
+            }
+        };
+        Ok(Some(event))
+    }
+
+    fn start_element(&mut self, mut tag: STag, empty: bool) -> Result<Event, XmlError> {
+        // hold elements to the nesting limit of `parse_str`, even though the
+        // reader itself does not recurse
+        if self.open.len() >= self.options.max_depth {
+            let depth = self.open.len() as u32 + 1;
+            return Err(XmlError::new(XmlErrorKind::MaxRecurDepth(depth), tag.start, "element"));
+        }
+        for attrib in tag.attribs.iter_mut() {
+            check_attvalue_char_refs(&attrib.value, self.version)?;
+            check_attvalue_entities(&attrib.value, &self.declared)?;
+            attrib.version = self.version;
+        }
+        let depth = self.scope.depth();
+        if self.options.namespaces {
+            let production = if empty { "EmptyElemTag" } else { "STag" };
+            tag.namespace = self.scope.resolve_tag(tag.start, &tag.name, &mut tag.attribs, production)?;
+        }
+        let end = EndElement {
+            start: tag.end,
+            end: tag.end,
+            name: tag.name.clone(),
+            namespace: tag.namespace.clone(),
+        };
+        if empty {
+            self.scope.leave(depth);
+            self.pending_end = Some(end);
+        } else {
+            self.open.push((end, tag.start, depth));
+        }
+        self.stage = if self.open.is_empty() { Stage::Tail } else { Stage::Content };
+        Ok(Event::StartElement(StartElement { tag, empty }))
+    }
+
+    fn end_element(&mut self, etag: ETag) -> Result<Event, XmlError> {
+        let (mut end, start, depth) = match self.open.pop() {
+            Some(open) => open,
+            None => return Err(XmlError::new(XmlErrorKind::NoValidVariant, etag.start, "content")),
+        };
+        if end.name != etag.name {
+            return Err(XmlError::new(
+                XmlErrorKind::MismatchedTags(end.name.0, etag.name.0),
+                etag.start,
+                "ETag",
+            )
+            .with_related(start));
+        }
+        self.scope.leave(depth);
+        if self.open.is_empty() {
+            self.stage = Stage::Tail;
+        }
+        end.start = etag.start;
+        end.end = etag.end;
+        Ok(Event::EndElement(end))
+    }
+}
+
+fn misc_token(misc: Misc) -> (Token, usize) {
+    let end = misc.get_endpos();
+    let token = match misc {
+        Misc::Ws(_) => Token::Ws,
+        Misc::Comment(comment) => Token::Comment(comment),
+        Misc::ProcInstr(pi) => Token::PI(pi),
+    };
+    (token, end)
+}
+
+fn read_start_tag(text: &str, pos: usize) -> Result<(Token, usize), XmlError> {
+    // as `parse_elem` does, try the empty-element form first
+    let (tag, empty) = match parse_empty_elem(text, pos) {
+        Ok(empty) => {
+            let tag = STag {
+                start: empty.start,
+                end: empty.end,
+                name: empty.name,
+                attribs: empty.attribs,
+                namespace: None,
+            };
+            (tag, true)
+        }
+        Err(e) if matches!(e.kind(), XmlErrorKind::TextEnd) => return Err(e),
+        Err(_) => (parse_starttag(text, pos)?, false),
+    };
+    let end = tag.end;
+    Ok((Token::StartTag(tag, empty), end))
+}
+
+/// Read character data and decoded references as a single token, stopping
+/// before markup or a reference to an entity that is not predefined.
+/// Returns `None` if the text may continue past what is available.
+fn read_text(
+    text: &str,
+    pos: usize,
+    eof: bool,
+    full: bool,
+    version: XmlVersion,
+) -> Result<Option<(Token, usize)>, XmlError> {
+    let mut buf = String::new();
+    let mut here = pos;
+    // how many `]` end the last run of character data, which must be kept
+    // for the next token if the text is split, so `]]>` is still caught
+    let mut brackets = 0;
+    while here < text.len() && !rest(text, here).starts_with('<') {
+        if rest(text, here).starts_with('&') {
+            let reference = parse_reference(text, here)?;
+            check_char_ref(&reference, here, version)?;
+            if let Reference::EntityRef(name) = &reference
+                && reference.decode().is_none()
+            {
+                if buf.is_empty() {
+                    let end = here + reference.text_len();
+                    return Ok(Some((Token::EntityRef(name.clone(), here), end)));
+                }
+                break;
+            }
+            here += reference.text_len();
+            push_decoded(&mut buf, &reference);
+            brackets = 0;
+        } else {
+            let chardata = parse_chardata(text, here)?;
+            here = chardata.get_endpos();
+            brackets = chardata.text.len() - chardata.text.trim_end_matches(']').len();
+            buf.push_str(&chardata.text);
+        }
+    }
+    if here == text.len() && !eof {
+        if !full || buf.len() == brackets {
+            return Ok(None);
+        }
+        buf.truncate(buf.len() - brackets);
+        here -= brackets;
+    }
+    Ok(Some((Token::Text(buf), here)))
+}
+
+/// Move the positions in `token` on by `by` bytes, for a token read from
+/// a part of the document starting at that offset
+fn shift_token(token: &mut Token, by: usize) {
+    match token {
+        Token::StartTag(tag, _) => {
+            tag.start += by;
+            tag.end += by;
+            for attrib in tag.attribs.iter_mut() {
+                attrib.start += by;
+                attrib.end += by;
+                attrib.value.start += by;
+            }
+        }
+        Token::EndTag(etag) => {
+            etag.start += by;
+            etag.end += by;
+        }
+        Token::Comment(comment) => comment.start += by,
+        Token::PI(pi) => shift_pi(pi, by),
+        Token::EntityRef(_, start) => *start += by,
+        Token::DocType(doctype) => shift_doctype(doctype, by),
+        // nothing is read before the XML declaration, so nothing is dropped
+        Token::XmlDecl(_) => debug_assert_eq!(by, 0),
+        Token::Ws | Token::Text(_) | Token::CData(_) | Token::End => (),
+    };
+}
+
+fn shift_pi(pi: &mut ProcInstr, by: usize) {
+    pi.start += by;
+    if let Some(ws) = pi.space.as_mut() {
+        ws.start += by;
+    }
+}
+
+fn shift_doctype(doctype: &mut DoctypeDecl, by: usize) {
+    doctype.start += by;
+    doctype.end += by;
+    if let Some(ext_id) = doctype.ext_id.as_mut() {
+        shift_external_id(ext_id, by);
+    }
+    if let Some(subset) = doctype.int_subset.as_mut() {
+        subset.start += by;
+        subset.end += by;
+        shift_decls(&mut subset.items, by);
+    }
+}
+
+fn shift_decls(items: &mut [IntSubsetItem], by: usize) {
+    for item in items.iter_mut() {
+        match item {
+            IntSubsetItem::Blank(ws) => ws.start += by,
+            // the items of a parameter entity are positioned in its replacement text
+            IntSubsetItem::PEReference { start, .. } | IntSubsetItem::ParamEntity { start, .. } => *start += by,
+            IntSubsetItem::IncludeSect { start, end, items } => {
+                *start += by;
+                *end += by;
+                shift_decls(items, by);
+            }
+            IntSubsetItem::IgnoreSect { start, end } => {
+                *start += by;
+                *end += by;
+            }
+            IntSubsetItem::ElemDecl(decl) => {
+                decl.start += by;
+                decl.end += by;
+                match &mut decl.content_spec {
+                    ContentSpec::Mixed(mixed) => {
+                        mixed.start += by;
+                        mixed.end += by;
+                    }
+                    ContentSpec::Children(particle) => shift_particle(particle, by),
+                    ContentSpec::Empty | ContentSpec::Any => (),
+                };
+            }
+            IntSubsetItem::AttlistDecl(decl) => {
+                decl.start += by;
+                decl.end += by;
+                for def in decl.att_defs.iter_mut() {
+                    def.start += by;
+                    def.end += by;
+                    if let DefaultDecl::Fixed(value) | DefaultDecl::Default(value) = &mut def.default_decl {
+                        value.start += by;
+                    }
+                }
+            }
+            IntSubsetItem::EntityDecl(EntityDecl::GEDecl(decl)) => {
+                decl.start += by;
+                decl.end += by;
+                match &mut decl.entity_def {
+                    EntityDef::EntityValue(value) => value.start += by,
+                    EntityDef::External { ext_id, ndatadecl } => {
+                        shift_external_id(ext_id, by);
+                        if let Some(ndatadecl) = ndatadecl {
+                            ndatadecl.start += by;
+                            ndatadecl.end += by;
+                        }
+                    }
+                };
+            }
+            IntSubsetItem::EntityDecl(EntityDecl::PEDecl(decl)) => {
+                decl.start += by;
+                decl.end += by;
+                match &mut decl.pedef {
+                    PEDef::EntityValue(value) => value.start += by,
+                    PEDef::ExternalID(ext_id) => shift_external_id(ext_id, by),
+                };
+            }
+            IntSubsetItem::NotationDecl(decl) => {
+                decl.start += by;
+                decl.end += by;
+                match &mut decl.notation_id {
+                    NotationID::External(ext_id) => shift_external_id(ext_id, by),
+                    NotationID::Public(public_id) => {
+                        public_id.start += by;
+                        public_id.end += by;
+                    }
+                };
+            }
+            IntSubsetItem::ProcInstr(pi) => shift_pi(pi, by),
+            IntSubsetItem::Comment(comment) => comment.start += by,
+        };
+    }
+}
+
+fn shift_particle(particle: &mut ContentParticle, by: usize) {
+    particle.start += by;
+    particle.end += by;
+    if let Particle::Choice(items) | Particle::Seq(items) = &mut particle.particle {
+        for item in items.iter_mut() {
+            shift_particle(item, by);
+        }
+    }
+}
+
+fn shift_external_id(ext_id: &mut ExternalID, by: usize) {
+    match ext_id {
+        ExternalID::System { start, end, .. } | ExternalID::Public { start, end, .. } => {
+            *start += by;
+            *end += by;
+        }
+    };
+}
+
+impl Iterator for Reader<'_> {
+    type Item = Result<Event, XmlError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.read_event().transpose()
+    }
+}
+
+impl<R: Read> Iterator for StreamReader<R> {
+    type Item = Result<Event, XmlError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.read_event().transpose()
+    }
+}
@@ -0,0 +1,138 @@
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Component, Path, PathBuf};
+
+/// The content of an external entity, as loaded by an `EntityResolver`
+#[derive(Debug, Clone, PartialEq)]
+pub struct ResolvedEntity {
+    /// where the entity was loaded from, used as the base URI for relative
+    /// system identifiers within it
+    pub uri: String,
+    /// the raw content of the entity
+    pub bytes: Vec<u8>,
+}
+
+/// Loads external entities and DTD subsets for the parser.
+///
+/// The parser never touches the filesystem or network by itself; every
+/// external identifier it wants to read is passed to the resolver set in
+/// `ParseOptions`, which defaults to `DenyResolver`.
+pub trait EntityResolver: fmt::Debug + Send + Sync {
+    /// Load the entity with the given public and system identifiers.
+    /// `base_uri` is the URI of the document or entity containing the
+    /// declaration, against which a relative `system_id` is resolved.
+    ///
+    /// Returns `Ok(None)` to skip the entity, leaving references to it
+    /// unexpanded, or an error if the entity should have been loaded but
+    /// could not be.
+    fn resolve(
+        &self,
+        public_id: Option<&str>,
+        system_id: &str,
+        base_uri: Option<&str>,
+    ) -> io::Result<Option<ResolvedEntity>>;
+}
+
+/// A resolver that loads nothing, so external entities are never read
+#[derive(Debug, Clone, Copy, Default)]
+pub struct DenyResolver;
+
+impl EntityResolver for DenyResolver {
+    fn resolve(
+        &self,
+        _public_id: Option<&str>,
+        _system_id: &str,
+        _base_uri: Option<&str>,
+    ) -> io::Result<Option<ResolvedEntity>> {
+        Ok(None)
+    }
+}
+
+/// A resolver that reads system identifiers as paths relative to a root
+/// directory. Identifiers with a URI scheme, and paths that would lead
+/// outside the root, directly or through symbolic links, are refused with
+/// `PermissionDenied`.
+#[derive(Debug, Clone)]
+pub struct FsResolver {
+    /// the root directory, with symbolic links resolved when it exists
+    root: PathBuf,
+}
+
+impl FsResolver {
+    pub fn new(root: impl Into<PathBuf>) -> FsResolver {
+        let root = root.into();
+        FsResolver {
+            root: fs::canonicalize(&root).unwrap_or(root),
+        }
+    }
+
+    /// The path below the root that `system_id` refers to, relative to the
+    /// directory of `base_uri`
+    fn locate(&self, system_id: &str, base_uri: Option<&str>) -> io::Result<PathBuf> {
+        let denied = |reason: &str| io::Error::new(io::ErrorKind::PermissionDenied, format!("`{}` {}", system_id, reason));
+        let system_id = system_id.strip_prefix("file:").unwrap_or(system_id);
+        if has_scheme(system_id) {
+            return Err(denied("is not a local file"));
+        }
+        let mut path = PathBuf::new();
+        // absolute identifiers are taken relative to the root
+        if !system_id.starts_with('/')
+            && let Some(base) = base_uri
+            && let Some(dir) = Path::new(base.strip_prefix("file:").unwrap_or(base)).parent()
+        {
+            path.push(dir);
+        }
+        path.push(system_id.trim_start_matches('/'));
+        let mut normal = PathBuf::new();
+        for component in path.components() {
+            match component {
+                Component::Normal(part) => normal.push(part),
+                Component::CurDir | Component::RootDir => (),
+                Component::ParentDir => {
+                    if !normal.pop() {
+                        return Err(denied("leads outside the resolver root"));
+                    }
+                }
+                Component::Prefix(_) => return Err(denied("is not a relative path")),
+            };
+        }
+        Ok(normal)
+    }
+}
+
+impl EntityResolver for FsResolver {
+    fn resolve(
+        &self,
+        _public_id: Option<&str>,
+        system_id: &str,
+        base_uri: Option<&str>,
+    ) -> io::Result<Option<ResolvedEntity>> {
+        let path = self.locate(system_id, base_uri)?;
+        // a symbolic link below the root may still lead outside it
+        let target = fs::canonicalize(self.root.join(&path))?;
+        if !target.starts_with(&self.root) {
+            let reason = format!("`{}` links outside the resolver root", system_id);
+            return Err(io::Error::new(io::ErrorKind::PermissionDenied, reason));
+        }
+        let bytes = fs::read(target)?;
+        let uri = path
+            .components()
+            .map(|component| component.as_os_str().to_string_lossy())
+            .collect::<Vec<_>>()
+            .join("/");
+        Ok(Some(ResolvedEntity { uri, bytes }))
+    }
+}
+
+/// Whether `id` starts with a URI scheme such as `http:`
+pub(crate) fn has_scheme(id: &str) -> bool {
+    match id.split_once(':') {
+        Some((scheme, _)) => {
+            scheme.len() > 1
+                && scheme.starts_with(|c: char| c.is_ascii_alphabetic())
+                && scheme.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '+' | '-' | '.'))
+        }
+        None => false,
+    }
+}
@@ -1,10 +1,13 @@
+#![allow(clippy::assertions_on_constants)]
+
+use std::borrow::Cow;
+
 use super::*;
 
 #[test]
 fn recog_comment() {
     let text = "<!--This is a valid comment-->";
-    let chars: Vec<char> = text.chars().collect();
-    let cparse = parse_comment(&chars, 0);
+    let cparse = parse_comment(text, 0);
     match cparse {
         Ok(comment) => {
             let c_text = comment.text;
@@ -20,14 +23,13 @@ fn recog_comment() {
 #[test]
 fn reject_invalid_comment() {
     let text = "<!-- This comment contains an illegal -- substring -->";
-    let chars: Vec<char> = text.chars().collect();
-    let cparse = parse_comment(&chars, 0);
+    let cparse = parse_comment(text, 0);
     match cparse {
         Ok(_comment) => {
             assert!(false, "Failed to reject invalid comment");
         }
-        Err(e) => match e {
-            XmlError::IllegalSubstr => (),
+        Err(e) => match e.kind() {
+            XmlErrorKind::IllegalSubstr => (),
             _ => {
                 assert!(false, "Expected error variant is IllegalSubstr: {:?}", e);
             }
@@ -38,8 +40,7 @@ fn reject_invalid_comment() {
 #[test]
 fn take_pi_noarg() {
     let text = "<?NoArgumentPI?>";
-    let chars: Vec<char> = text.chars().collect();
-    let piparse = parse_pi(&chars, 0);
+    let piparse = parse_pi(text, 0);
     match piparse {
         Ok(pi) => {
             assert_eq!(pi.target.name.0, "NoArgumentPI");
@@ -59,8 +60,7 @@ fn take_pi_noarg() {
 #[test]
 fn take_pi_witharg() {
     let text = "<?PIname argtext1 argtext1 ?>";
-    let chars: Vec<char> = text.chars().collect();
-    let pi_parse = parse_pi(&chars, 0);
+    let pi_parse = parse_pi(text, 0);
     match pi_parse {
         Ok(pi) => {
             assert_eq!(pi.target.name.0, "PIname");
@@ -86,12 +86,11 @@ fn take_pi_witharg() {
 #[test]
 fn reject_xmlpi() {
     let text = "<?xml?>";
-    let chars: Vec<char> = text.chars().collect();
-    let pi_parse = parse_pi(&chars, 0);
+    let pi_parse = parse_pi(text, 0);
     match pi_parse {
         Ok(_) => assert!(false, "should have rejected name XML in PI context"),
-        Err(e) => match e {
-            XmlError::ReservedNameXml => (),
+        Err(e) => match e.kind() {
+            XmlErrorKind::ReservedNameXml => (),
             _ => assert!(false, "expected error ReservedNameXml, got {:?}", e),
         },
     }
@@ -100,16 +99,14 @@ fn reject_xmlpi() {
 #[test]
 fn correct_endpos_pi1() {
     let text = "<?target?>";
-    let chars: Vec<char> = text.chars().collect();
-    let pi_parse = parse_pi(&chars, 0).expect("Failed to parse example");
+    let pi_parse = parse_pi(text, 0).expect("Failed to parse example");
     assert_eq!(pi_parse.get_endpos(), text.len());
 }
 
 #[test]
 fn recognize_ws() {
     let text = " \n\t\r \n \t \r";
-    let chars: Vec<char> = text.chars().collect();
-    let ws_parse = parse_ws(&chars, 0);
+    let ws_parse = parse_ws(text, 0);
     match ws_parse {
         Ok(_) => (),
         Err(e) => assert!(false, "expected to parse whitespace, got error: {:?}", e),
@@ -119,8 +116,7 @@ fn recognize_ws() {
 #[test]
 fn recognize_misc() {
     let text1 = "    ";
-    let chars1: Vec<char> = text1.chars().collect();
-    let misc1 = parse_misc(&chars1, 0);
+    let misc1 = parse_misc(text1, 0);
     match misc1 {
         Ok(_) => (),
         Err(e) => {
@@ -132,8 +128,7 @@ fn recognize_misc() {
         }
     };
     let text2 = "<?pithing?>";
-    let chars2: Vec<char> = text2.chars().collect();
-    let misc2 = parse_misc(&chars2, 0);
+    let misc2 = parse_misc(text2, 0);
     match misc2 {
         Ok(_) => (),
         Err(e) => {
@@ -141,8 +136,7 @@ fn recognize_misc() {
         }
     };
     let text3 = "<!-- Comment text -->";
-    let chars3: Vec<char> = text3.chars().collect();
-    let misc3 = parse_misc(&chars3, 0);
+    let misc3 = parse_misc(text3, 0);
     match misc3 {
         Ok(_) => (),
         Err(e) => {
@@ -154,8 +148,7 @@ fn recognize_misc() {
 #[test]
 fn recognize_tail() {
     let text = "  <!-- this is a comment --> \t <?parse_instruct argument includes this?> \n  ";
-    let chars: Vec<char> = text.chars().collect();
-    let tail_parse = parse_tail(&chars, 0);
+    let tail_parse = parse_tail(text, 0);
     match tail_parse {
         Ok(_) => (),
         Err(e) => {
@@ -167,8 +160,7 @@ fn recognize_tail() {
 #[test]
 fn recognize_empty_noarg() {
     let text = "<EmptyTag/>";
-    let chars: Vec<char> = text.chars().collect();
-    let empty_parse = parse_empty_elem(&chars, 0);
+    let empty_parse = parse_empty_elem(text, 0);
     match empty_parse {
         Ok(empty) => {
             assert_eq!(empty.name.0, "EmptyTag");
@@ -182,11 +174,10 @@ fn recognize_empty_noarg() {
 #[test]
 fn recognize_empty_trailws() {
     let text = "<EmptyTrail    />";
-    let chars: Vec<char> = text.chars().collect();
-    let empty_parse = parse_empty_elem(&chars, 0);
+    let empty_parse = parse_empty_elem(text, 0);
     match empty_parse {
         Ok(empty) => {
-            assert_eq!(empty.get_endpos(), chars.len())
+            assert_eq!(empty.get_endpos(), text.len())
         }
         Err(e) => assert!(false, "should be valid parse, instead got error: {:?}", e),
     }
@@ -195,8 +186,7 @@ fn recognize_empty_trailws() {
 #[test]
 fn recognize_attval() {
     let text = "'thing text'";
-    let chars: Vec<char> = text.chars().collect();
-    let attval_parse = parse_attvalue(&chars, 0);
+    let attval_parse = parse_attvalue(text, 0);
     match attval_parse {
         Ok(attval) => {
             let v0 = &attval.items[0];
@@ -204,7 +194,7 @@ fn recognize_attval() {
                 AttValueItem::Text(s) => assert_eq!(s, "thing text"),
                 _ => assert!(false, "did not expect to recognize reference"),
             };
-            assert_eq!(attval.get_endpos(), chars.len());
+            assert_eq!(attval.get_endpos(), text.len());
         }
         Err(e) => assert!(false, "should be valid parse, instead got error: {:?}", e),
     }
@@ -213,11 +203,10 @@ fn recognize_attval() {
 #[test]
 fn recognize_attribute() {
     let text = "AttribName = 'value text'";
-    let chars: Vec<char> = text.chars().collect();
-    let attrib_parse = parse_attribute(&chars, 0);
+    let attrib_parse = parse_attribute(text, 0);
     match attrib_parse {
         Ok(attrib) => {
-            assert_eq!(attrib.get_endpos(), chars.len());
+            assert_eq!(attrib.get_endpos(), text.len());
             assert_eq!(attrib.name.0, "AttribName");
         }
         Err(e) => assert!(false, "should be valid parse, instead got error: {:?}", e),
@@ -227,8 +216,7 @@ fn recognize_attribute() {
 #[test]
 fn recognize_reference() {
     let text = "&SomeItem;";
-    let chars: Vec<char> = text.chars().collect();
-    let ref_parse = parse_reference(&chars, 0);
+    let ref_parse = parse_reference(text, 0);
     match ref_parse {
         Ok(_reference) => (),
         Err(e) => assert!(false, "should be valid parse, instead got error: {:?}", e),
@@ -238,8 +226,7 @@ fn recognize_reference() {
 #[test]
 fn recognize_empty_1arg() {
     let text = "<EmptyTag Attrib1 = \"Value 1\" />";
-    let chars: Vec<char> = text.chars().collect();
-    let empty_parse = parse_empty_elem(&chars, 0);
+    let empty_parse = parse_empty_elem(text, 0);
     match empty_parse {
         Ok(empty) => assert_eq!(empty.attribs.len(), 1),
         Err(e) => assert!(false, "should be valid parse, instead: {:?}", e),
@@ -249,10 +236,9 @@ fn recognize_empty_1arg() {
 #[test]
 fn recognize_empty_ref_2arg() {
     let text = "<EmptyTag attrib1 = \"Value 1\" attrib2 = \"&RefItem;\" />";
-    let chars: Vec<char> = text.chars().collect();
-    let empty_parse = parse_empty_elem(&chars, 0);
+    let empty_parse = parse_empty_elem(text, 0);
     match empty_parse {
-        Ok(empty) => assert_eq!(empty.get_endpos(), chars.len()),
+        Ok(empty) => assert_eq!(empty.get_endpos(), text.len()),
         Err(e) => assert!(false, "should be valid parse, instead: {:?}", e),
     }
 }
@@ -260,10 +246,9 @@ fn recognize_empty_ref_2arg() {
 #[test]
 fn recognize_end_tag() {
     let text = "</EndTag>";
-    let chars: Vec<char> = text.chars().collect();
-    let etag_parse = parse_endtag(&chars, 0);
+    let etag_parse = parse_endtag(text, 0);
     match etag_parse {
-        Ok(etag) => assert_eq!(etag.get_endpos(), chars.len()),
+        Ok(etag) => assert_eq!(etag.get_endpos(), text.len()),
         Err(e) => assert!(false, "should be valid parse, instead got: {:?}", e),
     }
 }
@@ -271,8 +256,7 @@ fn recognize_end_tag() {
 #[test]
 fn recognize_end_tag_trailws() {
     let text = "</TagSpace     >";
-    let chars: Vec<char> = text.chars().collect();
-    let etag_parse = parse_endtag(&chars, 0);
+    let etag_parse = parse_endtag(text, 0);
     match etag_parse {
         Ok(etag) => assert_eq!(etag.name.0, "TagSpace"),
         Err(e) => assert!(false, "should be valid parse, instead got: {:?}", e),
@@ -282,8 +266,7 @@ fn recognize_end_tag_trailws() {
 #[test]
 fn reject_bad_endtag() {
     let text = "</EndTag stuff that is not supposed to be here>";
-    let chars: Vec<char> = text.chars().collect();
-    let etag_parse = parse_endtag(&chars, 0);
+    let etag_parse = parse_endtag(text, 0);
     match etag_parse {
         Ok(_etag) => assert!(false, "This should be rejected"),
         Err(_e) => (),
@@ -293,10 +276,9 @@ fn reject_bad_endtag() {
 #[test]
 fn recognize_starttag() {
     let text = "<StartTag>";
-    let chars: Vec<char> = text.chars().collect();
-    let stag_parse = parse_starttag(&chars, 0);
+    let stag_parse = parse_starttag(text, 0);
     match stag_parse {
-        Ok(s_tag) => assert_eq!(s_tag.get_endpos(), chars.len()),
+        Ok(s_tag) => assert_eq!(s_tag.get_endpos(), text.len()),
         Err(e) => assert!(false, "should be valid, instead: {:?}", e),
     }
 }
@@ -304,8 +286,7 @@ fn recognize_starttag() {
 #[test]
 fn recognize_starttag_attribs() {
     let text = "<StartTag Attrib1=\"Value 1\" Attrib2=\'&RefValue2;\' >";
-    let chars: Vec<char> = text.chars().collect();
-    let stag_parse = parse_starttag(&chars, 0);
+    let stag_parse = parse_starttag(text, 0);
     match stag_parse {
         Ok(s_tag) => assert_eq!(s_tag.attribs.len(), 2),
         Err(e) => assert!(false, "should be valid, instead: {:?}", e),
@@ -315,10 +296,9 @@ fn recognize_starttag_attribs() {
 #[test]
 fn recognize_data() {
     let text = "<TagName> data goes here </TagName>";
-    let chars: Vec<char> = text.chars().collect();
-    let elem_parse = parse_elem(&chars, 0, 0);
+    let elem_parse = parse_elem(text, 0, 0, DEFAULT_MAX_DEPTH);
     match elem_parse {
-        Ok(elem) => assert_eq!(elem.get_endpos(), chars.len()),
+        Ok(elem) => assert_eq!(elem.get_endpos(), text.len()),
         Err(e) => assert!(false, "should be valid parse, instead: {:?}", e),
     }
 }
@@ -326,10 +306,9 @@ fn recognize_data() {
 #[test]
 fn recognize_cdsect() {
     let text = "<![CDATA[ this is a CDATA section ]]>";
-    let chars: Vec<char> = text.chars().collect();
-    let cdata_parse = parse_cdsect(&chars, 0);
+    let cdata_parse = parse_cdsect(text, 0);
     match cdata_parse {
-        Ok(cdsect) => assert_eq!(cdsect.get_endpos(), chars.len()),
+        Ok(cdsect) => assert_eq!(cdsect.get_endpos(), text.len()),
         Err(e) => assert!(false, "should be valid parse, instead: {:?}", e),
     }
 }
@@ -346,9 +325,7 @@ fn recognize_nested_elems() {
 
     </outer>";
 
-    let chars: Vec<char> = text.chars().collect();
-
-    let elem_parse = parse_elem(&chars, 0, 0);
+    let elem_parse = parse_elem(text, 0, 0, DEFAULT_MAX_DEPTH);
     match elem_parse {
         Ok(_elem) => (),
         Err(e) => assert!(false, "should be valid parse, instead: {:?}", e),
@@ -358,10 +335,9 @@ fn recognize_nested_elems() {
 #[test]
 fn recognize_version() {
     let text = "   version    = \t  \"1.0\"";
-    let chars: Vec<char> = text.chars().collect();
-    let ver_parse = parse_version(&chars, 0);
+    let ver_parse = parse_version(text, 0);
     match ver_parse {
-        Ok(ver) => assert_eq!(ver.get_endpos(), chars.len()),
+        Ok(ver) => assert_eq!(ver.get_endpos(), text.len()),
         Err(e) => assert!(false, "should be valid parse, instead: {:?}", e),
     }
 }
@@ -369,10 +345,9 @@ fn recognize_version() {
 #[test]
 fn recognize_encoding() {
     let text = "  encoding = 'utf-8'";
-    let chars: Vec<char> = text.chars().collect();
-    let enc_parse = parse_encoding(&chars, 0);
+    let enc_parse = parse_encoding(text, 0);
     match enc_parse {
-        Ok(enc) => assert_eq!(enc.get_endpos(), chars.len()),
+        Ok(enc) => assert_eq!(enc.get_endpos(), text.len()),
         Err(e) => assert!(false, "should be valid parse, instead: {:?}", e),
     }
 }
@@ -380,10 +355,9 @@ fn recognize_encoding() {
 #[test]
 fn recognize_standalone() {
     let text = "   standalone =  \"yes\"";
-    let chars: Vec<char> = text.chars().collect();
-    let stand_parse = parse_standalone(&chars, 0);
+    let stand_parse = parse_standalone(text, 0);
     match stand_parse {
-        Ok(stand) => assert_eq!(stand.get_endpos(), chars.len()),
+        Ok(stand) => assert_eq!(stand.get_endpos(), text.len()),
         Err(e) => assert!(false, "should be valid parse, instead: {:?}", e),
     }
 }
@@ -391,10 +365,9 @@ fn recognize_standalone() {
 #[test]
 fn recognize_xmldecl_version() {
     let text = "<?xml version = \'1.0\' ?>";
-    let chars: Vec<char> = text.chars().collect();
-    let xdecl_parse = parse_xmldecl(&chars, 0);
+    let xdecl_parse = parse_xmldecl(text, 0);
     match xdecl_parse {
-        Ok(xdecl) => assert_eq!(xdecl.get_endpos(), chars.len()),
+        Ok(xdecl) => assert_eq!(xdecl.get_endpos(), text.len()),
         Err(e) => assert!(false, "should be valid parse, instead :{:?}", e),
     }
 }
@@ -402,10 +375,9 @@ fn recognize_xmldecl_version() {
 #[test]
 fn recognize_xmldecl() {
     let text = "<?xml version = \'1.0\' encoding = \'utf-8\' standalone = \'yes\' ?>";
-    let chars: Vec<char> = text.chars().collect();
-    let xdecl_parse = parse_xmldecl(&chars, 0);
+    let xdecl_parse = parse_xmldecl(text, 0);
     match xdecl_parse {
-        Ok(xdecl) => assert_eq!(xdecl.get_endpos(), chars.len()),
+        Ok(xdecl) => assert_eq!(xdecl.get_endpos(), text.len()),
         Err(e) => assert!(false, "should be valid parse, instead: {:?}", e),
     }
 }
@@ -413,10 +385,9 @@ fn recognize_xmldecl() {
 #[test]
 fn recognize_extid1() {
     let text = "SYSTEM  \"Some sort of thing\"";
-    let chars: Vec<char> = text.chars().collect();
-    let extid_parse = parse_externalid(&chars, 0);
+    let extid_parse = parse_externalid(text, 0);
     match extid_parse {
-        Ok(extid) => assert_eq!(extid.get_endpos(), chars.len()),
+        Ok(extid) => assert_eq!(extid.get_endpos(), text.len()),
         Err(e) => assert!(false, "should be valid parse, instead: {:?}", e),
     }
 }
@@ -424,10 +395,9 @@ fn recognize_extid1() {
 #[test]
 fn recognize_extid2() {
     let text = "PUBLIC  \"another-sort\"   \"Some Sort of thing\"";
-    let chars: Vec<char> = text.chars().collect();
-    let extid_parse = parse_externalid(&chars, 0);
+    let extid_parse = parse_externalid(text, 0);
     match extid_parse {
-        Ok(extid) => assert_eq!(extid.get_endpos(), chars.len()),
+        Ok(extid) => assert_eq!(extid.get_endpos(), text.len()),
         Err(e) => assert!(false, "should be valid parse, instead: {:?}", e),
     }
 }
@@ -435,10 +405,9 @@ fn recognize_extid2() {
 #[test]
 fn recognize_simple_doctype() {
     let text = "<!DOCTYPE  Doc_Type>";
-    let chars: Vec<char> = text.chars().collect();
-    let doctype_parse = parse_doctype(&chars, 0);
+    let doctype_parse = parse_doctype(text, 0);
     match doctype_parse {
-        Ok(doctype) => assert_eq!(doctype.get_endpos(), chars.len()),
+        Ok(doctype) => assert_eq!(doctype.get_endpos(), text.len()),
         Err(e) => assert!(false, "should be valid parse, instead: {:?}", e),
     }
 }
@@ -446,10 +415,9 @@ fn recognize_simple_doctype() {
 #[test]
 fn recognize_doctype_extid() {
     let text = "<!DOCTYPE Doc_Type SYSTEM \"System Thing\" >";
-    let chars :Vec<char> = text.chars().collect();
-    let doctype_parse = parse_doctype(&chars, 0);
+    let doctype_parse = parse_doctype(text, 0);
     match doctype_parse {
-        Ok(doctype) => assert_eq!(doctype.get_endpos(), chars.len()),
+        Ok(doctype) => assert_eq!(doctype.get_endpos(), text.len()),
         Err(e) => assert!(false, "should be valid parse, instead: {:?}", e),
     }
 }
@@ -457,10 +425,9 @@ fn recognize_doctype_extid() {
 #[test]
 fn recognize_doctype_empty_intsub_explicit() {
     let text = "<!DOCTYPE Doc_type []>";
-    let chars :Vec<char> = text.chars().collect();
-    let doctype_parse = parse_doctype(&chars, 0);
+    let doctype_parse = parse_doctype(text, 0);
     match doctype_parse {
-        Ok(doctype) => assert_eq!(doctype.get_endpos(), chars.len()),
+        Ok(doctype) => assert_eq!(doctype.get_endpos(), text.len()),
         Err(e) => assert!(false, "should be valid parse, instead: {:?}", e),
     }
 }
@@ -468,10 +435,9 @@ fn recognize_doctype_empty_intsub_explicit() {
 #[test]
 fn recognize_peref() {
     let text = "%Something;";
-    let chars :Vec<char> = text.chars().collect();
-    let peref_parse = parse_pereference(&chars, 0);
+    let peref_parse = parse_pereference(text, 0);
     match peref_parse {
-        Ok(peref) => assert_eq!(peref.textlen(), chars.len()),
+        Ok(peref) => assert_eq!(peref.textlen(), text.len()),
         Err(e) => assert!(false, "should be valid parse, instead :{:?}", e),
     }
 }
@@ -479,10 +445,9 @@ fn recognize_peref() {
 #[test]
 fn recognize_public_id() {
     let text = "PUBLIC \"America\"";
-    let chars :Vec<char> = text.chars().collect();
-    let pubid_parse = parse_publicid(&chars, 0);
+    let pubid_parse = parse_publicid(text, 0);
     match pubid_parse {
-        Ok(pub_id) => assert_eq!(pub_id.get_endpos(), chars.len()),
+        Ok(pub_id) => assert_eq!(pub_id.get_endpos(), text.len()),
         Err(e) => assert!(false, "should be valid parse, instead: {:?}", e),
     }
 }
@@ -490,21 +455,19 @@ fn recognize_public_id() {
 #[test]
 fn reject_bad_public_id() {
     let text = "PUBLIC \"Americ";
-    let chars :Vec<char> = text.chars().collect();
-    let pubid_parse = parse_publicid(&chars, 0);
+    let pubid_parse = parse_publicid(text, 0);
     match pubid_parse {
         Ok(_pub_id) => assert!(false, "should reject this"),
-        Err(e) => assert_eq!(e, XmlError::TextEnd),
+        Err(e) => assert_eq!(*e.kind(), XmlErrorKind::TextEnd),
     }
 }
 
 #[test]
 fn recognize_notation_decl() {
     let text = "<!NOTATION MyNotation PUBLIC \"America\" >";
-    let chars :Vec<char> = text.chars().collect();
-    let notedecl_parse = parse_notationdecl(&chars, 0);
+    let notedecl_parse = parse_notationdecl(text, 0);
     match notedecl_parse {
-        Ok(notedecl) => assert_eq!(notedecl.get_endpos(), chars.len()),
+        Ok(notedecl) => assert_eq!(notedecl.get_endpos(), text.len()),
         Err(e) => assert!(false, "expected valid parse, instead: {:?}", e),
     }
 }
@@ -512,10 +475,9 @@ fn recognize_notation_decl() {
 #[test]
 fn recognize_notation_decl2() {
     let text = "<!NOTATION MyNotation SYSTEM \"America\" >";
-    let chars :Vec<char> = text.chars().collect();
-    let notedecl_parse = parse_notationdecl(&chars, 0);
+    let notedecl_parse = parse_notationdecl(text, 0);
     match notedecl_parse {
-        Ok(notedecl) => assert_eq!(notedecl.get_endpos(), chars.len()),
+        Ok(notedecl) => assert_eq!(notedecl.get_endpos(), text.len()),
         Err(e) => assert!(false, "expected valid parse, instead: {:?}", e),
     }
 }
@@ -523,10 +485,9 @@ fn recognize_notation_decl2() {
 #[test]
 fn recognize_ndatadecl() {
     let text = "  NDATA Something";
-    let chars :Vec<char> = text.chars().collect();
-    let ndatadecl_parse = parse_ndatadecl(&chars, 0);
+    let ndatadecl_parse = parse_ndatadecl(text, 0);
     match ndatadecl_parse {
-        Ok(ndata) => assert_eq!(ndata.get_endpos(), chars.len()),
+        Ok(ndata) => assert_eq!(ndata.get_endpos(), text.len()),
         Err(e) => assert!(false, "expected valid parse, instead: {:?}", e),
     }
 }
@@ -534,10 +495,9 @@ fn recognize_ndatadecl() {
 #[test]
 fn recognize_gedecl() {
     let text = "<!ENTITY SomeEntity \"Such Text\" >";
-    let chars :Vec<char> = text.chars().collect();
-    let gedecl_parse = parse_gedecl(&chars, 0);
+    let gedecl_parse = parse_gedecl(text, 0);
     match gedecl_parse {
-        Ok(gedecl) => assert_eq!(gedecl.get_endpos(), chars.len()),
+        Ok(gedecl) => assert_eq!(gedecl.get_endpos(), text.len()),
         Err(e) => assert!(false, "expected valid parse, instead: {:?}", e),
     }
 }
@@ -545,10 +505,9 @@ fn recognize_gedecl() {
 #[test]
 fn recognize_pedecl() {
     let text = "<!ENTITY % SomeEntity \"Such Text\" >";
-    let chars :Vec<char> = text.chars().collect();
-    let pedecl_parse = parse_pedecl(&chars, 0);
+    let pedecl_parse = parse_pedecl(text, 0);
     match pedecl_parse {
-        Ok(pedecl) => assert_eq!(pedecl.get_endpos(), chars.len()),
+        Ok(pedecl) => assert_eq!(pedecl.get_endpos(), text.len()),
         Err(e) => assert!(false, "expected valid parse, instead: {:?}", e),
     }
 }
@@ -556,10 +515,1731 @@ fn recognize_pedecl() {
 #[test]
 fn recognize_entitydecl1() {
     let text = "<!ENTITY SuchEntity SYSTEM \"Such System Lit\" >";
-    let chars :Vec<char> = text.chars().collect();
-    let entitydecl_parse = parse_entitydecl(&chars, 0);
+    let entitydecl_parse = parse_entitydecl(text, 0);
     match entitydecl_parse {
-        Ok(entitydecl) => assert_eq!(entitydecl.get_endpos(), chars.len()),
+        Ok(entitydecl) => assert_eq!(entitydecl.get_endpos(), text.len()),
         Err(e) => assert!(false, "expected valid parse, instead: {:?}", e),
     }
-}
\ No newline at end of file
+}
+#[test]
+fn parse_str_full_document() {
+    let text = "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<!-- prolog -->\n<r\u{e9}sum\u{e9} lang='fran\u{e7}ais'>na\u{ef}ve <b>caf\u{e9}</b></r\u{e9}sum\u{e9}>\n";
+    let doc_parse = parse_str(text);
+    match doc_parse {
+        Ok(doc) => assert_eq!(doc.elem.get_endpos(), text.len() - 1),
+        Err(e) => panic!("should be valid parse, instead: {:?}", e),
+    }
+}
+
+#[test]
+fn byte_offsets_slice_source() {
+    let text = "<\u{3b1}\u{3b2} \u{3b3}=\"\u{3b4}\u{3b5}\">\u{3b6}</\u{3b1}\u{3b2}>";
+    let stag = parse_starttag(text, 0).expect("Failed to parse example");
+    assert_eq!(&text[stag.start..stag.end], "<\u{3b1}\u{3b2} \u{3b3}=\"\u{3b4}\u{3b5}\">");
+    let attrib = &stag.attribs[0];
+    assert_eq!(&text[attrib.start..attrib.end], "\u{3b3}=\"\u{3b4}\u{3b5}\"");
+    let etag = parse_endtag(text, text.len() - "</\u{3b1}\u{3b2}>".len()).expect("Failed to parse example");
+    assert_eq!(etag.get_endpos(), text.len());
+}
+
+#[test]
+fn parse_bytes_matches_parse_str() {
+    let text = "<doc>\u{2603}</doc>";
+    let from_bytes = parse_bytes(text.as_bytes()).expect("Failed to parse example");
+    assert_eq!(from_bytes.elem.get_endpos(), text.len());
+    match parse_bytes(b"<doc>\xff</doc>") {
+        Ok(_) => panic!("should reject invalid UTF-8"),
+        Err(e) => {
+            assert_eq!(*e.kind(), XmlErrorKind::InvalidUtf8);
+            assert_eq!(e.doc_idx(), 5);
+        }
+    }
+}
+
+#[test]
+fn read_tree_through_accessors() {
+    let text = "<?xml version='1.0' encoding='utf-8' standalone='no'?>
+<!DOCTYPE catalog SYSTEM \"catalog.dtd\">
+<catalog owner=\"me &amp; you\">
+  <book id='b1'>Dune</book>
+  <!-- out of stock -->
+  <book id='b2'/>
+  <?render fast?>
+</catalog>";
+    let doc = parse_str(text).expect("Failed to parse example");
+    let decl = doc.prolog().xml_decl().expect("expected xml declaration");
+    assert_eq!(decl.version(), "1.0");
+    assert_eq!(decl.encoding(), Some("utf-8"));
+    assert_eq!(decl.standalone(), Some(false));
+    let doctype = doc.prolog().doctype().expect("expected doctype");
+    assert_eq!(doctype.name(), "catalog");
+    let ext_id = doctype.external_id().expect("expected external id");
+    assert_eq!(ext_id.system_id(), "catalog.dtd");
+    assert_eq!(ext_id.public_id(), None);
+
+    let root = doc.root();
+    assert_eq!(root.name(), "catalog");
+    assert_eq!(root.attributes().len(), 1);
+    let owner = root.attribute("owner").expect("expected owner attribute");
+    assert_eq!(owner.name(), "owner");
+    assert_eq!(owner.value(), "me & you");
+    assert_eq!(owner.raw_value(), "me &amp; you");
+
+    let books: Vec<&Elem> = root.child_elems().collect();
+    assert_eq!(books.len(), 2);
+    assert_eq!(books[0].attribute("id").map(|a| a.value()), Some(String::from("b1")));
+    match &books[0].children()[0] {
+        ContentItem::CharData(chardata) => assert_eq!(chardata.text(), "Dune"),
+        _ => panic!("expected character data"),
+    }
+    assert!(books[1].children().is_empty());
+    let comments = root
+        .children()
+        .iter()
+        .filter(|item| matches!(item, ContentItem::Comment(c) if c.text() == " out of stock "))
+        .count();
+    assert_eq!(comments, 1);
+    let pi = root.children().iter().find_map(|item| match item {
+        ContentItem::ProcInstr(pi) => Some(pi),
+        _ => None,
+    });
+    let pi = pi.expect("expected processing instruction");
+    assert_eq!(pi.target(), "render");
+    assert_eq!(pi.data(), Some("fast"));
+
+    let text = "<!DOCTYPE a [<!NOTATION gif PUBLIC 'image/gif'><!NOTATION png SYSTEM 'view-png'>]><a/>";
+    let doc = parse_str(text).expect("Failed to parse example");
+    let doctype = doc.prolog().doctype().expect("expected doctype");
+    let notations: Vec<(&str, Option<&str>, Option<&str>)> = doctype
+        .notation_decls()
+        .map(|notation| (notation.name(), notation.public_id(), notation.system_id()))
+        .collect();
+    assert_eq!(notations, vec![("gif", Some("image/gif"), None), ("png", None, Some("view-png"))]);
+}
+
+#[test]
+fn error_reports_index_and_context() {
+    let text = "<item name=\"a<b\"/>";
+    match parse_str(text) {
+        Ok(_) => panic!("should reject `<` in attribute value"),
+        Err(e) => {
+            assert_eq!(*e.kind(), XmlErrorKind::BadChar('<'));
+            assert_eq!(e.doc_idx(), 13);
+            assert_eq!(e.context(), "AttValue in STag `item`");
+        }
+    }
+}
+
+#[test]
+fn error_keeps_cause_from_alternatives() {
+    let text = "<outer><inner>text</outer>";
+    match parse_str(text) {
+        Ok(_) => panic!("should reject mismatched tags"),
+        Err(e) => {
+            assert_eq!(*e.kind(), XmlErrorKind::NoValidVariant);
+            assert_eq!(e.context(), "content");
+            assert_eq!(e.doc_idx(), 7);
+            let cause = e.underlying().expect("expected underlying error");
+            assert_eq!(
+                *cause.kind(),
+                XmlErrorKind::MismatchedTags(String::from("inner"), String::from("outer"))
+            );
+            assert_eq!(cause.doc_idx(), 18);
+            let source = std::error::Error::source(&e).expect("expected error source");
+            assert_eq!(source.to_string(), cause.to_string());
+        }
+    }
+}
+
+#[test]
+fn position_handles_line_endings() {
+    let text = "ab\ncd\r\nef\rg\u{e9}h";
+    assert_eq!(Position::locate(text, 0), Position { offset: 0, line: 1, column: 1 });
+    assert_eq!(Position::locate(text, 4), Position { offset: 4, line: 2, column: 2 });
+    assert_eq!(Position::locate(text, 7), Position { offset: 7, line: 3, column: 1 });
+    assert_eq!(Position::locate(text, 10), Position { offset: 10, line: 4, column: 1 });
+    // offsets count bytes, columns count characters
+    assert_eq!(Position::locate(text, 13), Position { offset: 13, line: 4, column: 3 });
+    assert_eq!(Position::locate(text, 12).offset, 11);
+    assert_eq!(Position::locate(text, 100).offset, text.len());
+}
+
+#[test]
+fn errors_and_nodes_report_line_and_column() {
+    let text = "<?xml version='1.0'?>\r\n<config>\r\n  <entry key='a'/>\r\n  <entry key='b>\r\n</config>";
+    match parse_str(text) {
+        Ok(_) => panic!("should reject unterminated attribute value"),
+        Err(e) => {
+            let pos = e.position().expect("parse_str should locate errors");
+            assert_eq!((pos.line, pos.column), (4, 3));
+            let cause = e.underlying().expect("expected underlying error");
+            let cause_pos = cause.position().expect("underlying errors are located too");
+            assert_eq!(cause_pos.line, 5);
+            assert!(e.to_string().starts_with("XMLError at line 4, column 3"));
+        }
+    }
+
+    let text = "<config>\n  <entry key='a'/>\n</config>";
+    let doc = parse_str(text).expect("Failed to parse example");
+    let entry = doc.root().child_elems().next().expect("expected child element");
+    assert_eq!(entry.start_position(text), Position { offset: 11, line: 2, column: 3 });
+    assert_eq!(entry.end_position(text).column, 19);
+    let attrib = &entry.attributes()[0];
+    assert_eq!(attrib.start_position(text).column, 10);
+}
+
+#[test]
+fn diagnostic_points_at_mismatched_tags() {
+    let text = "<doc>\n  <inner>\n</doc>";
+    let err = match parse_str(text) {
+        Ok(_) => panic!("should reject mismatched tags"),
+        Err(e) => e,
+    };
+    let rendered = err.diagnostic(text).to_string();
+    let expected = "error: tags `inner` and `doc` do not match
+ --> line 3, column 1
+  |
+3 | </doc>
+  | ^
+  = while parsing ETag
+  = note: opening tag `inner` is at line 2, column 3
+  |
+2 |   <inner>
+  |   ^
+";
+    assert_eq!(rendered, expected);
+}
+
+#[test]
+fn diagnostic_names_production_and_hint() {
+    let text = "<list>\n\t<item name=\"a<b\"/>\n</list>";
+    let err = match parse_str(text) {
+        Ok(_) => panic!("should reject `<` in attribute value"),
+        Err(e) => e,
+    };
+    let diag = Diagnostic::new(&err, text);
+    assert_eq!(*diag.primary().kind(), XmlErrorKind::BadChar('<'));
+    let rendered = diag.to_string();
+    assert!(rendered.contains("2 | \t<item name=\"a<b\"/>\n  | \t             ^\n"));
+    assert!(rendered.contains("= while parsing AttValue in STag `item`"));
+    assert!(rendered.contains("= note: write `<` as `&lt;` inside attribute values"));
+}
+
+#[test]
+fn recognize_elemdecl_keywords() {
+    let text = "<!ELEMENT br EMPTY>";
+    let elemdecl = parse_elemdecl(text, 0).expect("Failed to parse example");
+    assert_eq!(elemdecl.get_endpos(), text.len());
+    assert_eq!(elemdecl.name(), "br");
+    assert!(matches!(elemdecl.content_spec(), ContentSpec::Empty));
+
+    let text = "<!ELEMENT container ANY >";
+    let elemdecl = parse_elemdecl(text, 0).expect("Failed to parse example");
+    assert_eq!(elemdecl.get_endpos(), text.len());
+    assert!(matches!(elemdecl.content_spec(), ContentSpec::Any));
+}
+
+#[test]
+fn recognize_elemdecl_mixed() {
+    let text = "<!ELEMENT p ( #PCDATA | em |strong )* >";
+    let elemdecl = parse_elemdecl(text, 0).expect("Failed to parse example");
+    assert_eq!(elemdecl.get_endpos(), text.len());
+    match elemdecl.content_spec() {
+        ContentSpec::Mixed(mixed) => {
+            assert_eq!(mixed.names().collect::<Vec<_>>(), vec!["em", "strong"]);
+        }
+        _ => panic!("expected mixed content"),
+    };
+    assert_eq!(elemdecl.content_spec().to_string(), "(#PCDATA|em|strong)*");
+
+    let text = "<!ELEMENT title (#PCDATA)>";
+    let elemdecl = parse_elemdecl(text, 0).expect("Failed to parse example");
+    assert_eq!(elemdecl.content_spec().to_string(), "(#PCDATA)");
+
+    let text = "<!ELEMENT p (#PCDATA|em)>";
+    match parse_elemdecl(text, 0) {
+        Ok(_) => panic!("mixed content with names must end in `)*`"),
+        Err(e) => assert_eq!(e.doc_idx(), text.len() - 1),
+    }
+}
+
+#[test]
+fn recognize_elemdecl_children() {
+    let text = "<!ELEMENT book (title, (author | editor)+, chapter*, appendix?)>";
+    let elemdecl = parse_elemdecl(text, 0).expect("Failed to parse example");
+    assert_eq!(elemdecl.get_endpos(), text.len());
+    let cp = match elemdecl.content_spec() {
+        ContentSpec::Children(cp) => cp,
+        _ => panic!("expected element content"),
+    };
+    assert_eq!(cp.repetition(), Repetition::Once);
+    match cp.particle() {
+        Particle::Seq(items) => {
+            assert_eq!(items.len(), 4);
+            assert_eq!(items[1].repetition(), Repetition::OneOrMore);
+            assert!(matches!(items[1].particle(), Particle::Choice(choice) if choice.len() == 2));
+            assert_eq!(items[2].repetition(), Repetition::ZeroOrMore);
+            assert_eq!(items[3].repetition(), Repetition::Optional);
+        }
+        _ => panic!("expected sequence"),
+    };
+    assert_eq!(cp.to_string(), "(title,(author|editor)+,chapter*,appendix?)");
+}
+
+#[test]
+fn reject_elemdecl_mixed_separators() {
+    let text = "<!ELEMENT a (b, c | d)>";
+    match parse_elemdecl(text, 0) {
+        Ok(_) => panic!("should reject mixing `,` and `|` in one group"),
+        Err(e) => {
+            assert_eq!(*e.kind(), XmlErrorKind::BadChar('|'));
+            assert_eq!(e.context(), "seq in elementdecl `a`");
+        }
+    }
+}
+
+#[test]
+fn doctype_with_elemdecls() {
+    let text = "<!DOCTYPE memo [
+  <!ELEMENT memo (to, body)>
+  <!ELEMENT to (#PCDATA)>
+  <!ELEMENT body ANY>
+]>
+<memo><to>you</to><body/></memo>";
+    let doc = parse_str(text).expect("Failed to parse example");
+    let doctype = doc.prolog().doctype().expect("expected doctype");
+    let names: Vec<&str> = doctype.elem_decls().map(|decl| decl.name()).collect();
+    assert_eq!(names, vec!["memo", "to", "body"]);
+}
+
+#[test]
+fn recognize_attlistdecl_types() {
+    let text = "<!ATTLIST img
+  src CDATA #REQUIRED
+  id ID #IMPLIED
+  refs IDREFS #IMPLIED
+  ref IDREF #IMPLIED
+  data ENTITY #IMPLIED
+  more ENTITIES #IMPLIED
+  tok NMTOKEN #IMPLIED
+  toks NMTOKENS #IMPLIED
+  fmt NOTATION (gif | png) #IMPLIED
+  align ( left|right | 2col ) \"left\"
+>";
+    let attlist = parse_attlistdecl(text, 0).expect("Failed to parse example");
+    assert_eq!(attlist.get_endpos(), text.len());
+    assert_eq!(attlist.name(), "img");
+    let types: Vec<String> = attlist.att_defs().iter().map(|def| def.att_type().to_string()).collect();
+    assert_eq!(
+        types,
+        vec![
+            "CDATA", "ID", "IDREFS", "IDREF", "ENTITY", "ENTITIES", "NMTOKEN", "NMTOKENS",
+            "NOTATION (gif|png)", "(left|right|2col)",
+        ]
+    );
+    let align = &attlist.att_defs()[9];
+    assert_eq!(align.name(), "align");
+    assert_eq!(align.att_type(), &AttType::Enumeration(vec![
+        String::from("left"),
+        String::from("right"),
+        String::from("2col"),
+    ]));
+    match align.default_decl() {
+        DefaultDecl::Default(value) => assert_eq!(value.raw(), "left"),
+        _ => panic!("expected a default value"),
+    };
+}
+
+#[test]
+fn recognize_attlistdecl_defaults() {
+    let text = "<!ATTLIST memo version CDATA #FIXED '1.0' lang NMTOKEN 'en'>";
+    let attlist = parse_attlistdecl(text, 0).expect("Failed to parse example");
+    let defs = attlist.att_defs();
+    match defs[0].default_decl() {
+        DefaultDecl::Fixed(value) => assert_eq!(value.raw(), "1.0"),
+        _ => panic!("expected a fixed value"),
+    };
+    assert_eq!(defs[1].default_decl().value().map(|v| v.raw()), Some(String::from("en")));
+
+    let text = "<!ATTLIST memo>";
+    let attlist = parse_attlistdecl(text, 0).expect("Failed to parse example");
+    assert!(attlist.att_defs().is_empty());
+}
+
+#[test]
+fn reject_bad_attlistdecl() {
+    let text = "<!ATTLIST memo version CDATA #DEFAULT>";
+    match parse_attlistdecl(text, 0) {
+        Ok(_) => panic!("#DEFAULT is not a default declaration"),
+        Err(e) => {
+            assert_eq!(e.kind(), &XmlErrorKind::KeywordMatchFail);
+            assert_eq!(e.doc_idx(), 29);
+            assert_eq!(e.context(), "DefaultDecl in AttDef `version` in AttlistDecl `memo`");
+        }
+    };
+
+    let text = "<!ATTLIST memo kind (a|b c) #IMPLIED>";
+    match parse_attlistdecl(text, 0) {
+        Ok(_) => panic!("enumeration tokens must be separated by `|`"),
+        Err(e) => assert_eq!(e.kind(), &XmlErrorKind::BadChar('c')),
+    };
+
+    let text = "<!ATTLIST memo kind STRING #IMPLIED>";
+    match parse_attlistdecl(text, 0) {
+        Ok(_) => panic!("STRING is not an attribute type"),
+        Err(e) => assert_eq!(e.kind(), &XmlErrorKind::KeywordMatchFail),
+    };
+}
+
+#[test]
+fn doctype_with_attlistdecls() {
+    let text = "<!DOCTYPE memo [
+  <!ELEMENT memo ANY>
+  <!ATTLIST memo id ID #REQUIRED>
+]>
+<memo id='m1'/>";
+    let doc = parse_str(text).expect("Failed to parse example");
+    let doctype = doc.prolog().doctype().expect("expected doctype");
+    let attlist = doctype.attlist_decls().next().expect("expected attlist");
+    assert_eq!(attlist.name(), "memo");
+    assert_eq!(attlist.att_defs()[0].att_type(), &AttType::Id);
+    assert!(matches!(attlist.att_defs()[0].default_decl(), DefaultDecl::Required));
+}
+
+#[test]
+fn deep_nesting_is_an_error() {
+    let depth = 100_000;
+    let text = format!("{}{}", "<a>".repeat(depth), "</a>".repeat(depth));
+    match parse_str(&text) {
+        Ok(_) => panic!("nesting should be limited"),
+        Err(e) => assert!(matches!(e.kind(), XmlErrorKind::MaxRecurDepth(_))),
+    };
+
+    let text = format!("<!ELEMENT a {}b{}>", "(".repeat(depth), ")".repeat(depth));
+    match parse_elemdecl(&text, 0) {
+        Ok(_) => panic!("content model nesting should be limited"),
+        Err(e) => assert!(matches!(e.kind(), XmlErrorKind::MaxRecurDepth(_))),
+    };
+
+    let nested = |depth: usize| format!("{}{}", "<a>".repeat(depth), "</a>".repeat(depth));
+    parse_str(&nested(200)).expect("moderate nesting should parse");
+
+    // the limit counts the root element as the first level
+    let options = ParseOptions { max_depth: 20, ..ParseOptions::default() };
+    parse_str_with(&nested(20), &options).expect("nesting at the limit should parse");
+    match parse_str_with(&nested(21), &options) {
+        Ok(_) => panic!("nesting beyond the limit"),
+        Err(e) => assert_eq!((e.kind(), e.doc_idx()), (&XmlErrorKind::MaxRecurDepth(21), 60)),
+    };
+
+    // elements in replacement text are nested inside the reference
+    let text = format!("<!DOCTYPE a [<!ENTITY e '<a><a/></a>'>]>{}", nested(18).replace("<a></a>", "<a>&e;</a>"));
+    let options = ParseOptions { max_depth: 20, ..expanding() };
+    parse_str_with(&text, &options).expect("nesting at the limit should parse");
+    let text = text.replace("<a><a/></a>", "<a><a><a/></a></a>");
+    match parse_str_with(&text, &options) {
+        Ok(_) => panic!("nesting beyond the limit"),
+        Err(e) => assert!(matches!(e.kind(), XmlErrorKind::MaxRecurDepth(21))),
+    };
+}
+
+#[test]
+fn cdsect_close_after_brackets() {
+    let text = "<![CDATA[a]]]>";
+    let cdsect = parse_cdsect(text, 0).expect("Failed to parse example");
+    assert_eq!(cdsect.text(), "a]");
+}
+
+/// Small deterministic generator, so failures can be reproduced from the seed
+struct XorShift(u64);
+
+impl XorShift {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    fn below(&mut self, n: usize) -> usize {
+        (self.next() % n as u64) as usize
+    }
+}
+
+#[test]
+fn arbitrary_input_never_panics() {
+    let seeds = [
+        "<?xml version='1.0' encoding='UTF-8' standalone='yes'?>\n<!DOCTYPE a SYSTEM 'a.dtd' [\n<!ELEMENT a (b|c)*>\n<!ATTLIST a id ID #IMPLIED k (x|y) 'x'>\n<!ENTITY e 'v'>\n<!ENTITY % p SYSTEM 'p'>\n<!NOTATION n PUBLIC 'n'>\n%p;\n]>\n<a id='1'>t&e;&#65;&#x42;<b/><![CDATA[c]]><!-- c --><?pi d?></a>\n<!-- tail -->",
+        "<a b=\"c &amp; d\"><c>text</c>]]<d/></a>",
+    ];
+    let fragments = [
+        "<", ">", "</", "/>", "<?", "?>", "<!", "<!--", "-->", "<![CDATA[", "]]>", "&", ";",
+        "&#", "&#x", "%", "'", "\"", "=", "(", ")", "|", ",", "*", "#PCDATA", "<!DOCTYPE",
+        "<!ELEMENT", "<!ATTLIST", "<!ENTITY", "[", "]", " ", "\r\n", "a", "é", "\u{10000}",
+        "xml", "<?xml", "\u{0}",
+    ];
+    let mut rng = XorShift(0x9E37_79B9_7F4A_7C15);
+    for _ in 0..20_000 {
+        let mut text = String::from(seeds[rng.below(seeds.len())]);
+        for _ in 0..rng.below(8) + 1 {
+            // cut at a character boundary, then splice in a fragment
+            let mut at = rng.below(text.len() + 1);
+            while !text.is_char_boundary(at) {
+                at -= 1;
+            }
+            match rng.below(3) {
+                0 => text.truncate(at),
+                1 => text.insert_str(at, fragments[rng.below(fragments.len())]),
+                _ => {
+                    let mut end = (at + rng.below(8)).min(text.len());
+                    while !text.is_char_boundary(end) {
+                        end -= 1;
+                    }
+                    text.replace_range(at..end, "");
+                }
+            };
+        }
+        if let Err(e) = parse_str(&text) {
+            // rendering the error must not panic either
+            let _ = e.diagnostic(&text).to_string();
+        }
+        let chars: Vec<char> = text.chars().collect();
+        let _ = parse_doc(&chars);
+        let mut bytes = text.into_bytes();
+        if !bytes.is_empty() {
+            let at = rng.below(bytes.len());
+            bytes[at] = rng.next() as u8;
+        }
+        let _ = parse_bytes(&bytes);
+    }
+}
+
+#[test]
+fn decode_references() {
+    for (text, expected) in [
+        ("&#65;", Some('A')),
+        ("&#x41;", Some('A')),
+        ("&#x1F600;", Some('\u{1F600}')),
+        ("&lt;", Some('<')),
+        ("&gt;", Some('>')),
+        ("&amp;", Some('&')),
+        ("&apos;", Some('\'')),
+        ("&quot;", Some('"')),
+        ("&custom;", None),
+    ] {
+        let reference = parse_reference(text, 0).expect("Failed to parse example");
+        assert_eq!(reference.decode(), expected, "decoding {}", text);
+    }
+}
+
+#[test]
+fn reject_illegal_charrefs() {
+    for text in ["&#0;", "&#x0;", "&#xD800;", "&#xFFFE;", "&#x110000;", "&#99999999999;"] {
+        match parse_reference(text, 0) {
+            Ok(_) => panic!("{} is not a legal character", text),
+            Err(e) => assert!(matches!(e.kind(), XmlErrorKind::InvalidCharRef(_)), "{}", text),
+        };
+    }
+    for (text, idx) in [("&#;", 2), ("&#x;", 3), ("&#1a;", 3), ("&#X41;", 2)] {
+        match parse_reference(text, 0) {
+            Ok(_) => panic!("{} is not a character reference", text),
+            Err(e) => assert_eq!(e.doc_idx(), idx, "{}", text),
+        };
+    }
+}
+
+#[test]
+fn decoded_text_and_values() {
+    let text = "<p title='a &lt; b &#38; c'>x &gt; y<![CDATA[ &lt; ]]><b>&#x42;</b><!-- no --></p>";
+    let doc = parse_str(text).expect("Failed to parse example");
+    let root = doc.root();
+    assert_eq!(root.text(), "x > y &lt; B");
+    assert_eq!(root.attribute("title").map(|a| a.value()), Some(String::from("a < b & c")));
+    let items: Vec<Option<String>> = root.children().iter().map(|item| item.text()).collect();
+    assert_eq!(items, vec![
+        Some(String::from("x ")),
+        Some(String::from(">")),
+        Some(String::from(" y")),
+        Some(String::from(" &lt; ")),
+        Some(String::from("B")),
+        None,
+    ]);
+}
+
+#[test]
+fn undeclared_entities() {
+    let text = "<p>a &nbsp; b</p>";
+    match parse_str(text) {
+        Ok(_) => panic!("nbsp is not declared"),
+        Err(e) => {
+            assert_eq!(e.kind(), &XmlErrorKind::UndeclaredEntity(String::from("nbsp")));
+            assert_eq!(e.doc_idx(), 5);
+        }
+    };
+
+    let text = "<p a='&x;'/>";
+    match parse_str(text) {
+        Ok(_) => panic!("x is not declared"),
+        Err(e) => {
+            assert_eq!(e.doc_idx(), 6);
+            assert_eq!(e.context(), "EntityRef in AttValue");
+        }
+    };
+
+    let text = "<!DOCTYPE p [<!ENTITY nbsp '&#160;'>]><p>a&nbsp;b</p>";
+    let doc = parse_str(text).expect("declared entity should be accepted");
+    // only predefined entities are decoded until the DTD is applied
+    assert_eq!(doc.root().text(), "a&nbsp;b");
+
+    // the declaration might be in the external subset
+    let text = "<!DOCTYPE p SYSTEM 'p.dtd'><p>a&nbsp;b</p>";
+    parse_str(text).expect("entity may be declared externally");
+    let text = "<?xml version='1.0' standalone='yes'?><!DOCTYPE p SYSTEM 'p.dtd'><p>a&nbsp;b</p>";
+    match parse_str(text) {
+        Ok(_) => panic!("standalone documents must declare entities internally"),
+        Err(e) => assert!(matches!(e.kind(), XmlErrorKind::UndeclaredEntity(_))),
+    };
+
+    // references in replacement text and attribute defaults count too,
+    // whether or not entities are expanded
+    for (text, idx) in [
+        ("<!DOCTYPE a [<!ENTITY e 'x&undeclared;y'>]><a>&e;</a>", 46),
+        ("<!DOCTYPE a [<!ENTITY e 'x&undeclared;y'>]><a b='&e;'/>", 49),
+        ("<!DOCTYPE a [<!ENTITY f '&undeclared;'><!ENTITY e '&f;'>]><a>&e;</a>", 61),
+        ("<!DOCTYPE a [<!ATTLIST a b CDATA '&undeclared;'>]><a/>", 34),
+    ] {
+        for options in [ParseOptions::default(), expanding()] {
+            match parse_str_with(text, &options) {
+                Ok(_) => panic!("undeclared is not declared in {}", text),
+                Err(e) => {
+                    assert_eq!(e.kind(), &XmlErrorKind::UndeclaredEntity(String::from("undeclared")), "{}", text);
+                    assert_eq!(e.doc_idx(), idx, "{}", text);
+                }
+            };
+        }
+        assert!(Reader::new(text).any(|event| event.is_err()), "{}", text);
+    }
+    // an entity that is never referenced is not checked
+    parse_str("<!DOCTYPE a [<!ENTITY e '&undeclared;'>]><a/>").expect("unused entity should be accepted");
+}
+
+fn expanding() -> ParseOptions {
+    ParseOptions {
+        expand_entities: true,
+        ..ParseOptions::default()
+    }
+}
+
+#[test]
+fn entity_table_from_internal_subset() {
+    let text = "<!DOCTYPE doc [
+<!ENTITY copy '&#169; 2024 &corp;'>
+<!ENTITY corp \"ACME\">
+<!ENTITY copy 'ignored, the first declaration is binding'>
+<!ENTITY logo SYSTEM 'logo.gif' NDATA gif>
+<!ENTITY chap PUBLIC '-//ACME//Chapter//EN' 'chap.xml'>
+]><doc/>";
+    let doc = parse_str(text).expect("Failed to parse example");
+    let entities = doc.prolog().doctype().expect("expected doctype").entities();
+    assert_eq!(entities.get("copy").and_then(|e| e.replacement_text()), Some("\u{a9} 2024 &corp;"));
+    assert_eq!(entities.get("corp").and_then(|e| e.replacement_text()), Some("ACME"));
+    assert!(entities.get("logo").is_some_and(|e| e.is_unparsed()));
+    assert_eq!(entities.get("chap"), Some(&Entity::External {
+        public_id: Some(String::from("-//ACME//Chapter//EN")),
+        system_id: String::from("chap.xml"),
+        notation: None,
+        base_uri: None,
+    }));
+    assert_eq!(entities.get("missing"), None);
+}
+
+#[test]
+fn expand_entities_in_content() {
+    let text = "<!DOCTYPE doc [
+<!ENTITY corp 'ACME'>
+<!ENTITY sig '<b>&corp;</b> &#38;#60;inc&#38;#62;'>
+]><doc a='&corp; &amp; co'>by &sig;</doc>";
+    let doc = parse_str(text).expect("Failed to parse example");
+    // expansion is opt-in
+    assert!(matches!(doc.root().children()[1], ContentItem::Reference { .. }));
+
+    let doc = parse_str_with(text, &expanding()).expect("Failed to parse example");
+    let root = doc.root();
+    assert_eq!(root.text(), "by ACME <inc>");
+    assert_eq!(root.attribute("a").map(|a| a.value()), Some(String::from("ACME & co")));
+    assert_eq!(root.attribute("a").map(|a| a.raw_value()), Some(String::from("&corp; &amp; co")));
+    match &root.children()[1] {
+        ContentItem::Entity { start, name, items } => {
+            assert_eq!(name.as_str(), "sig");
+            assert_eq!(&text[*start..root.children()[1].get_endpos()], "&sig;");
+            assert_eq!(items.len(), 5);
+        }
+        _ => panic!("expected an expanded entity"),
+    };
+    let names: Vec<&str> = root.child_elems().map(|elem| elem.name()).collect();
+    assert_eq!(names, vec!["b"]);
+}
+
+#[test]
+fn reject_bad_entity_expansion() {
+    let text = "<!DOCTYPE doc [
+<!ENTITY a 'x&b;'>
+<!ENTITY b '&a;'>
+]><doc>&a;</doc>";
+    match parse_str_with(text, &expanding()) {
+        Ok(_) => panic!("recursive entities cannot be expanded"),
+        Err(e) => {
+            assert_eq!(e.kind(), &XmlErrorKind::RecursiveEntity(String::from("a")));
+            assert_eq!(e.doc_idx(), text.find("&a;</doc>").unwrap());
+        }
+    };
+
+    let text = "<!DOCTYPE doc [<!ENTITY open '<p>'>]><doc>&open;</doc>";
+    match parse_str_with(text, &expanding()) {
+        Ok(_) => panic!("replacement text must be balanced content"),
+        Err(e) => assert_eq!(e.context(), "content in entity `open`"),
+    };
+
+    let text = "<!DOCTYPE doc [<!ENTITY lt2 '&#60;'>]><doc a='&lt2;'/>";
+    match parse_str_with(text, &expanding()) {
+        Ok(_) => panic!("`<` is not allowed in attribute values"),
+        Err(e) => {
+            assert_eq!(e.kind(), &XmlErrorKind::BadChar('<'));
+            assert_eq!(e.doc_idx(), text.find("&lt2;'").unwrap());
+        }
+    };
+
+    let text = "<!DOCTYPE doc [<!ENTITY bad 'a & b'>]><doc/>";
+    match parse_str(text) {
+        Ok(_) => panic!("`&` in an entity value must start a reference"),
+        Err(e) => {
+            let primary = e.diagnostic(text).primary();
+            assert_eq!(primary.kind(), &XmlErrorKind::BadChar(' '));
+            assert_eq!(primary.context(), "Name in EntityRef in EntityValue");
+            assert_eq!(primary.position().map(|pos| pos.column), Some(33));
+        }
+    };
+
+    // external entities cannot be used in attribute values, even indirectly,
+    // nor unparsed entities in content
+    let decls = "<!DOCTYPE doc [
+<!NOTATION gif SYSTEM 'viewer'>
+<!ENTITY ext SYSTEM 'ext.xml'>
+<!ENTITY pic SYSTEM 'pic.gif' NDATA gif>
+<!ENTITY wrap 'a&ext;'>
+]>";
+    for (body, kind) in [
+        ("<doc a='x&ext;'/>", XmlErrorKind::ExternalEntityInAttValue(String::from("ext"))),
+        ("<doc a='x&wrap;'/>", XmlErrorKind::ExternalEntityInAttValue(String::from("ext"))),
+        ("<doc>x&pic;</doc>", XmlErrorKind::UnparsedEntityRef(String::from("pic"))),
+    ] {
+        let text = format!("{}{}", decls, body);
+        match parse_str_with(&text, &expanding()) {
+            Ok(_) => panic!("should reject {}", body),
+            Err(e) => {
+                assert_eq!(e.kind(), &kind);
+                assert_eq!(e.doc_idx(), text.rfind('&').unwrap(), "{}", body);
+            }
+        };
+    }
+
+    // they are well-formedness constraints, so hold when nothing is expanded
+    for (text, kind) in [
+        (
+            "<!DOCTYPE a [<!ENTITY c SYSTEM 'x'>]><a b='&c;'/>",
+            XmlErrorKind::ExternalEntityInAttValue(String::from("c")),
+        ),
+        (
+            "<!DOCTYPE a [<!ENTITY c SYSTEM 'x' NDATA n><!NOTATION n SYSTEM 'n'>]><a>&c;</a>",
+            XmlErrorKind::UnparsedEntityRef(String::from("c")),
+        ),
+        ("<!DOCTYPE a [<!ENTITY c '&c;'>]><a>&c;</a>", XmlErrorKind::RecursiveEntity(String::from("c"))),
+        ("<!DOCTYPE a [<!ENTITY c '<'>]><a b='&c;'/>", XmlErrorKind::BadChar('<')),
+    ] {
+        match parse_str(text) {
+            Ok(_) => panic!("should reject {}", text),
+            Err(e) => {
+                assert_eq!(e.kind(), &kind);
+                assert_eq!(e.doc_idx(), text.rfind('&').unwrap(), "{}", text);
+            }
+        };
+        match Reader::new(text).collect::<Result<Vec<Event>, XmlError>>() {
+            Ok(_) => panic!("reader should reject {}", text),
+            Err(e) => assert_eq!(e.kind(), &kind),
+        };
+    }
+}
+
+/// The "billion laughs" document, with `levels` entities each referring ten
+/// times to the one before
+fn laughs(levels: usize) -> String {
+    let mut text = String::from("<!DOCTYPE lolz [\n<!ENTITY lol0 'lol'>\n");
+    for level in 1..levels {
+        let refs = format!("&lol{};", level - 1).repeat(10);
+        text.push_str(&format!("<!ENTITY lol{} '{}'>\n", level, refs));
+    }
+    text.push_str(&format!("]>\n<lolz a='&lol{0};'>&lol{0};</lolz>", levels - 1));
+    text
+}
+
+#[test]
+fn entity_expansion_limits() {
+    let text = laughs(10);
+    match parse_str_with(&text, &expanding()) {
+        Ok(_) => panic!("billion laughs should be stopped"),
+        Err(e) => {
+            assert_eq!(e.kind(), &XmlErrorKind::ExpansionLimit(ExpansionLimit::Ratio(100)));
+            assert_eq!(e.doc_idx(), text.find("&lol9;").unwrap());
+        }
+    };
+
+    let options = ParseOptions {
+        max_expansion_ratio: usize::MAX,
+        max_expanded_chars: 5000,
+        ..expanding()
+    };
+    match parse_str_with(&text, &options) {
+        Ok(_) => panic!("billion laughs should be stopped"),
+        Err(e) => assert_eq!(e.kind(), &XmlErrorKind::ExpansionLimit(ExpansionLimit::TotalChars(5000))),
+    };
+
+    let options = ParseOptions {
+        max_entity_depth: 3,
+        ..expanding()
+    };
+    match parse_str_with(&laughs(5), &options) {
+        Ok(_) => panic!("entities nest too deeply"),
+        Err(e) => assert_eq!(e.kind(), &XmlErrorKind::ExpansionLimit(ExpansionLimit::Depth(3))),
+    };
+
+    // within the limits the expansion goes through
+    let doc = parse_str_with(&laughs(3), &expanding()).expect("Failed to parse example");
+    assert_eq!(doc.root().text(), "lol".repeat(100));
+    assert_eq!(doc.root().attribute("a").map(|a| a.value()), Some("lol".repeat(100)));
+}
+
+#[test]
+fn expand_parameter_entities() {
+    let text = "<!DOCTYPE doc [
+<!ENTITY % common '<!ATTLIST doc id ID #IMPLIED>'>
+<!ENTITY % body \"<!ELEMENT doc (#PCDATA)> &#37;common; <!ENTITY corp 'ACME'>\">
+%body;
+%undeclared;
+]><doc id='d'>&corp;</doc>";
+    let doc = parse_str_with(text, &expanding()).expect("Failed to parse example");
+    let doctype = doc.prolog().doctype().expect("expected doctype");
+    let elems: Vec<&str> = doctype.elem_decls().map(|decl| decl.name()).collect();
+    assert_eq!(elems, vec!["doc"]);
+    let attlists: Vec<&str> = doctype.attlist_decls().map(|decl| decl.name()).collect();
+    assert_eq!(attlists, vec!["doc"]);
+    let entities = doctype.entities();
+    assert_eq!(entities.get("corp").and_then(|e| e.replacement_text()), Some("ACME"));
+    assert!(entities.get_parameter("common").is_some());
+    assert_eq!(doc.root().text(), "ACME");
+}
+
+#[test]
+fn reject_bad_parameter_entities() {
+    let text = "<!DOCTYPE doc [
+<!ENTITY % a '%b;'>
+]><doc/>";
+    match parse_str(text) {
+        Ok(_) => panic!("parameter entities may not be used within declarations in the internal subset"),
+        Err(e) => {
+            assert_eq!(e.kind(), &XmlErrorKind::BadChar('%'));
+            assert_eq!(e.doc_idx(), text.find("%b;").unwrap());
+        }
+    };
+
+    let text = "<!DOCTYPE doc [
+<!ENTITY % a '<!ELEMENT doc ANY'>
+%a;
+]><doc/>";
+    match parse_str(text) {
+        Ok(_) => panic!("replacement text must hold complete declarations"),
+        Err(e) => {
+            assert_eq!(e.doc_idx(), text.find("%a;").unwrap());
+            assert!(e.context().ends_with("in entity `%a`"), "{}", e.context());
+        }
+    };
+
+    let text = format!("<!DOCTYPE doc [\n<!ENTITY % a '{}'>\n%a;\n]><doc/>", "<!-- x -->".repeat(20));
+    let options = ParseOptions {
+        max_expanded_chars: 100,
+        ..ParseOptions::default()
+    };
+    match parse_str_with(&text, &options) {
+        Ok(_) => panic!("expansion is limited"),
+        Err(e) => assert!(matches!(e.kind(), XmlErrorKind::ExpansionLimit(_))),
+    };
+}
+
+/// An empty directory for the files of test `name`
+fn scratch_dir(name: &str) -> std::path::PathBuf {
+    let dir = std::env::temp_dir().join(format!("xml-{}-{}", name, std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(dir.join("docs")).expect("Failed to create test directory");
+    dir
+}
+
+#[test]
+fn resolve_external_entities() {
+    let root = scratch_dir("resolver");
+    std::fs::write(root.join("docs/chapter.xml"), "<p>one &amp; <b>two</b></p>").unwrap();
+    std::fs::write(root.join("secret.txt"), "hunter2").unwrap();
+    let text = "<!DOCTYPE doc [
+<!ENTITY chapter SYSTEM 'chapter.xml'>
+<!ENTITY missing PUBLIC '-//Example//Missing//EN' 'missing.xml'>
+]><doc>&chapter;</doc>";
+
+    // nothing is loaded unless a resolver is set
+    let doc = parse_str_with(text, &expanding()).expect("Failed to parse example");
+    assert!(matches!(doc.root().children()[0], ContentItem::Reference { .. }));
+    assert_eq!(DenyResolver.resolve(None, "/etc/passwd", None).unwrap(), None);
+
+    let options = ParseOptions {
+        resolver: Arc::new(FsResolver::new(&root)),
+        base_uri: Some(String::from("docs/book.xml")),
+        ..expanding()
+    };
+    let doc = parse_str_with(text, &options).expect("Failed to parse example");
+    assert_eq!(doc.root().text(), "one & two");
+    let names: Vec<&str> = doc.root().child_elems().map(|elem| elem.name()).collect();
+    assert_eq!(names, vec!["p"]);
+
+    let resolver = FsResolver::new(&root);
+    let loaded = resolver.resolve(None, "../secret.txt", Some("docs/book.xml")).unwrap();
+    assert_eq!(loaded.map(|entity| entity.uri), Some(String::from("secret.txt")));
+    for escape in ["../../secret.txt", "http://example.com/x.dtd", "file:../secret.txt"] {
+        match resolver.resolve(None, escape, None) {
+            Err(e) => assert_eq!(e.kind(), std::io::ErrorKind::PermissionDenied, "{}", escape),
+            Ok(_) => panic!("`{}` is outside the resolver root", escape),
+        };
+    }
+    #[cfg(unix)]
+    {
+        let outside = scratch_dir("resolver-outside");
+        std::fs::write(outside.join("secret.txt"), "hunter2").unwrap();
+        std::os::unix::fs::symlink(outside.join("secret.txt"), root.join("docs/leak.xml")).unwrap();
+        std::os::unix::fs::symlink(&outside, root.join("elsewhere")).unwrap();
+        std::os::unix::fs::symlink(root.join("docs/chapter.xml"), root.join("alias.xml")).unwrap();
+        for escape in ["docs/leak.xml", "elsewhere/secret.txt"] {
+            match resolver.resolve(None, escape, None) {
+                Err(e) => assert_eq!(e.kind(), std::io::ErrorKind::PermissionDenied, "{}", escape),
+                Ok(_) => panic!("`{}` links outside the resolver root", escape),
+            };
+        }
+        // links that stay below the root are followed
+        let loaded = resolver.resolve(None, "alias.xml", None).unwrap();
+        assert_eq!(loaded.map(|entity| entity.bytes), Some(b"<p>one &amp; <b>two</b></p>".to_vec()));
+        let _ = std::fs::remove_dir_all(&outside);
+    }
+
+    let text = text.replace("&chapter;", "&missing;");
+    match parse_str_with(&text, &options) {
+        Ok(_) => panic!("entity cannot be loaded"),
+        Err(e) => {
+            assert!(matches!(e.kind(), XmlErrorKind::UnresolvedEntity(id, _) if id == "missing.xml"));
+            assert_eq!(e.doc_idx(), text.find("&missing;").unwrap());
+        }
+    };
+    let _ = std::fs::remove_dir_all(&root);
+}
+
+#[test]
+fn options_are_send_and_sync() {
+    fn assert_send_sync<T: Send + Sync>() {}
+    assert_send_sync::<ParseOptions>();
+    assert_send_sync::<Reader<'static>>();
+    assert_send_sync::<StreamReader<std::fs::File>>();
+}
+
+#[test]
+fn catalog_lookup() {
+    let root = scratch_dir("catalog");
+    std::fs::create_dir_all(root.join("dtd/docbook")).unwrap();
+    std::fs::write(root.join("dtd/docbook/chapter.ent"), "<para>vendored</para>").unwrap();
+    std::fs::write(
+        root.join("catalog.xml"),
+        "<?xml version='1.0'?>
+<catalog xmlns='urn:oasis:names:tc:entity:xmlns:xml:catalog' prefer='public'>
+  <public publicId='-//Example//ENTITIES  Chapter//EN' uri='dtd/docbook/chapter.ent'/>
+  <group xml:base='dtd/' prefer='system'>
+    <public publicId='-//Example//DTD Other//EN' uri='other.dtd'/>
+    <system systemId='http://example.com/book.dtd' uri='book.dtd'/>
+    <systemSuffix systemIdSuffix='/chapter.ent' uri='docbook/chapter.ent'/>
+  </group>
+  <rewriteSystem systemIdStartString='http://example.com/' rewritePrefix='mirror/'/>
+  <rewriteSystem systemIdStartString='http://example.com/docbook/' rewritePrefix='dtd/docbook/'/>
+  <systemSuffix systemIdSuffix='/modules/long-module-name.mod' uri='long.mod'/>
+  <x:system xmlns:x='urn:x-other' systemId='urn:x-example:foreign' uri='foreign.dtd'/>
+  <nextCatalog catalog='more/catalog'/>
+</catalog>",
+    )
+    .unwrap();
+    std::fs::create_dir_all(root.join("more")).unwrap();
+    std::fs::write(
+        root.join("more/catalog"),
+        "-- a TR9401 catalog --
+OVERRIDE YES
+PUBLIC \"-//Example//DTD Text//EN\" text.dtd
+BASE \"sub/\"
+SYSTEM 'urn:x-example:text' text.dtd",
+    )
+    .unwrap();
+    let catalog = Catalog::load(root.join("catalog.xml")).expect("Failed to load catalog");
+    let at = |path: &str| Some(root.join(path));
+
+    assert_eq!(catalog.lookup(Some("-//Example//ENTITIES Chapter//EN"), Some("ch.ent")), at("dtd/docbook/chapter.ent"));
+    assert_eq!(catalog.lookup(None, Some("http://example.com/book.dtd")), at("dtd/book.dtd"));
+    assert_eq!(catalog.lookup(None, Some("http://example.com/a/b.dtd")), at("mirror/a/b.dtd"));
+    assert_eq!(catalog.lookup(None, Some("http://example.com/docbook/x.dtd")), at("dtd/docbook/x.dtd"));
+    assert_eq!(catalog.lookup(None, Some("http://example.com/docbook/chapter.ent")), at("dtd/docbook/chapter.ent"));
+    // a rewriteSystem match is used even where a systemSuffix is longer
+    let long = "http://example.com/modules/long-module-name.mod";
+    assert_eq!(catalog.lookup(None, Some(long)), at("mirror/modules/long-module-name.mod"));
+    assert_eq!(catalog.lookup(None, Some("http://example.org/modules/long-module-name.mod")), at("long.mod"));
+    // elements outside the catalog namespace are not entries
+    assert_eq!(catalog.lookup(None, Some("urn:x-example:foreign")), None);
+    // the group prefers system identifiers
+    assert_eq!(catalog.lookup(Some("-//Example//DTD Other//EN"), Some("other.dtd")), None);
+    assert_eq!(catalog.lookup(Some("-//Example//DTD Other//EN"), None), at("dtd/other.dtd"));
+    assert_eq!(catalog.lookup(Some("-//Example//DTD Text//EN"), Some("t.dtd")), at("more/text.dtd"));
+    assert_eq!(catalog.lookup(None, Some("urn:x-example:text")), at("more/sub/text.dtd"));
+    assert_eq!(catalog.lookup(Some("-//Example//DTD Unknown//EN"), Some("unknown.dtd")), None);
+
+    let text = "<!DOCTYPE doc [
+<!ENTITY chapter PUBLIC '-//Example//ENTITIES Chapter//EN' 'http://example.org/chapter.xml'>
+]><doc>&chapter;</doc>";
+    let options = ParseOptions {
+        resolver: Arc::new(catalog),
+        ..expanding()
+    };
+    let doc = parse_str_with(text, &options).expect("Failed to parse example");
+    assert_eq!(doc.root().text(), "vendored");
+
+    std::fs::write(root.join("plain.xml"), "<catalog><system systemId='urn:x-example:plain' uri='p.dtd'/></catalog>")
+        .unwrap();
+    let plain = Catalog::load(root.join("plain.xml")).expect("Failed to load catalog");
+    assert_eq!(plain.lookup(None, Some("urn:x-example:plain")), None);
+
+    std::fs::write(root.join("bad.cat"), "PUBLIC \"-//Example//DTD Text//EN\"").unwrap();
+    assert!(matches!(Catalog::load(root.join("bad.cat")), Err(CatalogError::Syntax(..))));
+    let _ = std::fs::remove_dir_all(&root);
+}
+
+#[test]
+fn load_external_subset() {
+    let root = scratch_dir("extsubset");
+    std::fs::create_dir_all(root.join("dtd/mod")).unwrap();
+    std::fs::write(
+        root.join("dtd/book.dtd"),
+        "<?xml version='1.0' encoding='UTF-8'?>
+<!ENTITY % draft 'IGNORE'>
+<!ENTITY % final 'INCLUDE'>
+<!ENTITY % inline 'b | i'>
+<!ENTITY % common.att 'id ID #IMPLIED'>
+<!ENTITY % blocks SYSTEM 'mod/blocks.mod'>
+%blocks;
+<!ELEMENT book (title, (%block;)+)>
+<!ATTLIST book %common.att; lang CDATA 'en'>
+<!ENTITY title \"Book of %inline;\">
+<!ENTITY corp 'from the DTD'>
+<!ENTITY legal SYSTEM 'mod/legal.xml'>
+<![%final;[
+  <!ELEMENT title (#PCDATA | %inline;)*>
+  <![ IGNORE [ <!ELEMENT ignored <![ nested ]]> ANY> ]]>
+]]>
+<![%draft;[ <!ELEMENT draft ANY> ]]>",
+    )
+    .unwrap();
+    std::fs::write(
+        root.join("dtd/mod/blocks.mod"),
+        "<?xml encoding='UTF-8'?><!ENTITY % block 'para | list'>\n<!ELEMENT para (#PCDATA)>",
+    )
+    .unwrap();
+    std::fs::write(root.join("dtd/mod/legal.xml"), "<?xml encoding='UTF-8'?><para>&corp;</para>").unwrap();
+    let text = "<!DOCTYPE book SYSTEM 'dtd/book.dtd' [
+<!ENTITY corp 'ACME'>
+]><book>&title; &legal;</book>";
+    let options = ParseOptions {
+        resolver: Arc::new(FsResolver::new(&root)),
+        base_uri: Some(String::from("book.xml")),
+        load_external_dtd: true,
+        ..expanding()
+    };
+
+    // the external subset is only loaded when asked for
+    let doc = parse_str_with(text, &ParseOptions { load_external_dtd: false, ..options.clone() })
+        .expect("Failed to parse example");
+    assert_eq!(doc.prolog().doctype().and_then(|doctype| doctype.external_subset_uri()), None);
+
+    let doc = parse_str_with(text, &options).expect("Failed to parse example");
+    let doctype = doc.prolog().doctype().expect("expected doctype");
+    assert_eq!(doctype.external_subset_uri(), Some("dtd/book.dtd"));
+    let elems: Vec<&str> = doctype.elem_decls().map(|decl| decl.name()).collect();
+    assert_eq!(elems, vec!["para", "book", "title"]);
+    let book = doctype.elem_decls().nth(1).unwrap();
+    assert_eq!(book.content_spec().to_string(), "(title,(para|list)+)");
+    let atts: Vec<&str> = doctype.attlist_decls().flat_map(|decl| decl.att_defs()).map(|def| def.name()).collect();
+    assert_eq!(atts, vec!["id", "lang"]);
+    // the internal subset is read first, so its declarations are binding
+    let entities = doctype.entities();
+    assert_eq!(entities.get("corp").and_then(|e| e.replacement_text()), Some("ACME"));
+    assert_eq!(entities.get("title").and_then(|e| e.replacement_text()), Some("Book of b | i"));
+    assert_eq!(doc.root().text(), "Book of b | i ACME");
+
+    std::fs::write(root.join("dtd/bad.dtd"), "<!ELEMENT a ANY>\n\n<!ELEMENT b (c,)>").unwrap();
+    let text = "<!DOCTYPE a SYSTEM 'dtd/bad.dtd'><a/>";
+    match parse_str_with(text, &options) {
+        Ok(_) => panic!("external subset is malformed"),
+        Err(e) => {
+            assert_eq!(e.doc_idx(), text.find("SYSTEM").unwrap());
+            assert!(e.context().ends_with("`dtd/bad.dtd` line 3 in external subset"), "{}", e.context());
+        }
+    };
+
+    std::fs::write(root.join("dtd/open.dtd"), "<![INCLUDE[ <!ELEMENT a ANY>").unwrap();
+    let text = "<!DOCTYPE a SYSTEM 'dtd/open.dtd'><a/>";
+    match parse_str_with(text, &options) {
+        Ok(_) => panic!("include section is not closed"),
+        Err(e) => assert_eq!(e.kind(), &XmlErrorKind::TextEnd),
+    };
+
+    // conditional sections are not allowed in the internal subset
+    let text = "<!DOCTYPE a [<![INCLUDE[ <!ELEMENT a ANY> ]]>]><a/>";
+    assert!(parse_str_with(text, &options).is_err());
+    let _ = std::fs::remove_dir_all(&root);
+}
+
+#[test]
+fn validate_against_dtd() {
+    let text = "<!DOCTYPE memo [
+<!ELEMENT memo (to+, from?, body)>
+<!ELEMENT to (#PCDATA)>
+<!ELEMENT from (#PCDATA)>
+<!ELEMENT body (#PCDATA | em)*>
+<!ELEMENT em EMPTY>
+<!ATTLIST memo version CDATA #FIXED '1.0' id ID #REQUIRED>
+<!ATTLIST to ref IDREF #IMPLIED kind (person | list) 'person'>
+<!ENTITY sig '<from>me</from>'>
+]>
+<memo version='1.0' id='m1'><to kind=' list '>all</to><to ref='m1'>you</to>&sig;<body>Hi <em/></body></memo>";
+    let (doc, errors) = parse_str_validating(text, &ParseOptions::default()).expect("Failed to parse example");
+    assert!(errors.is_empty(), "{:?}", errors);
+    assert_eq!(doc.root().name(), "memo");
+
+    let text = "<!DOCTYPE memo [
+<!ELEMENT memo (to+, body)>
+<!ELEMENT to (#PCDATA)>
+<!ELEMENT body (#PCDATA)>
+<!ATTLIST memo version CDATA #FIXED '1.0' id ID #REQUIRED>
+<!ATTLIST to id ID #IMPLIED ref IDREFS #IMPLIED kind (person | list) 'person'>
+]>
+<memo version='2.0'>
+<body>first</body>
+<to id='a' kind='group'>x<b/></to>
+<to id='a' ref='a nowhere' extra=''/>
+</memo>";
+    let (_, errors) = parse_str_validating(text, &ParseOptions::default()).expect("Failed to parse example");
+    let found: Vec<(&XmlErrorKind, usize)> = errors
+        .iter()
+        .map(|e| (e.kind(), e.position().map(|pos| pos.line).unwrap_or_default()))
+        .collect();
+    let invalid = |error: ValidityError| XmlErrorKind::Invalid(error);
+    assert_eq!(found, vec![
+        (&invalid(ValidityError::FixedAttribute(String::from("version"))), 8),
+        (&invalid(ValidityError::MissingAttribute(String::from("id"))), 8),
+        (&invalid(ValidityError::InvalidContent(String::from("memo"), "(to+,body)".into())), 8),
+        (&invalid(ValidityError::InvalidAttributeValue(String::from("kind"))), 10),
+        (&invalid(ValidityError::InvalidContent(String::from("to"), "(#PCDATA)".into())), 10),
+        (&invalid(ValidityError::UndeclaredElement(String::from("b"))), 10),
+        (&invalid(ValidityError::DuplicateId(String::from("a"))), 11),
+        (&invalid(ValidityError::UndeclaredAttribute(String::from("extra"))), 11),
+        (&invalid(ValidityError::UnknownIdRef(String::from("nowhere"))), 11),
+    ]);
+    let duplicate = &errors[6];
+    assert_eq!(duplicate.related_idx(), text.find("id='a' kind"));
+    assert_eq!(
+        duplicate.diagnostic(text).hint(),
+        Some(String::from("the ID is first used at line 10, column 5"))
+    );
+
+    // constraints on the declarations themselves
+    let text = "<!DOCTYPE a [
+<!ELEMENT a ANY>
+<!ELEMENT b EMPTY>
+<!ELEMENT a (b)>
+<!ATTLIST a id ID #REQUIRED key ID #IMPLIED>
+<!ATTLIST b ref CDATA #IMPLIED id ID 'x' id ID #IMPLIED>
+<!ATTLIST b ref ID #IMPLIED>
+<!ENTITY % again '<!ELEMENT b ANY>'>
+%again;
+]>
+<a id='a1'/>";
+    let (_, errors) = parse_str_validating(text, &ParseOptions::default()).expect("Failed to parse example");
+    let found: Vec<(&XmlErrorKind, usize)> = errors.iter().map(|e| (e.kind(), e.doc_idx())).collect();
+    assert_eq!(found, vec![
+        (&invalid(ValidityError::DuplicateElementDecl(String::from("a"))), text.find("<!ELEMENT a (b)").unwrap()),
+        (&invalid(ValidityError::MultipleIdAttributes(String::from("a"))), text.find("<!ATTLIST a").unwrap()),
+        (&invalid(ValidityError::IdAttributeDefault(String::from("id"))), text.find("<!ATTLIST b").unwrap()),
+        (&invalid(ValidityError::DuplicateElementDecl(String::from("b"))), text.find("%again;").unwrap()),
+    ]);
+    assert_eq!(
+        errors[0].diagnostic(text).hint(),
+        Some(String::from("the element type is first declared at line 2, column 1"))
+    );
+
+    let (_, errors) = parse_str_validating("<doc/>", &ParseOptions::default()).expect("Failed to parse example");
+    assert_eq!(errors.len(), 1);
+    assert_eq!(errors[0].kind(), &XmlErrorKind::Invalid(ValidityError::NoDoctype));
+}
+
+#[test]
+fn attribute_defaults_and_normalization() {
+    let text = "<!DOCTYPE list [
+<!ENTITY sep ' | '>
+<!ATTLIST list kind (plain | fancy) 'plain' title CDATA #FIXED 'A&sep;B' owner CDATA #IMPLIED>
+<!ATTLIST item tags NMTOKENS #IMPLIED note CDATA #IMPLIED>
+<!ATTLIST list kind CDATA 'ignored'>
+]>
+<list><item tags='  a\r\n\tb  ' note='x\r\ny&#10;z&#9;'/></list>";
+    let options = ParseOptions { expand_entities: true, ..ParseOptions::default() };
+    let doc = parse_str_with(text, &options).expect("Failed to parse example");
+    let root = doc.root();
+    let names: Vec<(&str, bool)> = root.attributes().iter().map(|a| (a.name(), a.is_specified())).collect();
+    assert_eq!(names, vec![("kind", false), ("title", false)]);
+    let kind = root.attribute("kind").unwrap();
+    assert_eq!(kind.value(), "plain");
+    assert_eq!((kind.get_startpos(), kind.get_endpos()), (root.get_startpos(), root.get_startpos()));
+    assert_eq!(root.attribute("title").unwrap().value(), "A | B");
+    assert!(root.attribute("owner").is_none());
+
+    let item = root.child_elems().next().unwrap();
+    assert!(item.attributes().iter().all(|a| a.is_specified()));
+    assert_eq!(item.attribute("tags").unwrap().value(), "a b");
+    assert_eq!(item.attribute("note").unwrap().value(), "x y\nz\t");
+    assert_eq!(item.attribute("note").unwrap().raw_value(), "x\r\ny&#10;z&#9;");
+
+    // without entity expansion, the default is copied as declared
+    let doc = parse_str(text).expect("Failed to parse example");
+    assert_eq!(doc.root().attribute("title").unwrap().raw_value(), "A&sep;B");
+}
+
+#[test]
+fn resolve_namespaces() {
+    let text = "<!DOCTYPE r [<!ATTLIST x:item x:kind CDATA 'plain'><!ENTITY inner '<x:item/>'>]>
+<r xmlns='urn:default' xmlns:x='urn:x'>
+  <x:item x:id='1' id='2' xml:lang='en'/>
+  <plain xmlns=''><x:item xmlns:x='urn:other'/>&inner;</plain>
+</r>";
+    let namespaced = ParseOptions { namespaces: true, ..ParseOptions::default() };
+    let options = ParseOptions { expand_entities: true, ..namespaced.clone() };
+    let doc = parse_str_with(text, &options).expect("Failed to parse example");
+    let root = doc.root();
+    assert_eq!(root.expanded_name(), "{urn:default}r");
+    assert_eq!(root.attribute("xmlns:x").unwrap().expanded_name(), "{http://www.w3.org/2000/xmlns/}x");
+    let elems: Vec<&Elem> = root.child_elems().collect();
+    let item = elems[0];
+    assert_eq!((item.prefix(), item.local_name(), item.namespace_uri()), (Some("x"), "item", Some("urn:x")));
+    let names: Vec<String> = item.attributes().iter().map(|a| a.expanded_name()).collect();
+    assert_eq!(names, vec![
+        "{urn:x}id",
+        "id",
+        "{http://www.w3.org/XML/1998/namespace}lang",
+        "{urn:x}kind",
+    ]);
+    let plain = elems[1];
+    assert_eq!(plain.expanded_name(), "plain");
+    let nested: Vec<String> = plain.child_elems().map(|e| e.expanded_name()).collect();
+    assert_eq!(nested, vec!["{urn:other}item", "{urn:x}item"]);
+
+    let fail = |text: &str| match parse_str_with(text, &namespaced) {
+        Ok(_) => panic!("namespace error not reported"),
+        Err(e) => e,
+    };
+    let namespace = |error: NamespaceError| XmlErrorKind::Namespace(error);
+    let e = fail("<a><b:c/></a>");
+    assert_eq!(e.kind(), &namespace(NamespaceError::UndeclaredPrefix(String::from("b"))));
+    assert_eq!(e.doc_idx(), 3);
+    let e = fail("<a xmlns:p='urn:p' xmlns:q='urn:p' p:x='1' q:x='2'/>");
+    assert_eq!(e.kind(), &namespace(NamespaceError::DuplicateAttribute(String::from("{urn:p}x"))));
+    assert_eq!((e.doc_idx(), e.related_idx()), (43, Some(35)));
+    assert_eq!(
+        e.diagnostic("<a xmlns:p='urn:p' xmlns:q='urn:p' p:x='1' q:x='2'/>").hint(),
+        Some(String::from("the attribute is first given at line 1, column 36"))
+    );
+    let e = fail("<a xmlns:xml='urn:mine'/>");
+    assert_eq!(e.kind(), &namespace(NamespaceError::ReservedPrefix(String::from("xml"))));
+    let e = fail("<a xmlns:xmlns='urn:mine'/>");
+    assert_eq!(e.kind(), &namespace(NamespaceError::ReservedPrefix(String::from("xmlns"))));
+    let e = fail("<a xmlns='http://www.w3.org/2000/xmlns/'/>");
+    assert_eq!(e.kind(), &namespace(NamespaceError::ReservedNamespace(String::from(XMLNS_NAMESPACE))));
+    let e = fail("<a xmlns:p=''/>");
+    assert_eq!(e.kind(), &namespace(NamespaceError::EmptyNamespace(String::from("p"))));
+    let e = fail("<a:b:c xmlns:a='urn:a'/>");
+    assert_eq!(e.kind(), &namespace(NamespaceError::BadQName(String::from("a:b:c"))));
+    assert!(parse_str_with("<xml:a/>", &namespaced).is_ok());
+
+    // declarations are read from the normalized attribute value
+    let text = "<!DOCTYPE p:a [<!ATTLIST p:a xmlns:p NMTOKEN #IMPLIED>]><p:a xmlns:p=' urn:p\t'/>";
+    let doc = parse_str_with(text, &namespaced).expect("Failed to parse example");
+    assert_eq!(doc.root().namespace_uri(), Some("urn:p"));
+
+    // namespaces are not processed by default, so any Name is accepted
+    let doc = parse_str("<a:b:c d:e='1'/>").expect("Failed to parse example");
+    assert_eq!((doc.root().prefix(), doc.root().local_name(), doc.root().namespace_uri()), (Some("a"), "b:c", None));
+    for text in ["<a><b:c/></a>", "<x xmlns:xml='urn:y'/>"] {
+        assert!(parse_str(text).is_ok(), "{}", text);
+        assert!(parse_bytes(text.as_bytes()).is_ok(), "{}", text);
+    }
+}
+
+#[test]
+fn pull_reader_events() {
+    let text = "<?xml version='1.0'?>
+<!DOCTYPE doc [<!ENTITY who 'world'>]>
+<!-- intro -->
+<doc xmlns:p='urn:p'>Hello &amp; &who;!<p:item a='1'/><![CDATA[<raw>]]><?pi data?></doc>
+";
+    let mut events = Vec::new();
+    let mut reader = Reader::with_options(text, &ParseOptions { namespaces: true, ..ParseOptions::default() });
+    while let Some(event) = reader.read_event().expect("Failed to read example") {
+        let offset = reader.offset();
+        events.push(match event {
+            Event::XmlDecl(decl) => format!("decl {}", decl.version()),
+            Event::DocType(doctype) => format!("doctype {} {}", doctype.name(), doctype.entities().names().count()),
+            Event::StartElement(start) => {
+                assert_eq!(&text[offset..start.get_endpos()].chars().next(), &Some('<'));
+                format!("start {} {} {}", start.expanded_name(), start.attributes().len(), start.is_empty())
+            }
+            Event::EndElement(end) => format!("end {}", end.name()),
+            Event::Text(text) => format!("text {}", text),
+            Event::CData(text) => format!("cdata {}", text),
+            Event::Comment(comment) => format!("comment {}", comment.text()),
+            Event::PI(pi) => format!("pi {}", pi.target()),
+            Event::EntityRef(name) => format!("ref {}", name),
+        });
+    }
+    assert_eq!(events, vec![
+        "decl 1.0",
+        "doctype doc 1",
+        "comment  intro ",
+        "start doc 1 false",
+        "text Hello & ",
+        "ref who",
+        "text !",
+        "start {urn:p}item 1 true",
+        "end p:item",
+        "cdata <raw>",
+        "pi pi",
+        "end doc",
+    ]);
+    assert!(reader.read_event().expect("Failed to read past the end").is_none());
+
+    let text = "<a>\n  <b>text</a>";
+    let errors: Vec<XmlError> = Reader::new(text).filter_map(Result::err).collect();
+    assert_eq!(errors.len(), 1);
+    assert_eq!(errors[0].kind(), &XmlErrorKind::MismatchedTags(String::from("b"), String::from("a")));
+    assert_eq!(errors[0].related_idx(), Some(6));
+    assert_eq!(errors[0].position().map(|pos| pos.line), Some(2));
+
+    for text in ["<a>&undeclared;</a>", "<a>", "<a/><b/>", "<a/>text", "<!-- only -->"] {
+        assert!(parse_str(text).is_err());
+        assert!(Reader::new(text).any(|event| event.is_err()), "{}", text);
+    }
+    let namespaced = ParseOptions { namespaces: true, ..ParseOptions::default() };
+    assert!(parse_str_with("<p:a/>", &namespaced).is_err());
+    assert!(Reader::with_options("<p:a/>", &namespaced).any(|event| event.is_err()));
+
+    // both parsers hold elements to the same nesting limit
+    for depth in [256, 257, 100_000] {
+        let text = format!("{}{}", "<a>".repeat(depth), "</a>".repeat(depth));
+        let tree = parse_str(&text).map(|_| ()).map_err(|e| (e.kind().to_string(), e.doc_idx()));
+        let pulled = match Reader::new(&text).find_map(Result::err) {
+            Some(e) => Err((e.kind().to_string(), e.doc_idx())),
+            None => Ok(()),
+        };
+        assert_eq!(pulled, tree, "{}", depth);
+        assert_eq!(tree.is_ok(), depth <= 256);
+    }
+}
+
+/// A source handing out a few bytes at a time, as pipes do
+struct Trickle<'a> {
+    bytes: &'a [u8],
+    step: usize,
+}
+
+impl std::io::Read for Trickle<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let count = self.step.min(buf.len()).min(self.bytes.len());
+        buf[..count].copy_from_slice(&self.bytes[..count]);
+        self.bytes = &self.bytes[count..];
+        Ok(count)
+    }
+}
+
+fn describe_event(event: Event) -> String {
+    match event {
+        Event::XmlDecl(_) => String::from("decl"),
+        Event::DocType(doctype) => format!("doctype {}", doctype.name()),
+        Event::StartElement(start) => {
+            let attribs: Vec<String> = start
+                .attributes()
+                .iter()
+                .map(|a| format!("{}@{}={}", a.expanded_name(), a.get_startpos(), a.value()))
+                .collect();
+            format!("start {}@{} {:?}", start.expanded_name(), start.get_startpos(), attribs)
+        }
+        Event::EndElement(end) => format!("end {}@{}", end.name(), end.get_startpos()),
+        Event::Text(text) => format!("text {}", text),
+        Event::CData(text) => format!("cdata {}", text),
+        Event::Comment(comment) => format!("comment {}@{}", comment.text(), comment.get_startpos()),
+        Event::PI(pi) => format!("pi {}@{}", pi.target(), pi.get_startpos()),
+        Event::EntityRef(name) => format!("ref {}", name),
+    }
+}
+
+#[test]
+fn stream_reader_matches_reader() {
+    let text = "<?xml version='1.0'?>
+<!DOCTYPE doc [<!ENTITY e 'x'>]>
+<doc xmlns='urn:d' note='caf\u{e9} \u{1F600}'>
+  <!-- a comment with \u{1F600} in it -->
+  <item n='1'>Gr\u{fc}\u{df}e &amp; &e; &#x1F600;</item>
+  <![CDATA[a <long> CDATA section, with ]] inside]]>
+  <?pi some data?>
+  text ]] more
+</doc>
+<!-- after -->";
+    let expected: Vec<String> = Reader::new(text).map(|e| describe_event(e.unwrap())).collect();
+    assert!(expected.len() > 10);
+    for step in [1, 3, 7, 64] {
+        let source = Trickle { bytes: text.as_bytes(), step };
+        let mut reader = StreamReader::with_options(source, &ParseOptions::default(), 64);
+        let mut events = Vec::new();
+        while let Some(event) = reader.read_event().expect("Failed to stream example") {
+            events.push(event);
+        }
+        // text may be split differently where it does not fit in the buffer
+        let mut found: Vec<String> = Vec::new();
+        for event in events.into_iter().map(describe_event) {
+            match (found.last_mut(), event.strip_prefix("text ")) {
+                (Some(last), Some(more)) if last.starts_with("text ") => last.push_str(more),
+                _ => found.push(event),
+            };
+        }
+        assert_eq!(found, expected, "reading {} bytes at a time", step);
+    }
+
+    let stream = |text: &str, capacity: usize| -> XmlError {
+        let source = Trickle { bytes: text.as_bytes(), step: 5 };
+        let reader = StreamReader::with_options(source, &ParseOptions::default(), capacity);
+        match reader.filter_map(Result::err).next() {
+            Some(e) => e,
+            None => panic!("no error streaming {:?}", text),
+        }
+    };
+    let e = stream("<doc>\n  <item a='1'></doc>", 16);
+    assert_eq!(e.kind(), &XmlErrorKind::MismatchedTags(String::from("item"), String::from("doc")));
+    assert_eq!((e.doc_idx(), e.related_idx()), (20, Some(8)));
+    assert_eq!(e.position().map(|pos| (pos.line, pos.column)), Some((2, 15)));
+    // `]]>` split over two reads of a full buffer
+    let e = stream("<doc>0123456789abcdefghi]]>xyz</doc>", 24);
+    assert_eq!((e.kind(), e.doc_idx()), (&XmlErrorKind::IllegalSubstr, 24));
+    let e = stream("<doc><!-- a comment longer than the buffer --></doc>", 16);
+    assert_eq!(e.kind(), &XmlErrorKind::BufferLimit(16));
+    let e = stream("<?xml version='1.0'?><!-- a comment longer than the buffer --><doc/>", 23);
+    assert_eq!((e.kind(), e.doc_idx()), (&XmlErrorKind::BufferLimit(23), 21));
+
+    // the prolog is dropped as it is read, with or without a DTD after it
+    let prolog = "<!-- c -->\n".repeat(1000);
+    for doctype in ["", "<!DOCTYPE a [<!ENTITY e 'x'>]>"] {
+        let text = format!("{}{}<a/>", prolog, doctype);
+        let source = Trickle { bytes: text.as_bytes(), step: 100 };
+        let mut reader = StreamReader::with_options(source, &ParseOptions::default(), 4096);
+        while let Some(event) = reader.read_event().expect("Failed to stream example") {
+            if let Event::DocType(doctype) = event {
+                assert_eq!((doctype.get_startpos(), reader.offset()), (prolog.len(), prolog.len()));
+                assert_eq!(doctype.get_endpos(), text.find("<a/>").unwrap());
+            }
+        }
+    }
+    let text = format!("{}<!DOCTYPE a [<!ENTITY e '&#0;'>]><a/>", prolog);
+    let source = Trickle { bytes: text.as_bytes(), step: 100 };
+    let e = StreamReader::with_options(source, &ParseOptions::default(), 4096).filter_map(Result::err).next().unwrap();
+    assert_eq!((e.kind(), e.doc_idx()), (&XmlErrorKind::InvalidCharRef(String::from("0")), text.find("&#0;").unwrap()));
+    let e = stream("<!-- \u{1} --><doc/>", 64);
+    assert_eq!((e.kind(), e.doc_idx()), (&XmlErrorKind::BadChar('\u{1}'), 5));
+    let source = Trickle { bytes: b"<doc>ok \xFF</doc>", step: 4 };
+    let e = StreamReader::new(source).filter_map(Result::err).next().unwrap();
+    assert_eq!((e.kind(), e.doc_idx()), (&XmlErrorKind::InvalidUtf8, 8));
+}
+
+/// Records what it is called with, stopping at the element named `stop_at`
+#[derive(Default)]
+struct Recorder {
+    calls: Vec<String>,
+    stop_at: Option<&'static str>,
+}
+
+impl ContentHandler for Recorder {
+    type Error = String;
+
+    fn start_document(&mut self, decl: Option<&XmlDecl>) -> Result<(), String> {
+        self.calls.push(format!("start_document {}", decl.is_some()));
+        Ok(())
+    }
+
+    fn end_document(&mut self) -> Result<(), String> {
+        self.calls.push(String::from("end_document"));
+        Ok(())
+    }
+
+    fn doctype(&mut self, doctype: &DoctypeDecl) -> Result<(), String> {
+        self.calls.push(format!("doctype {}", doctype.name()));
+        Ok(())
+    }
+
+    fn start_element(&mut self, name: &str, attrs: &[Attribute]) -> Result<(), String> {
+        if self.stop_at == Some(name) {
+            return Err(format!("stopped at {}", name));
+        }
+        let attrs: Vec<String> = attrs.iter().map(|a| format!("{}={}", a.name(), a.value())).collect();
+        self.calls.push(format!("start_element {} {}", name, attrs.join(",")));
+        Ok(())
+    }
+
+    fn end_element(&mut self, name: &str) -> Result<(), String> {
+        self.calls.push(format!("end_element {}", name));
+        Ok(())
+    }
+
+    fn characters(&mut self, text: &str) -> Result<(), String> {
+        self.calls.push(format!("characters {}", text));
+        Ok(())
+    }
+
+    fn cdata(&mut self, text: &str) -> Result<(), String> {
+        self.calls.push(format!("cdata {}", text));
+        Ok(())
+    }
+
+    fn comment(&mut self, text: &str) -> Result<(), String> {
+        self.calls.push(format!("comment {}", text));
+        Ok(())
+    }
+
+    fn processing_instruction(&mut self, target: &str, data: Option<&str>) -> Result<(), String> {
+        self.calls.push(format!("processing_instruction {} {:?}", target, data));
+        Ok(())
+    }
+}
+
+#[test]
+fn content_handler_callbacks() {
+    let text = "<?xml version='1.0'?><!DOCTYPE doc><doc a='1'><!--c--><?go now?>x &lt; y<e/><![CDATA[<z>]]></doc>";
+    let mut recorder = Recorder::default();
+    parse_str_with_handler(text, &ParseOptions::default(), &mut recorder).expect("Failed to parse example");
+    assert_eq!(recorder.calls, vec![
+        "start_document true",
+        "doctype doc",
+        "start_element doc a=1",
+        "comment c",
+        "processing_instruction go Some(\"now\")",
+        "characters x < y",
+        "start_element e ",
+        "end_element e",
+        "cdata <z>",
+        "end_element doc",
+        "end_document",
+    ]);
+    let mut streamed = Recorder::default();
+    parse_read_with_handler(text.as_bytes(), &ParseOptions::default(), &mut streamed).expect("Failed to parse example");
+    assert_eq!(streamed.calls, recorder.calls);
+
+    // the handler stops parsing before the malformed end of the document
+    let mut recorder = Recorder { stop_at: Some("stop"), ..Recorder::default() };
+    let result = parse_str_with_handler("<doc><a/><stop/><b></doc>", &ParseOptions::default(), &mut recorder);
+    assert!(matches!(result, Err(HandlerError::Handler(msg)) if msg == "stopped at stop"));
+    assert_eq!(recorder.calls.last().map(String::as_str), Some("end_element a"));
+
+    let mut recorder = Recorder::default();
+    let result = parse_str_with_handler("<doc><b></doc>", &ParseOptions::default(), &mut recorder);
+    assert!(matches!(result, Err(HandlerError::Xml(e)) if matches!(e.kind(), XmlErrorKind::MismatchedTags(..))));
+    assert!(!recorder.calls.contains(&String::from("end_document")));
+}
+
+#[test]
+fn detect_and_transcode_encodings() {
+    let utf16 = |text: &str, bom: bool, big_endian: bool| -> Vec<u8> {
+        let units = if bom { Some(0xFEFF) } else { None }.into_iter().chain(text.encode_utf16());
+        units
+            .flat_map(|unit| if big_endian { unit.to_be_bytes() } else { unit.to_le_bytes() })
+            .collect()
+    };
+    let text = "<?xml version='1.0' encoding='UTF-16'?><doc a='\u{e9}\u{1F600}'/>";
+    for (bom, big_endian) in [(true, false), (true, true), (false, false), (false, true)] {
+        let bytes = utf16(text, bom, big_endian);
+        let (decoded, _) = encoding::decode(&bytes).expect("Failed to decode example");
+        assert_eq!(decoded, text);
+        let doc = parse_bytes(&bytes).expect("Failed to parse example");
+        assert_eq!(doc.elem.attribute("a").map(Attribute::value), Some(String::from("\u{e9}\u{1F600}")));
+        // pairs of surrogates split between reads
+        let streamed: Vec<String> = StreamReader::new(Trickle { bytes: &bytes, step: 3 })
+            .map(|event| describe_event(event.expect("Failed to read example")))
+            .collect();
+        let read: Vec<String> = Reader::new(text).map(|event| describe_event(event.unwrap())).collect();
+        assert_eq!(streamed, read);
+    }
+
+    let latin1 = b"<?xml version='1.0' encoding='ISO-8859-1'?><doc a='caf\xe9 \x80'/>";
+    let doc = parse_bytes(latin1).expect("Failed to parse example");
+    assert_eq!(doc.elem.attribute("a").map(Attribute::value), Some(String::from("caf\u{e9} \u{80}")));
+    let cp1252 = b"<?xml version='1.0' encoding='windows-1252'?><doc a='caf\xe9 \x80'/>";
+    let doc = parse_bytes(cp1252).expect("Failed to parse example");
+    assert_eq!(doc.elem.attribute("a").map(Attribute::value), Some(String::from("caf\u{e9} \u{20AC}")));
+    let (decoded, encoding) = encoding::decode(b"\xEF\xBB\xBF<doc/>").expect("Failed to decode example");
+    assert_eq!((&*decoded, encoding), ("<doc/>", TextEncoding::Utf8));
+    // UTF-8 is borrowed from the input, with or without a byte order mark
+    assert!(matches!(decoded, Cow::Borrowed(_)));
+    let (decoded, _) = encoding::decode(b"<doc>caf\xc3\xa9</doc>").expect("Failed to decode example");
+    assert!(matches!(decoded, Cow::Borrowed("<doc>caf\u{e9}</doc>")));
+    let (decoded, _) = encoding::decode(latin1).expect("Failed to decode example");
+    assert!(matches!(decoded, Cow::Owned(_)));
+
+    let error = |bytes: &[u8]| match encoding::decode(bytes) {
+        Ok(_) => panic!("should reject {:?}", bytes),
+        Err(e) => e,
+    };
+    let e = error(b"<?xml version='1.0' encoding='US-ASCII'?>\n<doc>\xe9</doc>");
+    assert_eq!((e.kind(), e.doc_idx()), (&XmlErrorKind::InvalidEncoding(TextEncoding::Ascii), 47));
+    assert_eq!(e.position().map(|pos| (pos.line, pos.column)), Some((2, 6)));
+    let e = error(b"\xEF\xBB\xBF<?xml version='1.0' encoding='ISO-8859-1'?><doc/>");
+    assert_eq!(e.kind(), &XmlErrorKind::EncodingConflict(String::from("ISO-8859-1"), TextEncoding::Utf8));
+    assert_eq!(e.doc_idx(), 30);
+    let e = error(b"<?xml version='1.0' encoding='UTF-16'?><doc/>");
+    assert_eq!(e.kind(), &XmlErrorKind::EncodingConflict(String::from("UTF-16"), TextEncoding::Utf8));
+    let e = error(&utf16("<?xml version='1.0' encoding='UTF-8'?><doc/>", true, false));
+    assert_eq!(e.kind(), &XmlErrorKind::EncodingConflict(String::from("UTF-8"), TextEncoding::Utf16Le));
+    let e = error(b"<?xml version='1.0' encoding='KOI8-R'?><doc/>");
+    assert_eq!(e.kind(), &XmlErrorKind::UnsupportedEncoding(String::from("KOI8-R")));
+    let e = error(&utf16("<doc>\u{1F600}</doc>", true, false)[..14]);
+    assert_eq!(e.kind(), &XmlErrorKind::InvalidEncoding(TextEncoding::Utf16Le));
+}
+
+#[test]
+fn xml_version_handling() {
+    let doc = parse_str("<?xml version='1.10'?><doc/>").expect("Failed to parse example");
+    let decl = doc.prolog().xml_decl().expect("expected xml declaration");
+    assert_eq!((decl.version(), doc.version()), ("1.10", XmlVersion::V1_0));
+    for version in ["1e0", "2.0", "1.", "1", ".1", "1.1.1"] {
+        let text = format!("<?xml version='{}'?><doc/>", version);
+        assert!(parse_str(&text).is_err(), "{} is not a version number", version);
+    }
+
+    let error = |text: &str| match parse_str(text) {
+        Ok(_) => panic!("should reject {}", text),
+        Err(e) => e,
+    };
+    // C0 controls may be referred to in XML 1.1 only
+    let text = "<?xml version='1.1'?><doc a='&#1;'>&#x1F;</doc>";
+    let doc = parse_str(text).expect("Failed to parse example");
+    assert_eq!(doc.version(), XmlVersion::V1_1);
+    assert_eq!(doc.root().attribute("a").map(Attribute::value), Some(String::from("\u{1}")));
+    let e = error("<?xml version='1.0'?><doc a='&#1;'/>");
+    assert_eq!((e.kind(), e.doc_idx()), (&XmlErrorKind::InvalidCharRef(String::from("1")), 29));
+    let e = error("<doc>&#x1F;</doc>");
+    assert_eq!((e.kind(), e.doc_idx()), (&XmlErrorKind::InvalidCharRef(String::from("x1F")), 5));
+    let e = error("<!DOCTYPE doc [<!ENTITY e 'a&#2;'>]><doc/>");
+    assert_eq!((e.kind(), e.doc_idx()), (&XmlErrorKind::InvalidCharRef(String::from("2")), 28));
+    let e = Reader::new("<doc>&#1;</doc>").filter_map(Result::err).next().expect("expected an error");
+    assert_eq!((e.kind(), e.doc_idx()), (&XmlErrorKind::InvalidCharRef(String::from("1")), 5));
+
+    // while XML 1.1 only allows C1 controls other than NEL as references
+    assert!(parse_str("<doc>\u{80}</doc>").is_ok());
+    let e = error("<?xml version='1.1'?><doc>\u{80}</doc>");
+    assert_eq!((e.kind(), e.doc_idx()), (&XmlErrorKind::BadChar('\u{80}'), 26));
+    let text = "<?xml version='1.1'?><doc>\u{85}</doc>";
+    let source = text.replace('\u{85}', "\u{9F}");
+    let e = StreamReader::new(source.as_bytes()).filter_map(Result::err).next().expect("expected an error");
+    assert_eq!((e.kind(), e.doc_idx()), (&XmlErrorKind::BadChar('\u{9F}'), 26));
+    assert!(parse_str(text).is_ok());
+
+    // NEL and U+2028 end lines in XML 1.1
+    let text = "<?xml version='1.1'?><doc a='x\u{85}y\r\u{85}z\u{2028}'>\u{2028}\r\u{85}</dc>";
+    let e = error(text);
+    assert_eq!(e.position().map(|pos| (pos.line, pos.column)), Some((6, 1)));
+    let doc = parse_str(&text.replace("</dc>", "</doc>")).expect("Failed to parse example");
+    assert_eq!(doc.root().attribute("a").map(Attribute::value), Some(String::from("x y z ")));
+    let text = text.replace("1.1", "1.0");
+    assert_eq!(error(&text).position().map(|pos| (pos.line, pos.column)), Some((3, 2)));
+    let doc = parse_str(&text.replace("</dc>", "</doc>")).expect("Failed to parse example");
+    let normalized = doc.root().attribute("a").map(Attribute::value);
+    assert_eq!(normalized, Some(String::from("x\u{85}y \u{85}z\u{2028}")));
+}
+
+#[test]
+fn reject_chars_outside_char_production() {
+    for (text, idx) in [
+        ("<doc>ab\u{1}</doc>", 7),
+        ("<doc><!-- \u{FFFE} --></doc>", 10),
+        ("<doc><?pi \u{FFFF}?></doc>", 10),
+        ("<doc><![CDATA[x\u{8}]]></doc>", 15),
+        ("<doc a='\u{1F}'/>", 8),
+        ("<!DOCTYPE doc [<!ENTITY e 'x\u{B}'>]><doc/>", 28),
+        ("<!DOCTYPE doc SYSTEM 'a\u{0}.dtd'><doc/>", 23),
+        ("<!-- \u{1} --><doc/>", 5),
+        ("<?xml version='1.0'?><?pi \u{1}?><doc/>", 26),
+        ("<!DOCTYPE doc><!-- \u{1} --><doc/>", 19),
+        ("<doc/><!-- \u{1} -->", 11),
+    ] {
+        match parse_str(text) {
+            Ok(_) => panic!("should reject {:?}", text),
+            Err(e) => {
+                assert!(matches!(e.kind(), XmlErrorKind::BadChar(_)), "{:?}: {}", text, e);
+                assert_eq!(e.doc_idx(), idx, "{:?}", text);
+            }
+        };
+    }
+    // the reader finds them in the same place
+    for (text, idx) in [("<doc>ab\u{1}</doc>", 7), ("<!-- \u{1} --><doc/>", 5), ("<doc/><?pi \u{1}?>", 11)] {
+        let e = Reader::new(text).filter_map(Result::err).next().expect("expected an error");
+        assert_eq!((e.kind(), e.doc_idx()), (&XmlErrorKind::BadChar('\u{1}'), idx), "{:?}", text);
+    }
+    // and references to them are rejected too
+    let e = parse_str("<doc>&#xFFFE;</doc>").err().expect("expected an error");
+    assert_eq!((e.kind(), e.doc_idx()), (&XmlErrorKind::InvalidCharRef(String::from("xFFFE")), 5));
+    assert!(parse_str("<doc a='\u{D7FF}\u{E000}'>\t\u{10FFFF}<!--\u{FFFD}--></doc>").is_ok());
+}
+
+#[test]
+fn reject_duplicate_attributes() {
+    for (text, idx, first) in [
+        ("<doc a='1' b='2' a='3'/>", 17, 5),
+        ("<doc a='1' a='1'></doc>", 11, 5),
+        ("<doc><item x='' x=\"\">text</item></doc>", 16, 11),
+    ] {
+        match parse_str(text) {
+            Ok(_) => panic!("should reject {}", text),
+            Err(e) => {
+                assert_eq!(e.kind(), &XmlErrorKind::DuplicateAttribute(String::from(&text[idx..idx + 1])), "{}", text);
+                assert_eq!((e.doc_idx(), e.related_idx()), (idx, Some(first)), "{}", text);
+            }
+        };
+        let e = Reader::new(text).filter_map(Result::err).next().expect("expected an error");
+        assert_eq!((e.doc_idx(), e.related_idx()), (idx, Some(first)), "{}", text);
+    }
+    let text = "<doc a='1' a='2'/>";
+    let hint = parse_str(text).err().and_then(|e| e.diagnostic(text).hint());
+    assert_eq!(hint.as_deref(), Some("the attribute is first given at line 1, column 6"));
+
+    // names that differ only by prefix may still be the same expanded name
+    let text = "<doc xmlns:p='urn:x' xmlns:q='urn:x' p:a='1' q:a='2'/>";
+    let options = ParseOptions { namespaces: true, ..ParseOptions::default() };
+    let e = parse_str_with(text, &options).err().expect("expected an error");
+    let duplicate = NamespaceError::DuplicateAttribute(String::from("{urn:x}a"));
+    assert_eq!((e.kind(), e.related_idx()), (&XmlErrorKind::Namespace(duplicate), Some(37)));
+    assert!(parse_str(text).is_ok());
+}
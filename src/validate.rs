@@ -0,0 +1,311 @@
+use std::collections::{BTreeSet, HashMap, HashSet};
+
+use crate::error::{ValidityError, XmlError, XmlErrorKind};
+use crate::{
+    parse_name, parse_nmtoken, AttDef, AttType, ContentItem, ContentParticle, ContentSpec,
+    DefaultDecl, Doc, DoctypeDecl, Elem, Ends, EntityTable, IntSubsetItem, Particle, Repetition,
+};
+
+/// Check a well-formed document against the declarations in its DTD,
+/// returning every validity error found, in document order apart from
+/// `IDREF`s, which are checked last.
+///
+/// Positions are byte offsets into the parsed text. Problems within the
+/// replacement text of an entity are reported at the reference to it.
+pub fn validate(doc: &Doc) -> Vec<XmlError> {
+    let root = doc.root();
+    let doctype = match doc.prolog().doctype() {
+        Some(doctype) => doctype,
+        None => return vec![invalid(ValidityError::NoDoctype, root.get_startpos(), "document")],
+    };
+    let mut validator = Validator::new(doctype);
+    if doctype.name() != root.name() {
+        let error = ValidityError::RootMismatch(String::from(root.name()));
+        validator.errors.push(invalid(error, root.get_startpos(), "element"));
+    }
+    validator.check_elem(root, None);
+    validator.check_idrefs();
+    validator.errors
+}
+
+fn invalid(error: ValidityError, doc_idx: usize, context: &str) -> XmlError {
+    XmlError::new(XmlErrorKind::Invalid(error), doc_idx, context)
+}
+
+struct Validator<'a> {
+    /// content models by element type, the first declaration being binding
+    /// and any others reported
+    models: HashMap<&'a str, &'a ContentSpec>,
+    /// attribute definitions by element type, the first definition of each
+    /// attribute being binding
+    att_defs: HashMap<&'a str, Vec<&'a AttDef>>,
+    entities: EntityTable,
+    /// where each `ID` value was first given
+    ids: HashMap<String, usize>,
+    /// `IDREF` values with where and in which attribute they were given,
+    /// checked once all `ID`s are known
+    idrefs: Vec<(String, usize, String)>,
+    errors: Vec<XmlError>,
+}
+
+/// The content of an element, looking through expanded entities
+#[derive(Default)]
+struct Children<'b> {
+    /// child elements, with the position of the entity reference they came
+    /// from, if any
+    elems: Vec<(&'b Elem, Option<usize>)>,
+    /// whether there is character data other than white space
+    text: bool,
+}
+
+impl<'a> Validator<'a> {
+    fn new(doctype: &'a DoctypeDecl) -> Validator<'a> {
+        let mut models = HashMap::new();
+        let mut errors = Vec::new();
+        // where each element type is first declared, which attribute is its
+        // `ID`, and the attributes defined for it so far
+        let mut declared: HashMap<&str, usize> = HashMap::new();
+        let mut id_atts: HashMap<&str, &str> = HashMap::new();
+        let mut bound: HashSet<(&str, &str)> = HashSet::new();
+        for (pos, item) in doctype.located_decls() {
+            match item {
+                IntSubsetItem::ElemDecl(decl) => match declared.get(decl.name()) {
+                    // VC: Unique Element Type Declaration
+                    Some(first) => {
+                        let error = ValidityError::DuplicateElementDecl(String::from(decl.name()));
+                        let context = format!("elementdecl `{}`", decl.name());
+                        errors.push(invalid(error, pos, &context).with_related(*first));
+                    }
+                    None => {
+                        declared.insert(decl.name(), pos);
+                        models.insert(decl.name(), decl.content_spec());
+                    }
+                },
+                IntSubsetItem::AttlistDecl(decl) => {
+                    for def in decl.att_defs() {
+                        // only the first definition of an attribute is binding
+                        let binding = bound.insert((decl.name(), def.name()));
+                        if !matches!(def.att_type(), AttType::Id) {
+                            continue;
+                        }
+                        let context = format!("AttDef `{}` in AttlistDecl `{}`", def.name(), decl.name());
+                        // VC: ID Attribute Default
+                        if !matches!(def.default_decl(), DefaultDecl::Implied | DefaultDecl::Required) {
+                            let error = ValidityError::IdAttributeDefault(String::from(def.name()));
+                            errors.push(invalid(error, pos, &context));
+                        }
+                        // VC: One ID per Element Type
+                        if binding && id_atts.insert(decl.name(), def.name()).is_some() {
+                            let error = ValidityError::MultipleIdAttributes(String::from(decl.name()));
+                            errors.push(invalid(error, pos, &context));
+                        }
+                    }
+                }
+                _ => (),
+            };
+        }
+        Validator {
+            models,
+            att_defs: doctype.att_defs_by_elem(),
+            entities: doctype.entities(),
+            ids: HashMap::new(),
+            idrefs: Vec::new(),
+            errors,
+        }
+    }
+
+    /// Check `elem` and its descendants. `anchor` is the position of the
+    /// entity reference the element came from, if any.
+    fn check_elem(&mut self, elem: &Elem, anchor: Option<usize>) {
+        let pos = anchor.unwrap_or(elem.get_startpos());
+        let context = format!("element `{}`", elem.name());
+        self.check_attributes(elem, anchor);
+        let mut children = Children::default();
+        collect_children(elem.children(), anchor, &mut children);
+        match self.models.get(elem.name()) {
+            None => {
+                let error = ValidityError::UndeclaredElement(String::from(elem.name()));
+                self.errors.push(invalid(error, pos, &context));
+            }
+            Some(spec) => {
+                let valid = match spec {
+                    ContentSpec::Empty => elem.children().is_empty(),
+                    ContentSpec::Any => true,
+                    ContentSpec::Mixed(mixed) => children
+                        .elems
+                        .iter()
+                        .all(|(child, _)| mixed.names().any(|name| name == child.name())),
+                    ContentSpec::Children(cp) => {
+                        let names: Vec<&str> = children.elems.iter().map(|(child, _)| child.name()).collect();
+                        !children.text && match_particle(cp, &names, BTreeSet::from([0])).contains(&names.len())
+                    }
+                };
+                if !valid {
+                    let error = ValidityError::InvalidContent(String::from(elem.name()), spec.to_string().into());
+                    self.errors.push(invalid(error, pos, &context));
+                }
+            }
+        };
+        for (child, child_anchor) in children.elems {
+            self.check_elem(child, child_anchor);
+        }
+    }
+
+    fn check_attributes(&mut self, elem: &Elem, anchor: Option<usize>) {
+        let no_defs = Vec::new();
+        let defs = self.att_defs.get(elem.name()).unwrap_or(&no_defs).clone();
+        for attrib in elem.attributes() {
+            let pos = anchor.unwrap_or(attrib.get_startpos());
+            let context = format!("Attribute `{}` in STag `{}`", attrib.name(), elem.name());
+            let def = match defs.iter().find(|def| def.name() == attrib.name()) {
+                Some(def) => def,
+                None => {
+                    let error = ValidityError::UndeclaredAttribute(String::from(attrib.name()));
+                    self.errors.push(invalid(error, pos, &context));
+                    continue;
+                }
+            };
+            let value = attrib.value();
+            if !self.check_value(def.att_type(), &value, pos, &context) {
+                let error = ValidityError::InvalidAttributeValue(String::from(attrib.name()));
+                self.errors.push(invalid(error, pos, &context));
+            }
+            if let DefaultDecl::Fixed(fixed) = def.default_decl() {
+                let fixed = fixed.normalized(def.att_type(), attrib.version);
+                if value != fixed {
+                    let error = ValidityError::FixedAttribute(String::from(attrib.name()));
+                    self.errors.push(invalid(error, pos, &context));
+                }
+            }
+        }
+        for def in defs {
+            if matches!(def.default_decl(), DefaultDecl::Required) && elem.attribute(def.name()).is_none() {
+                let error = ValidityError::MissingAttribute(String::from(def.name()));
+                let pos = anchor.unwrap_or(elem.get_startpos());
+                self.errors.push(invalid(error, pos, &format!("STag `{}`", elem.name())));
+            }
+        }
+    }
+
+    /// Whether the normalized `value` is allowed by `att_type`, recording
+    /// `ID`s and `IDREF`s as they are found
+    fn check_value(&mut self, att_type: &AttType, value: &str, pos: usize, context: &str) -> bool {
+        let tokens: Vec<&str> = value.split(' ').collect();
+        match att_type {
+            AttType::CData => true,
+            AttType::Id => {
+                if !is_name(value) {
+                    return false;
+                }
+                match self.ids.get(value) {
+                    Some(first) => {
+                        let error = ValidityError::DuplicateId(String::from(value));
+                        let error = invalid(error, pos, context).with_related(*first);
+                        self.errors.push(error);
+                    }
+                    None => {
+                        self.ids.insert(String::from(value), pos);
+                    }
+                };
+                true
+            }
+            AttType::IdRef | AttType::IdRefs => {
+                if tokens.len() > 1 && matches!(att_type, AttType::IdRef) || !tokens.iter().all(|token| is_name(token)) {
+                    return false;
+                }
+                for token in tokens {
+                    self.idrefs.push((String::from(token), pos, String::from(context)));
+                }
+                true
+            }
+            AttType::Entity | AttType::Entities => {
+                (tokens.len() == 1 || matches!(att_type, AttType::Entities))
+                    && tokens.iter().all(|token| {
+                        is_name(token) && self.entities.get(token).is_some_and(|entity| entity.is_unparsed())
+                    })
+            }
+            AttType::NmToken => is_nmtoken(value),
+            AttType::NmTokens => tokens.iter().all(|token| is_nmtoken(token)),
+            AttType::Notation(names) => names.iter().any(|name| name.as_str() == value),
+            AttType::Enumeration(allowed) => allowed.iter().any(|token| token == value),
+        }
+    }
+
+    fn check_idrefs(&mut self) {
+        for (idref, pos, context) in std::mem::take(&mut self.idrefs) {
+            if !self.ids.contains_key(&idref) {
+                self.errors.push(invalid(ValidityError::UnknownIdRef(idref), pos, &context));
+            }
+        }
+    }
+}
+
+/// Gather the child elements and note any character data in `items`
+fn collect_children<'b>(items: &'b [ContentItem], anchor: Option<usize>, children: &mut Children<'b>) {
+    for item in items {
+        match item {
+            ContentItem::Elem(elem) => children.elems.push((elem, anchor)),
+            ContentItem::Entity { start, items, .. } => collect_children(items, anchor.or(Some(*start)), children),
+            ContentItem::CharData(chardata) => {
+                if !chardata.text.chars().all(|c| matches!(c, ' ' | '\t' | '\r' | '\n')) {
+                    children.text = true;
+                }
+            }
+            ContentItem::CDSect(_) => children.text = true,
+            // even references to white space are character data, while the
+            // content of entities that were not expanded is unknown
+            ContentItem::Reference { reference, .. } => {
+                if reference.decode().is_some() {
+                    children.text = true;
+                }
+            }
+            ContentItem::ProcInstr(_) | ContentItem::Comment(_) => (),
+        };
+    }
+}
+
+/// The positions in `names` reachable by matching `cp` from any of `from`
+fn match_particle(cp: &ContentParticle, names: &[&str], from: BTreeSet<usize>) -> BTreeSet<usize> {
+    let once = |from: &BTreeSet<usize>| -> BTreeSet<usize> {
+        match cp.particle() {
+            Particle::Name(name) => from
+                .iter()
+                .filter(|&&idx| names.get(idx) == Some(&name.as_str()))
+                .map(|idx| idx + 1)
+                .collect(),
+            Particle::Seq(items) => items.iter().fold(from.clone(), |reach, item| match_particle(item, names, reach)),
+            Particle::Choice(items) => items
+                .iter()
+                .flat_map(|item| match_particle(item, names, from.clone()))
+                .collect(),
+        }
+    };
+    match cp.repetition() {
+        Repetition::Once => once(&from),
+        Repetition::Optional => from.union(&once(&from)).copied().collect(),
+        Repetition::ZeroOrMore | Repetition::OneOrMore => {
+            let mut reach = match cp.repetition() {
+                Repetition::ZeroOrMore => from.clone(),
+                _ => BTreeSet::new(),
+            };
+            let mut frontier = once(&from);
+            loop {
+                let new: BTreeSet<usize> = frontier.difference(&reach).copied().collect();
+                if new.is_empty() {
+                    break;
+                }
+                reach.extend(&new);
+                frontier = once(&new);
+            }
+            reach
+        }
+    }
+}
+
+fn is_name(token: &str) -> bool {
+    parse_name(token, 0).is_ok_and(|name| name.as_str().len() == token.len())
+}
+
+fn is_nmtoken(token: &str) -> bool {
+    parse_nmtoken(token, 0).is_ok_and(|nmtoken| nmtoken.len() == token.len())
+}